@@ -0,0 +1,90 @@
+//! measures how much faster than real time the engine processes audio
+//!
+//! run with `cargo bench -p whisper_ware_core`, every case pushes a minute of stereo noise
+//! through the processor with the native compressor, like the offline mode does
+
+use rtrb::RingBuffer;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+use whisper_ware_core::{
+    AtomicConfig, Compressor, PluginChain, StreamFormat, StreamRings, StreamSignals, processor,
+};
+
+/// the length of the audio pushed through every case
+const SECONDS: usize = 60;
+const CHANNELS: usize = 2;
+
+fn main() {
+    let directory = std::env::temp_dir().join(format!("whisper_ware_bench_{}", std::process::id()));
+    let (notify, _receiver) = std::sync::mpsc::channel();
+    let config = AtomicConfig::open(directory.join("config.json"), notify);
+    // the resampler only runs between different rates
+    config.set_drift_compensation(false);
+
+    for (name, input_rate, output_rate) in [
+        ("48 kHz", 48_000, 48_000),
+        ("44.1 kHz to 48 kHz", 44_100, 48_000),
+    ] {
+        let elapsed = run(&config, input_rate, output_rate);
+        println!(
+            "{:<20} {:>8.1} ms  {:>6.0}x real time",
+            name,
+            elapsed.as_secs_f64() * 1000_f64,
+            SECONDS as f64 / elapsed.as_secs_f64()
+        );
+    }
+
+    let _ = std::fs::remove_dir_all(directory);
+}
+
+/// processes the noise once and returns how long the processor took
+fn run(config: &AtomicConfig, input_rate: usize, output_rate: usize) -> Duration {
+    let frames = input_rate * SECONDS;
+    let output_frames = frames * output_rate / input_rate + input_rate;
+
+    let mut chain = PluginChain::new();
+    chain.push(0, Box::new(Compressor::default()));
+    chain.arrange(config);
+    chain.prepare(input_rate as f32);
+
+    let (mut input_producer, input_consumer) = RingBuffer::<f32>::new(frames * CHANNELS);
+    let (output_producer, _output_consumer) = RingBuffer::<f32>::new(output_frames * CHANNELS);
+
+    // a fixed generator keeps the cases comparable between runs
+    let mut state = 0x2545_f491_u32;
+    let chunk = input_producer
+        .write_chunk_uninit(frames * CHANNELS)
+        .unwrap();
+    chunk.fill_from_iter(std::iter::repeat_with(|| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as f32 / u32::MAX as f32 - 0.5
+    }));
+    // abandoning the producer ends the processor once the input is used up
+    drop(input_producer);
+
+    let start = Instant::now();
+    processor(
+        StreamRings {
+            input: input_consumer,
+            sidechain: None,
+            output: output_producer,
+        },
+        Arc::new(StreamSignals::default()),
+        &mut chain,
+        config,
+        StreamFormat {
+            input_rate: input_rate as f32,
+            output_rate: output_rate as f32,
+            input_channels: CHANNELS,
+            output_channels: CHANNELS,
+            sidechain_channels: 0,
+            compensate_drift: false,
+        },
+        &Arc::new(AtomicBool::new(true)),
+    )
+    .unwrap();
+    start.elapsed()
+}
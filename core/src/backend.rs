@@ -0,0 +1,603 @@
+use crate::chain::PluginChain;
+use crate::config::AtomicConfig;
+use crate::control::{BackendControl, BackendState};
+use crate::devices::Direction;
+use crate::drift::DriftController;
+use crate::error::ErrorKind;
+use crate::limiter::Limiter;
+use crate::resampler::{Resampler, ResamplerQuality};
+use crate::watchdog::{Heartbeats, Stage, Watchdog};
+use crate::{BLOCK_SIZE, Result};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Device, InputCallbackInfo, OutputCallbackInfo, Stream};
+use log::{error, info, warn};
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::scope;
+use std::time::Duration;
+
+/// how long the processor waits for input before checking whether it should stop
+const INPUT_WAIT: Duration = Duration::from_millis(100);
+
+/// configures and runs the audio processing backend
+///
+/// returns once the stream ends, when a restart is requested, a device reports an error or
+/// the watchdog notices a stall
+pub(crate) fn backend(
+    host: &Arc<cpal::Host>,
+    config: &AtomicConfig,
+    chain: &mut PluginChain,
+    watchdog: &Watchdog,
+    control: &BackendControl,
+) -> Result<()> {
+    let run = &control.run;
+    let (input_device_id, output_device_id) = config.devices();
+
+    let input_device = device_by_id(host, &input_device_id, cpal::Host::default_input_device)
+        .ok_or(ErrorKind::NoInputDevice)?;
+
+    let output_device = device_by_id(host, &output_device_id, cpal::Host::default_output_device)
+        .ok_or(ErrorKind::NoOutputDevice)?;
+
+    // streams on the default devices move when the system default changes
+    control.follow(
+        Direction::Input,
+        input_device_id.is_none().then_some(&input_device),
+    );
+    control.follow(
+        Direction::Output,
+        output_device_id.is_none().then_some(&output_device),
+    );
+
+    info!(
+        "output device: {}",
+        output_device
+            .description()
+            .map(|d| d.to_string())
+            .unwrap_or_else(|_| "unknown".to_string())
+    );
+    info!(
+        "input device: {}",
+        input_device
+            .description()
+            .map(|d| d.to_string())
+            .unwrap_or_else(|_| "unknown".to_string())
+    );
+
+    let input_config = input_device.default_input_config()?;
+    let output_config = output_device.default_output_config()?;
+    let input_sample_rate = input_config.sample_rate() as f32;
+    let output_sample_rate = output_config.sample_rate() as f32;
+    let input_channels = input_config.channels() as usize;
+    let output_channels = output_config.channels() as usize;
+
+    // pick up the order and bypasses of the slots, which may have changed since the last run
+    chain.arrange(config);
+    chain.prepare(input_sample_rate);
+
+    // the sidechain device is only opened while a plugin listens to it
+    let sidechain = match config.sidechain_device() {
+        Some(id) if chain.uses_sidechain_device() => {
+            match device_by_id(host, &Some(id), |_| None) {
+                Some(device) => Some(sidechain_stream(
+                    &device,
+                    input_sample_rate,
+                    config.resampler_quality(),
+                )?),
+                None => {
+                    warn!("the sidechain device is missing, the sidechain is silent");
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    let sidechain_channels = sidechain.as_ref().map_or(0, |(_, _, channels)| *channels);
+
+    let compensate_drift = config.drift_compensation();
+
+    // the rings carry interleaved samples in the layout of their device
+    let (mut input_producer, input_consumer) =
+        RingBuffer::<f32>::new(BLOCK_SIZE * 4 * input_channels);
+    let signals = Arc::new(StreamSignals::default());
+    let input_signals = Arc::clone(&signals);
+    let output_signals = Arc::clone(&signals);
+
+    // leave room for the extra frames produced when upsampling, and for the headroom
+    // the drift controller adds when the device callbacks beat against each other
+    let blocks = if compensate_drift { 8_f32 } else { 4_f32 };
+    let output_frames =
+        (BLOCK_SIZE as f32 * blocks * (output_sample_rate / input_sample_rate).max(1_f32)) as usize;
+    let (output_producer, mut output_consumer) =
+        RingBuffer::<f32>::new(output_frames * output_channels);
+
+    // allows input_stream to stop the program on errors
+    let run_clone_a = Arc::clone(run);
+    // allows output_stream to stop the program on errors
+    let run_clone_b = Arc::clone(run);
+
+    let input_stream = input_device.build_input_stream(
+        &input_config.clone().into(),
+        move |input: &[f32], info: &InputCallbackInfo| {
+            let timestamp = info.timestamp();
+            if let Some(latency) = timestamp.callback.duration_since(&timestamp.capture) {
+                input_signals
+                    .input_latency
+                    .store(latency.as_nanos() as u64, Relaxed);
+            }
+
+            input_signals.heartbeats.beat(Stage::Input);
+
+            let Ok(chunk) = input_producer.write_chunk_uninit(input.len()) else {
+                return;
+            };
+
+            chunk.fill_from_iter(input.iter().copied());
+            input_signals.input_ready.notify_one();
+        },
+        move |error| {
+            error!("an error occurred on the input stream: {error}");
+            run_clone_a.store(false, Relaxed);
+        },
+        None,
+    )?;
+
+    let output_stream = output_device.build_output_stream(
+        &output_config.clone().into(),
+        move |output: &mut [f32], info: &OutputCallbackInfo| {
+            let timestamp = info.timestamp();
+            if let Some(latency) = timestamp.playback.duration_since(&timestamp.callback) {
+                output_signals
+                    .output_latency
+                    .store(latency.as_nanos() as u64, Relaxed);
+            }
+            output_signals.heartbeats.beat(Stage::Output);
+
+            match output_consumer.read_chunk(output.len()) {
+                Ok(chunk) => {
+                    for (sample, output) in chunk.into_iter().zip(output.iter_mut()) {
+                        *output = sample;
+                    }
+                }
+                Err(_) => {
+                    // Not enough samples available; fill with silence
+                    output.fill(0_f32);
+                    output_signals.underruns.fetch_add(1, Relaxed);
+                }
+            }
+        },
+        move |error| {
+            error!("an error occurred on the output stream: {error}");
+            run_clone_b.store(false, Relaxed);
+        },
+        None,
+    )?;
+
+    input_stream.play()?;
+    output_stream.play()?;
+    // the stream stops when it is dropped at the end of the backend
+    let (_sidechain_stream, sidechain_consumer) = match sidechain {
+        Some((stream, consumer, _)) => {
+            stream.play()?;
+            (Some(stream), Some(consumer))
+        }
+        None => (None, None),
+    };
+
+    control.transition(BackendState::Running);
+
+    let done = AtomicBool::new(false);
+    let processing = chain.processing();
+    scope(|scope| {
+        scope.spawn(|| watchdog.watch(&signals, run, &done, &processing, config));
+
+        let result = processor(
+            StreamRings {
+                input: input_consumer,
+                sidechain: sidechain_consumer,
+                output: output_producer,
+            },
+            Arc::clone(&signals),
+            chain,
+            config,
+            StreamFormat {
+                input_rate: input_sample_rate,
+                output_rate: output_sample_rate,
+                input_channels,
+                output_channels,
+                sidechain_channels,
+                compensate_drift,
+            },
+            run,
+        );
+        done.store(true, Relaxed);
+        result
+    })
+}
+
+/// opens a capture device for the sidechain, resampled to the input rate
+///
+/// returns the stream, the ring it fills with interleaved samples and its channel count
+fn sidechain_stream(
+    device: &Device,
+    sample_rate: f32,
+    quality: ResamplerQuality,
+) -> Result<(Stream, Consumer<f32>, usize)> {
+    info!(
+        "sidechain device: {}",
+        device
+            .description()
+            .map(|d| d.to_string())
+            .unwrap_or_else(|_| "unknown".to_string())
+    );
+
+    let stream_config = device.default_input_config()?;
+    let device_rate = stream_config.sample_rate() as f32;
+    let channels = stream_config.channels() as usize;
+
+    let (mut producer, consumer) = RingBuffer::<f32>::new(BLOCK_SIZE * 4 * channels);
+    let mut resampler = (device_rate != sample_rate)
+        .then(|| Resampler::new(device_rate, sample_rate, channels, quality));
+
+    let stream = device.build_input_stream(
+        &stream_config.into(),
+        move |input: &[f32], _: &_| {
+            // the resampler takes a block at a time, whatever the device delivers
+            for block in input.chunks(BLOCK_SIZE * channels) {
+                let samples = match resampler.as_mut() {
+                    Some(resampler) => resampler.process(block),
+                    None => block,
+                };
+
+                // the key is dropped while the processor is behind, it only has to be current
+                let available = producer.slots() / channels * channels;
+                if let Ok(chunk) = producer.write_chunk_uninit(samples.len().min(available)) {
+                    chunk.fill_from_iter(samples.iter().copied());
+                }
+            }
+        },
+        // losing the sidechain only silences the key, so the backend keeps running
+        move |error| error!("an error occurred on the sidechain stream: {error}"),
+        None,
+    )?;
+
+    Ok((stream, consumer, channels))
+}
+
+/// the device formats the processor converts between
+#[derive(Clone, Copy)]
+pub struct StreamFormat {
+    /// the input sample rate, which the plugins run at
+    pub input_rate: f32,
+    pub output_rate: f32,
+    /// the channels the plugins process
+    pub input_channels: usize,
+    /// the channels after routing
+    pub output_channels: usize,
+    /// the channels of the sidechain device, zero without one
+    pub sidechain_channels: usize,
+    /// whether the input and output run on independent clocks that need compensating
+    pub compensate_drift: bool,
+}
+
+/// the rings between the device callbacks and the processor, carrying interleaved samples
+pub struct StreamRings {
+    pub input: Consumer<f32>,
+    /// the sidechain device at the input rate, none without one
+    pub sidechain: Option<Consumer<f32>>,
+    pub output: Producer<f32>,
+}
+
+/// state shared between the device callbacks and the processor
+#[derive(Default)]
+pub struct StreamSignals {
+    /// wakes the processor when input arrives
+    pub input_ready: Condvar,
+    /// counts output callbacks that ran out of audio
+    pub underruns: AtomicUsize,
+    /// the nanoseconds between the input device capturing audio and its callback
+    pub input_latency: AtomicU64,
+    /// the nanoseconds between the output callback and the device playing its audio
+    pub output_latency: AtomicU64,
+    /// beaten by the device callbacks and every block, watched for stalls
+    pub heartbeats: Heartbeats,
+}
+
+/// the delay between the input and output devices by stage
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Latency {
+    /// the input device buffer, as reported by the device
+    pub input_device: Duration,
+    /// collecting a block from the input ring and the audio still queued there
+    pub input_buffer: Duration,
+    /// the initial delay the plugins report
+    pub plugins: Duration,
+    pub limiter: Duration,
+    pub resampler: Duration,
+    /// the audio queued in the output ring
+    pub output_buffer: Duration,
+    /// the output device buffer, as reported by the device
+    pub output_device: Duration,
+    /// the clock drift the resampler corrects in parts per million, none without drift
+    /// compensation
+    pub drift_ppm: Option<f64>,
+}
+
+impl Latency {
+    /// the end to end delay
+    pub fn total(&self) -> Duration {
+        self.input_device
+            + self.input_buffer
+            + self.plugins
+            + self.limiter
+            + self.resampler
+            + self.output_buffer
+            + self.output_device
+    }
+}
+
+/// the audio processing thread
+pub fn processor(
+    rings: StreamRings,
+    signals: Arc<StreamSignals>,
+    chain: &mut PluginChain,
+    config: &AtomicConfig,
+    format: StreamFormat,
+    run: &Arc<AtomicBool>,
+) -> Result<()> {
+    let StreamRings {
+        input: mut consumer,
+        mut sidechain,
+        output: mut producer,
+    } = rings;
+    let channels = format.input_channels;
+    // plugins expect at least a stereo pair, mono is carried on the first channel
+    let main = channels.max(2);
+    // the buffers of every plugin in the layout it reports
+    let mut buffers = chain.allocate(channels);
+
+    // the main channels before and after the chain
+    let mut inputs = vec![vec![0_f32; BLOCK_SIZE]; main];
+    let mut outputs = vec![vec![0_f32; BLOCK_SIZE]; main];
+    // the channels of the sidechain device, empty without one
+    let sidechain_channels = sidechain.as_ref().map_or(0, |_| format.sidechain_channels);
+    let mut keys = vec![vec![0_f32; BLOCK_SIZE]; sidechain_channels];
+    // maps the processed channels onto the output layout
+    let routing = config.routing().matrix(channels, format.output_channels);
+    let mut routed = vec![vec![0_f32; BLOCK_SIZE]; format.output_channels];
+    // converts to the output rate when the devices disagree or their clocks drift
+    let mut resampler =
+        (format.input_rate != format.output_rate || format.compensate_drift).then(|| {
+            Resampler::new(
+                format.input_rate,
+                format.output_rate,
+                format.output_channels,
+                config.resampler_quality(),
+            )
+        });
+    // the hearing safety ceiling after routing, which may sum channels, and after resampling,
+    // whose sinc filter can overshoot
+    let mut limiter = Limiter::new(format.output_rate, format.output_channels);
+    // nudges the resampling ratio to hold the output latency constant
+    let mut drift = resampler
+        .as_ref()
+        .filter(|_| format.compensate_drift)
+        .map(|resampler| DriftController::new(resampler.ratio()));
+    // the processed samples before resampling
+    let mut interleaved = Vec::with_capacity(BLOCK_SIZE * format.output_channels);
+    // publishes the latency of the stream
+    let transport = chain.transport();
+    let seconds = |frames: usize, rate: f32| Duration::from_secs_f64(frames as f64 / rate as f64);
+    // the delays that are fixed for the stream
+    let fixed = Latency {
+        plugins: seconds(chain.latency(), format.input_rate),
+        limiter: seconds(limiter.latency(), format.output_rate),
+        resampler: resampler.as_ref().map_or(Duration::ZERO, |resampler| {
+            seconds(resampler.latency(), format.input_rate)
+        }),
+        ..Default::default()
+    };
+    // dummy mutex
+    let mutex = Mutex::new(());
+
+    'stream: while run.load(Relaxed) {
+        // block until enough slots are available
+        let frames = loop {
+            if consumer.slots() >= BLOCK_SIZE * channels {
+                break BLOCK_SIZE; // there are enough slots available
+            } else if consumer.is_abandoned() {
+                // the producer is gone, so flush whatever is left as a partial block
+                match consumer.slots() / channels {
+                    0 => break 'stream, // EOF
+                    available => break available.min(BLOCK_SIZE),
+                }
+            }
+            // a stalled input never fills the ring, so the watchdog stops the stream
+            if !run.load(Relaxed) {
+                break 'stream;
+            }
+            let guard = mutex.lock().unwrap();
+            drop(signals.input_ready.wait_timeout(guard, INPUT_WAIT).unwrap());
+        };
+        // read at most the number of samples that will fit in dst
+        signals.heartbeats.beat(Stage::Processor);
+        let chunk = consumer.read_chunk(frames * channels)?;
+        if frames < BLOCK_SIZE {
+            // pad the partial block with silence
+            inputs[..channels]
+                .iter_mut()
+                .for_each(|input| input.fill(0_f32));
+        }
+        // deinterleave samples into inputs, consuming chunk
+        for (i, sample) in chunk.into_iter().enumerate() {
+            inputs[i % channels][i / channels] = sample;
+        }
+
+        if let Some(sidechain) = sidechain.as_mut() {
+            read_sidechain(sidechain, &mut keys, frames)?;
+        }
+
+        // push parameters changed outside the plugins, such as a newly applied preset
+        if config.take_pending_apply() {
+            chain.apply_parameters();
+        }
+
+        // process the audio through every plugin that is not bypassed
+        chain.process(&mut buffers, &inputs[..channels], &keys, &mut outputs);
+        routing.process(&outputs[..channels], &mut routed);
+
+        // re-interleave the processed buffers
+        interleaved.clear();
+        interleaved.extend((0..frames).flat_map(|i| routed.iter().map(move |output| output[i])));
+
+        let processed = match resampler.as_mut() {
+            Some(resampler) => resampler.process(&interleaved),
+            None => &mut interleaved[..],
+        };
+        // nothing the plugins or the resampler output may exceed the ceiling
+        limiter.process(processed, config.limiter_ceiling());
+
+        // only whole frames are written so the channels stay aligned
+        let available = producer.slots() / format.output_channels * format.output_channels;
+        let to_write = processed.len().min(available);
+
+        // send the processed audio to the output
+        if let Ok(chunk) = producer.write_chunk_uninit(to_write) {
+            chunk.fill_from_iter(processed.iter().copied());
+        }
+
+        if let (Some(drift), Some(resampler)) = (drift.as_mut(), resampler.as_mut()) {
+            let capacity = producer.buffer().capacity() / format.output_channels;
+            let fill = capacity - producer.slots() / format.output_channels;
+            let underruns = signals.underruns.load(Relaxed);
+            resampler.set_ratio(drift.update(fill, underruns, capacity));
+        }
+
+        let queued_input = consumer.slots() / channels;
+        let queued_output =
+            (producer.buffer().capacity() - producer.slots()) / format.output_channels;
+        transport.set_latency(Latency {
+            input_device: Duration::from_nanos(signals.input_latency.load(Relaxed)),
+            input_buffer: seconds(BLOCK_SIZE + queued_input, format.input_rate),
+            output_buffer: seconds(queued_output, format.output_rate),
+            output_device: Duration::from_nanos(signals.output_latency.load(Relaxed)),
+            drift_ppm: drift.as_ref().map(DriftController::correction_ppm),
+            ..fixed
+        });
+    }
+
+    chain.transport().stop();
+    Ok(())
+}
+
+/// deinterleaves a block of the sidechain into keys, padding with silence when it is short
+///
+/// a sidechain running ahead of the input is skipped forward so the key stays in time
+fn read_sidechain(
+    consumer: &mut Consumer<f32>,
+    keys: &mut [Vec<f32>],
+    frames: usize,
+) -> Result<()> {
+    let channels = keys.len();
+    let queued = consumer.slots() / channels;
+    if queued > BLOCK_SIZE * 2 {
+        consumer
+            .read_chunk((queued - BLOCK_SIZE) * channels)?
+            .commit_all();
+    }
+
+    let available = (consumer.slots() / channels).min(frames);
+    let chunk = consumer.read_chunk(available * channels)?;
+    for (i, sample) in chunk.into_iter().enumerate() {
+        keys[i % channels][i / channels] = sample;
+    }
+    for key in keys.iter_mut() {
+        key[available..].fill(0_f32);
+    }
+
+    Ok(())
+}
+
+/// resolves a saved device id, falling back to the host default
+pub fn device_by_id(
+    host: &cpal::Host,
+    id: &Option<String>,
+    default: fn(&cpal::Host) -> Option<Device>,
+) -> Option<Device> {
+    match id {
+        None => default(host),
+        Some(id_string) => match id_string.parse::<cpal::DeviceId>() {
+            Ok(device_id) => host.device_by_id(&device_id),
+            Err(err) => {
+                warn!(
+                    "failed to parse device ID '{}': {}, falling back to default",
+                    id_string, err
+                );
+                default(host)
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{open_config, scratch_dir};
+    use rtrb::RingBuffer;
+
+    #[test]
+    fn resampled_output_stays_below_the_ceiling() {
+        let config = open_config(&scratch_dir("backend_ceiling"));
+        config.set_limiter_ceiling(-1_f32);
+        let mut chain = PluginChain::new();
+        chain.arrange(&config);
+        chain.prepare(44_100_f32);
+
+        // a full scale square wave, whose edges make the resampler ring past full scale
+        let frames = 44_100;
+        let signal = (0..frames).flat_map(|i| {
+            let sample = if (i / 50) % 2 == 0 { 1_f32 } else { -1_f32 };
+            [sample, sample]
+        });
+        let (mut input, consumer) = RingBuffer::new(frames * 2);
+        input
+            .write_chunk_uninit(frames * 2)
+            .unwrap()
+            .fill_from_iter(signal);
+        drop(input);
+        let (producer, mut output) = RingBuffer::new(frames * 4);
+
+        processor(
+            StreamRings {
+                input: consumer,
+                sidechain: None,
+                output: producer,
+            },
+            Arc::new(StreamSignals::default()),
+            &mut chain,
+            &config,
+            StreamFormat {
+                input_rate: 44_100_f32,
+                output_rate: 48_000_f32,
+                input_channels: 2,
+                output_channels: 2,
+                sidechain_channels: 0,
+                compensate_drift: false,
+            },
+            &Arc::new(AtomicBool::new(true)),
+        )
+        .unwrap();
+
+        let ceiling = 10_f32.powf(-1_f32 / 20_f32);
+        let samples: Vec<f32> = output
+            .read_chunk(output.slots())
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert!(samples.len() > frames * 2);
+        assert!(samples.iter().all(|sample| sample.abs() <= ceiling));
+        assert!(samples.iter().any(|sample| sample.abs() > ceiling * 0.9));
+    }
+}
@@ -0,0 +1,551 @@
+use crate::BLOCK_SIZE;
+use crate::config::AtomicConfig;
+use crate::parameters::ParameterValues;
+use crate::transport::Transport;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicBool, AtomicU32};
+use vst::host::HostBuffer;
+use vst::prelude::Plugin;
+
+/// what a plugin with sidechain inputs hears on them
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SidechainSource {
+    /// the sidechain inputs are silent
+    #[default]
+    Silence,
+    /// the unprocessed input of the stream, before any plugin in the chain
+    Input,
+    /// the capture device configured as the sidechain device, such as the voice chat
+    Device,
+}
+
+/// a plugin in the chain with the slot state the audio thread reads every block
+struct ChainSlot {
+    /// the id of the slot in the config
+    id: u32,
+    instance: Box<dyn Plugin>,
+    /// plugins are only initialized once, however often the backend restarts
+    initialized: bool,
+    bypass: Arc<AtomicBool>,
+    values: Arc<ParameterValues>,
+    sidechain: SidechainSource,
+}
+
+/// the buffers of a plugin, laid out as the plugin reports its inputs and outputs
+pub(crate) struct SlotBuffers {
+    buffer: HostBuffer<f32>,
+    inputs: Vec<Vec<f32>>,
+    outputs: Vec<Vec<f32>>,
+    /// the main channels the plugin has pins for, the others pass by it
+    pinned: usize,
+    /// the inputs fed from the sidechain source, inputs between the main channels and these
+    /// stay silent
+    sidechain: Range<usize>,
+    /// the path the audio takes while the plugin is bypassed or has no pins for a channel, as
+    /// late as through the plugin
+    bypass: Delay,
+    /// the input keying the sidechain, delayed by the plugins before this one so it lines up
+    /// with the audio the plugin receives. none unless the sidechain source is the input
+    key: Option<(Delay, Vec<Vec<f32>>)>,
+}
+
+/// delays channels by a fixed number of frames
+struct Delay {
+    lines: Vec<VecDeque<f32>>,
+}
+
+impl Delay {
+    fn new(channels: usize, frames: usize) -> Self {
+        Delay {
+            lines: vec![VecDeque::from(vec![0_f32; frames]); channels],
+        }
+    }
+
+    /// delays the channels in place
+    fn process(&mut self, channels: &mut [Vec<f32>]) {
+        self.feed(0, channels);
+    }
+
+    /// moves the delay on by the first channels without reading it, so it is current when
+    /// needed, and delays the channels after them in place
+    fn feed(&mut self, fed: usize, channels: &mut [Vec<f32>]) {
+        for (index, (line, channel)) in self.lines.iter_mut().zip(channels).enumerate() {
+            if index < fed {
+                line.extend(channel.iter());
+                line.drain(..channel.len());
+            } else {
+                for sample in channel.iter_mut() {
+                    line.push_back(*sample);
+                    *sample = line.pop_front().unwrap_or_default();
+                }
+            }
+        }
+    }
+}
+
+/// the value of [`PluginChain::processing`] while no plugin is inside process
+pub(crate) const NOT_PROCESSING: u32 = u32::MAX;
+
+/// the plugins the audio passes through in order, such as an eq before the compressor
+pub struct PluginChain {
+    slots: Vec<ChainSlot>,
+    /// shared with the hosts of the plugins
+    transport: Arc<Transport>,
+    /// the slot whose plugin is inside process, so the watchdog can name a hung plugin
+    processing: Arc<AtomicU32>,
+}
+
+impl Default for PluginChain {
+    fn default() -> Self {
+        PluginChain {
+            slots: Vec::new(),
+            transport: Default::default(),
+            processing: Arc::new(AtomicU32::new(NOT_PROCESSING)),
+        }
+    }
+}
+
+impl PluginChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// adds a plugin to the end of the chain for a slot of the config
+    pub fn push(&mut self, id: u32, instance: Box<dyn Plugin>) {
+        self.slots.push(ChainSlot {
+            id,
+            instance,
+            initialized: false,
+            bypass: Default::default(),
+            values: Arc::new(ParameterValues::default()),
+            sidechain: SidechainSource::default(),
+        });
+    }
+
+    /// the transport the hosts of the plugins report
+    pub fn transport(&self) -> Arc<Transport> {
+        Arc::clone(&self.transport)
+    }
+
+    /// the slot whose plugin is inside process, [`NOT_PROCESSING`] between plugins
+    pub(crate) fn processing(&self) -> Arc<AtomicU32> {
+        Arc::clone(&self.processing)
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// the plugins in processing order with their slot ids
+    pub fn instances_mut(&mut self) -> impl Iterator<Item = (u32, &mut dyn Plugin)> {
+        self.slots
+            .iter_mut()
+            .map(|slot| (slot.id, slot.instance.as_mut() as &mut dyn Plugin))
+    }
+
+    /// orders the plugins like the config and picks up the shared state of their slots
+    ///
+    /// plugins whose slot was removed from the config are bypassed and hear silence on their
+    /// sidechain
+    pub fn arrange(&mut self, config: &AtomicConfig) {
+        let ids = config.slot_ids();
+        self.slots.sort_by_key(|slot| {
+            ids.iter()
+                .position(|id| *id == slot.id)
+                .unwrap_or(usize::MAX)
+        });
+
+        for slot in &mut self.slots {
+            match config.slot_state(slot.id) {
+                Some((bypass, values)) => (slot.bypass, slot.values) = (bypass, values),
+                None => slot.bypass = Arc::new(AtomicBool::new(true)),
+            }
+            slot.sidechain = config.sidechain(slot.id).unwrap_or_default();
+        }
+    }
+
+    /// prepares the plugins for a stream, initializing each plugin the first time
+    pub fn prepare(&mut self, sample_rate: f32) {
+        self.transport.start(sample_rate);
+
+        for slot in &mut self.slots {
+            slot.instance.set_sample_rate(sample_rate);
+            slot.instance.set_block_size(BLOCK_SIZE as i64);
+
+            if !slot.initialized {
+                slot.instance.init();
+                slot.initialized = true;
+            }
+        }
+    }
+
+    /// the delay the plugins add in samples, bypassed plugins included so it never changes
+    /// while a stream runs
+    pub fn latency(&self) -> usize {
+        self.slots
+            .iter()
+            .map(|slot| slot.instance.get_info().initial_delay.max(0) as usize)
+            .sum()
+    }
+
+    /// allocates the buffers of every plugin for a stream of channels
+    ///
+    /// each plugin takes the main channels on its first inputs and outputs, mono is carried
+    /// on the first of a stereo pair. inputs beyond its outputs are the sidechain, a plugin with
+    /// as many inputs as outputs has its sidechain on the inputs after the main channels.
+    /// channels beyond the pins of a plugin pass by it, delayed as much as through it
+    pub(crate) fn allocate(&self, channels: usize) -> Vec<SlotBuffers> {
+        let main = channels.max(2);
+        // the delay of the plugins before the current one
+        let mut upstream = 0;
+        let mut buffers = Vec::with_capacity(self.slots.len());
+
+        for slot in &self.slots {
+            let info = slot.instance.get_info();
+            let inputs = info.inputs.max(0) as usize;
+            let outputs = info.outputs.max(0) as usize;
+            let pinned = main.min(inputs).min(outputs);
+            if pinned < main {
+                warn!(
+                    "{} has {} inputs and {} outputs, channels {} to {} pass by it",
+                    info.name,
+                    inputs,
+                    outputs,
+                    pinned + 1,
+                    main
+                );
+            }
+
+            let latency = info.initial_delay.max(0) as usize;
+            let key = (slot.sidechain == SidechainSource::Input).then(|| {
+                (
+                    Delay::new(channels, upstream),
+                    vec![vec![0_f32; BLOCK_SIZE]; channels],
+                )
+            });
+            upstream += latency;
+
+            let sidechain = if inputs > outputs { outputs } else { pinned };
+            buffers.push(SlotBuffers {
+                buffer: HostBuffer::new(inputs, outputs),
+                inputs: vec![vec![0_f32; BLOCK_SIZE]; inputs],
+                outputs: vec![vec![0_f32; BLOCK_SIZE]; outputs],
+                pinned,
+                sidechain: sidechain..inputs,
+                bypass: Delay::new(main, latency),
+                key,
+            });
+        }
+
+        buffers
+    }
+
+    /// whether any plugin listens to the sidechain device
+    pub(crate) fn uses_sidechain_device(&self) -> bool {
+        self.slots
+            .iter()
+            .any(|slot| slot.sidechain == SidechainSource::Device)
+    }
+
+    /// pushes the stored parameter values to every plugin
+    pub(crate) fn apply_parameters(&mut self) {
+        for slot in &mut self.slots {
+            slot.values.apply(slot.instance.as_mut());
+        }
+    }
+
+    /// runs the plugins that are not bypassed in order from the input channels into the main
+    /// channels of output, keying their sidechains from the input or the sidechain device
+    ///
+    /// buffers must come from [`PluginChain::allocate`] for the stream
+    pub(crate) fn process(
+        &mut self,
+        buffers: &mut [SlotBuffers],
+        input: &[Vec<f32>],
+        device: &[Vec<f32>],
+        output: &mut [Vec<f32>],
+    ) {
+        // the padding of mono stays silent whatever the plugins put out on it
+        for (index, output) in output.iter_mut().enumerate() {
+            match input.get(index) {
+                Some(input) => output.copy_from_slice(input),
+                None => output.fill(0_f32),
+            }
+        }
+
+        for (slot, buffers) in self.slots.iter_mut().zip(buffers) {
+            if let Some((delay, key)) = buffers.key.as_mut() {
+                for (key, input) in key.iter_mut().zip(input) {
+                    key.copy_from_slice(input);
+                }
+                delay.process(key);
+            }
+
+            // a bypassed plugin still delays the audio, so bypassing never shifts it in time
+            if slot.bypass.load(Relaxed) {
+                buffers.bypass.process(output);
+                continue;
+            }
+            buffers.bypass.feed(buffers.pinned, output);
+
+            // the output of each plugin feeds the next
+            for (pin, output) in buffers
+                .inputs
+                .iter_mut()
+                .zip(output.iter())
+                .take(buffers.pinned)
+            {
+                pin.copy_from_slice(output);
+            }
+
+            let pins = &mut buffers.inputs[buffers.sidechain.clone()];
+            match slot.sidechain {
+                SidechainSource::Silence => {}
+                SidechainSource::Input => {
+                    feed_sidechain(pins, buffers.key.as_ref().map_or(input, |(_, key)| key))
+                }
+                SidechainSource::Device => feed_sidechain(pins, device),
+            }
+
+            let mut audio_buffer = buffers.buffer.bind(&buffers.inputs, &mut buffers.outputs);
+            self.processing.store(slot.id, Relaxed);
+            slot.instance.process(&mut audio_buffer);
+            self.processing.store(NOT_PROCESSING, Relaxed);
+
+            for (output, pin) in output.iter_mut().zip(&buffers.outputs).take(buffers.pinned) {
+                output.copy_from_slice(pin);
+            }
+        }
+
+        self.transport.advance(BLOCK_SIZE);
+    }
+}
+
+/// copies a source onto sidechain pins, a single pin takes the average of the source channels
+///
+/// the pins are silent when the source has no channels, such as a missing sidechain device
+fn feed_sidechain(pins: &mut [Vec<f32>], source: &[Vec<f32>]) {
+    match pins {
+        [] => {}
+        pins if source.is_empty() => pins.iter_mut().for_each(|pin| pin.fill(0_f32)),
+        [pin] => {
+            let gain = 1_f32 / source.len() as f32;
+            pin.fill(0_f32);
+            for channel in source {
+                for (sample, source) in pin.iter_mut().zip(channel) {
+                    *sample += source * gain;
+                }
+            }
+        }
+        pins => {
+            for (index, pin) in pins.iter_mut().enumerate() {
+                pin.copy_from_slice(&source[index % source.len()]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use vst::buffer::AudioBuffer;
+    use vst::plugin::{HostCallback, Info};
+
+    /// the inputs of the last block a probe processed
+    type Heard = Arc<Mutex<Vec<Vec<f32>>>>;
+
+    /// a plugin that inverts its main channels and keeps the inputs of its last block
+    struct Probe {
+        inputs: i32,
+        outputs: i32,
+        delay: i32,
+        heard: Heard,
+    }
+
+    impl Plugin for Probe {
+        fn get_info(&self) -> Info {
+            Info {
+                name: "Probe".to_string(),
+                inputs: self.inputs,
+                outputs: self.outputs,
+                initial_delay: self.delay,
+                ..Default::default()
+            }
+        }
+
+        fn new(_host: HostCallback) -> Self {
+            unreachable!("probes are created by the tests")
+        }
+
+        fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+            let (inputs, mut outputs) = buffer.split();
+            *self.heard.lock().unwrap() = inputs.into_iter().map(<[f32]>::to_vec).collect();
+            for (index, output) in outputs.into_iter().enumerate() {
+                if index < inputs.len() {
+                    for (output, input) in output.iter_mut().zip(inputs.get(index)) {
+                        *output = -input;
+                    }
+                } else {
+                    output.fill(0_f32);
+                }
+            }
+        }
+    }
+
+    /// a chain of one probe per layout and what each of them heard
+    fn chain(layouts: &[(i32, i32, i32)]) -> (PluginChain, Vec<Heard>) {
+        let mut chain = PluginChain::new();
+        let mut heard = Vec::new();
+        for (id, (inputs, outputs, delay)) in layouts.iter().copied().enumerate() {
+            let probe = Probe {
+                inputs,
+                outputs,
+                delay,
+                heard: Default::default(),
+            };
+            heard.push(Arc::clone(&probe.heard));
+            chain.push(id as u32, Box::new(probe));
+        }
+        (chain, heard)
+    }
+
+    /// a block per channel that counts up from a different start on each
+    fn ramps(channels: usize) -> Vec<Vec<f32>> {
+        (0..channels)
+            .map(|channel| {
+                (0..BLOCK_SIZE)
+                    .map(|frame| (channel * 1000 + frame + 1) as f32)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// the channel shifted later by frames, silent before it starts
+    fn delayed(channel: &[f32], frames: usize) -> Vec<f32> {
+        let mut delayed = vec![0_f32; frames];
+        delayed.extend_from_slice(&channel[..channel.len() - frames]);
+        delayed
+    }
+
+    #[test]
+    fn delays_by_a_fixed_number_of_frames() {
+        let mut delay = Delay::new(2, 3);
+        let mut channels = vec![vec![1_f32, 2_f32, 3_f32, 4_f32]; 2];
+        delay.process(&mut channels);
+        assert_eq!(channels, [[0_f32, 0_f32, 0_f32, 1_f32]; 2]);
+
+        // the first channel moves the delay on unchanged, the second is delayed
+        let mut channels = vec![vec![5_f32, 6_f32, 7_f32, 8_f32]; 2];
+        delay.feed(1, &mut channels);
+        assert_eq!(channels[0], [5_f32, 6_f32, 7_f32, 8_f32]);
+        assert_eq!(channels[1], [2_f32, 3_f32, 4_f32, 5_f32]);
+
+        let mut channels = vec![vec![0_f32; 2]; 2];
+        delay.process(&mut channels);
+        assert_eq!(channels, [[6_f32, 7_f32]; 2]);
+    }
+
+    #[test]
+    fn picks_the_sidechain_pins_by_layout() {
+        let (chain, _) = chain(&[(4, 2, 0), (3, 3, 0), (2, 2, 0), (1, 1, 0)]);
+
+        let ranges = |channels| {
+            chain
+                .allocate(channels)
+                .iter()
+                .map(|buffers| (buffers.pinned, buffers.sidechain.clone()))
+                .collect::<Vec<_>>()
+        };
+        // mono is carried on a stereo pair
+        assert_eq!(ranges(1), [(2, 2..4), (2, 2..3), (2, 2..2), (1, 1..1)]);
+        assert_eq!(ranges(2), ranges(1));
+        assert_eq!(ranges(6), [(2, 2..4), (3, 3..3), (2, 2..2), (1, 1..1)]);
+    }
+
+    #[test]
+    fn averages_or_repeats_the_sidechain_source() {
+        let source = vec![vec![1_f32; 4], vec![3_f32; 4]];
+
+        let mut pins = vec![vec![9_f32; 4]];
+        feed_sidechain(&mut pins, &source);
+        assert_eq!(pins, [[2_f32; 4]]);
+
+        let mut pins = vec![vec![9_f32; 4]; 3];
+        feed_sidechain(&mut pins, &source);
+        assert_eq!(pins, [[1_f32; 4], [3_f32; 4], [1_f32; 4]]);
+
+        feed_sidechain(&mut pins, &[]);
+        assert_eq!(pins, [[0_f32; 4]; 3]);
+    }
+
+    #[test]
+    fn channels_beyond_the_pins_pass_by_delayed() {
+        // a stereo eq on 7.1 and a three channel plugin on 5.1
+        for (channels, layout) in [(8, (2, 2, 5)), (6, (3, 3, 7))] {
+            let (mut chain, _) = chain(&[layout]);
+            let mut buffers = chain.allocate(channels);
+            let input = ramps(channels);
+            let mut output = vec![vec![0_f32; BLOCK_SIZE]; channels];
+            chain.process(&mut buffers, &input, &[], &mut output);
+
+            let (pinned, delay) = (layout.1 as usize, layout.2 as usize);
+            for (index, (output, input)) in output.iter().zip(&input).enumerate() {
+                if index < pinned {
+                    let inverted: Vec<f32> = input.iter().map(|sample| -sample).collect();
+                    assert_eq!(output, &inverted, "channel {index} of {channels}");
+                } else {
+                    assert_eq!(
+                        output,
+                        &delayed(input, delay),
+                        "channel {index} of {channels}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_bypassed_plugin_still_delays_the_audio() {
+        let (mut chain, heard) = chain(&[(2, 2, 4)]);
+        chain.slots[0].bypass.store(true, Relaxed);
+        let mut buffers = chain.allocate(2);
+        let input = ramps(2);
+        let mut output = vec![vec![0_f32; BLOCK_SIZE]; 2];
+        chain.process(&mut buffers, &input, &[], &mut output);
+
+        assert!(heard[0].lock().unwrap().is_empty());
+        assert_eq!(output[0], delayed(&input[0], 4));
+        assert_eq!(output[1], delayed(&input[1], 4));
+    }
+
+    #[test]
+    fn the_input_key_lines_up_with_the_upstream_delay() {
+        let (mut chain, heard) = chain(&[(2, 2, 6), (4, 2, 0), (4, 2, 0)]);
+        chain.slots[1].sidechain = SidechainSource::Input;
+        chain.slots[2].sidechain = SidechainSource::Device;
+        let mut buffers = chain.allocate(2);
+        let input = ramps(2);
+        let device = vec![vec![0.5_f32; BLOCK_SIZE]];
+        let mut output = vec![vec![0_f32; BLOCK_SIZE]; 2];
+        chain.process(&mut buffers, &input, &device, &mut output);
+
+        let keyed = heard[1].lock().unwrap();
+        assert_eq!(keyed[2], delayed(&input[0], 6));
+        assert_eq!(keyed[3], delayed(&input[1], 6));
+        let ducked = heard[2].lock().unwrap();
+        assert_eq!(ducked[2], device[0]);
+        assert_eq!(ducked[3], device[0]);
+        assert_eq!(chain.latency(), 6);
+    }
+}
@@ -0,0 +1,486 @@
+use crate::BLOCK_SIZE;
+use atomic_float::AtomicF32;
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::sync::atomic::Ordering::Relaxed;
+use vst::buffer::AudioBuffer;
+use vst::plugin::{Category, HostCallback, Info, Plugin, PluginParameters};
+
+/// the number of parameters, matches the Rough Rider 3 layout
+const PARAM_COUNT: usize = 11;
+/// the parameter names in Rough Rider 3 order
+const PARAM_NAMES: [&str; PARAM_COUNT] = [
+    "Sidechain HPF",
+    "Input",
+    "Sensitivity",
+    "Ratio",
+    "Attack",
+    "Release",
+    "Makeup",
+    "Mix",
+    "Output",
+    "Sidechain",
+    "Full Bandwidth",
+];
+/// the most main channels the compressor links, enough for 7.1
+const MAX_CHANNELS: usize = 8;
+/// the default normalized values, used until config.json stores a value
+const PARAM_DEFAULTS: [f32; PARAM_COUNT] = [
+    1_f32, 1_f32, 0.48333332, 1_f32, 0_f32, 0.09090909, 0.33333334, 1_f32, 1_f32, 0_f32, 1_f32,
+];
+
+/// a pure Rust feed-forward compressor with the same parameters as Rough Rider 3
+///
+/// the compressor is linked across all main channels and takes the external sidechain on
+/// the input after the last main channel
+pub struct Compressor {
+    params: Arc<CompressorParameters>,
+    sample_rate: f32,
+    /// the smoothed gain reduction in dB
+    envelope: f32,
+    /// one sidechain high pass filter per detector channel
+    filters: [Biquad; MAX_CHANNELS],
+    /// the combined gain per sample, sized by set_block_size so process never allocates
+    gains: Vec<f32>,
+    /// the cutoff the filters were last designed for
+    filter_cutoff: f32,
+}
+
+/// lock free parameter storage shared with the host
+struct CompressorParameters {
+    values: [AtomicF32; PARAM_COUNT],
+}
+
+/// a biquad filter in transposed direct form II
+#[derive(Default, Clone, Copy)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+/// parameter values converted to their DSP units for one block
+struct Settings {
+    hpf_hz: f32,
+    input_gain: f32,
+    threshold_db: f32,
+    ratio: f32,
+    attack_coeff: f32,
+    release_coeff: f32,
+    makeup_db: f32,
+    mix: f32,
+    output_gain: f32,
+    external_sidechain: bool,
+    full_bandwidth: bool,
+}
+
+impl Default for Compressor {
+    fn default() -> Self {
+        Compressor {
+            params: Arc::new(CompressorParameters::default()),
+            sample_rate: 48_000_f32,
+            envelope: 0_f32,
+            filters: Default::default(),
+            gains: vec![0_f32; BLOCK_SIZE],
+            filter_cutoff: 0_f32,
+        }
+    }
+}
+
+impl Plugin for Compressor {
+    fn get_info(&self) -> Info {
+        Info {
+            name: "WhisperWare Compressor".to_string(),
+            vendor: "WhisperWare".to_string(),
+            unique_id: 0x5768_5772,
+            inputs: MAX_CHANNELS as i32 + 1,
+            outputs: MAX_CHANNELS as i32,
+            parameters: PARAM_COUNT as i32,
+            category: Category::Effect,
+            ..Default::default()
+        }
+    }
+
+    fn new(_host: HostCallback) -> Self {
+        Self::default()
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.sample_rate = rate;
+        // force the filters to be redesigned for the new rate
+        self.filter_cutoff = 0_f32;
+    }
+
+    fn set_block_size(&mut self, size: i64) {
+        self.gains.resize(size.max(1) as usize, 0_f32);
+    }
+
+    fn resume(&mut self) {
+        self.envelope = 0_f32;
+        self.filters.iter_mut().for_each(Biquad::reset);
+    }
+
+    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        let settings = self.settings();
+
+        if settings.hpf_hz != self.filter_cutoff {
+            for filter in &mut self.filters {
+                filter.design_high_pass(settings.hpf_hz, self.sample_rate);
+            }
+            self.filter_cutoff = settings.hpf_hz;
+        }
+
+        let (inputs, mut outputs) = buffer.split();
+        let channels = inputs.len().min(outputs.len()).min(MAX_CHANNELS);
+        if channels == 0 {
+            return;
+        }
+
+        // without a sidechain input the detector falls back to the program material
+        let sidechain = (settings.external_sidechain && inputs.len() > channels)
+            .then(|| inputs.get(inputs.len() - 1));
+        let frames = inputs.get(0).len();
+        let dry = 1_f32 - settings.mix;
+
+        // a block longer than announced is processed in parts
+        for start in (0..frames).step_by(self.gains.len()) {
+            let end = (start + self.gains.len()).min(frames);
+            let gains = &mut self.gains[..end - start];
+
+            for (i, gain) in (start..end).zip(gains.iter_mut()) {
+                // the detector listens to the external sidechain or the linked program material
+                let mut level = 0_f32;
+                for (c, filter) in self.filters[..channels].iter_mut().enumerate() {
+                    let mut key = match sidechain {
+                        Some(key) => key[i],
+                        None => inputs.get(c)[i] * settings.input_gain,
+                    };
+
+                    if !settings.full_bandwidth {
+                        key = filter.process(key);
+                    }
+                    level = level.max(key.abs());
+                }
+
+                let target =
+                    gain_reduction(gain_to_db(level), settings.threshold_db, settings.ratio);
+
+                let coeff = if target > self.envelope {
+                    settings.attack_coeff
+                } else {
+                    settings.release_coeff
+                };
+                self.envelope = target + coeff * (self.envelope - target);
+
+                let wet = settings.mix * db_to_gain(settings.makeup_db - self.envelope);
+                *gain = settings.input_gain * (dry + wet) * settings.output_gain;
+            }
+
+            for c in 0..channels {
+                let (input, output) = (
+                    &inputs.get(c)[start..end],
+                    &mut outputs.get_mut(c)[start..end],
+                );
+                for ((output, input), gain) in output.iter_mut().zip(input).zip(gains.iter()) {
+                    *output = input * gain;
+                }
+            }
+        }
+    }
+
+    fn get_parameter_object(&mut self) -> Arc<dyn PluginParameters> {
+        Arc::clone(&self.params) as Arc<dyn PluginParameters>
+    }
+}
+
+impl Compressor {
+    /// converts the normalized parameters to DSP units
+    fn settings(&self) -> Settings {
+        let value = |index: usize| scale(index, self.params.get_parameter(index as i32));
+
+        Settings {
+            hpf_hz: value(0),
+            input_gain: db_to_gain(value(1)),
+            threshold_db: value(2),
+            ratio: value(3),
+            attack_coeff: time_coeff(value(4), self.sample_rate),
+            release_coeff: time_coeff(value(5), self.sample_rate),
+            makeup_db: value(6),
+            mix: value(7),
+            output_gain: db_to_gain(value(8)),
+            external_sidechain: value(9) >= 0.5,
+            full_bandwidth: value(10) >= 0.5,
+        }
+    }
+}
+
+impl Default for CompressorParameters {
+    fn default() -> Self {
+        CompressorParameters {
+            values: PARAM_DEFAULTS.map(AtomicF32::new),
+        }
+    }
+}
+
+impl PluginParameters for CompressorParameters {
+    fn get_parameter_label(&self, index: i32) -> String {
+        match index {
+            0 => "Hz",
+            1 | 2 | 6 | 8 => "dB",
+            3 => ":1",
+            4 | 5 => "ms",
+            7 => "%",
+            _ => "",
+        }
+        .to_string()
+    }
+
+    fn get_parameter_text(&self, index: i32) -> String {
+        let value = scale(index as usize, self.get_parameter(index));
+
+        match index {
+            0 | 5 => format!("{:.0}", value),
+            7 => format!("{:.0}", value * 100_f32),
+            9 | 10 => if value >= 0.5 { "On" } else { "Off" }.to_string(),
+            _ => format!("{:.1}", value),
+        }
+    }
+
+    fn get_parameter_name(&self, index: i32) -> String {
+        PARAM_NAMES
+            .get(index as usize)
+            .map(|name| name.to_string())
+            .unwrap_or_default()
+    }
+
+    fn get_parameter(&self, index: i32) -> f32 {
+        self.values
+            .get(index as usize)
+            .map(|value| value.load(Relaxed).clamp(0_f32, 1_f32))
+            .unwrap_or_default()
+    }
+
+    fn set_parameter(&self, index: i32, value: f32) {
+        if let Some(atomic) = self.values.get(index as usize) {
+            atomic.store(value, Relaxed);
+        }
+    }
+}
+
+impl Biquad {
+    /// designs a second order butterworth high pass filter
+    fn design_high_pass(&mut self, cutoff: f32, sample_rate: f32) {
+        let cutoff = cutoff.min(sample_rate * 0.45);
+        let omega = 2_f32 * PI * cutoff / sample_rate;
+        let alpha = omega.sin() / (2_f32 * std::f32::consts::FRAC_1_SQRT_2);
+        let cos = omega.cos();
+        let a0 = 1_f32 + alpha;
+
+        self.b0 = (1_f32 + cos) / 2_f32 / a0;
+        self.b1 = -(1_f32 + cos) / a0;
+        self.b2 = self.b0;
+        self.a1 = -2_f32 * cos / a0;
+        self.a2 = (1_f32 - alpha) / a0;
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = self.b0 * input + self.z1;
+        self.z1 = self.b1 * input - self.a1 * output + self.z2;
+        self.z2 = self.b2 * input - self.a2 * output;
+        output
+    }
+
+    fn reset(&mut self) {
+        self.z1 = 0_f32;
+        self.z2 = 0_f32;
+    }
+}
+
+/// maps a normalized parameter to its DSP unit
+///
+/// the ranges approximate Rough Rider 3 so existing config values behave similarly
+fn scale(index: usize, value: f32) -> f32 {
+    match index {
+        0 => log_scale(value, 20_f32, 2_000_f32),
+        1 | 8 => lerp(value, -24_f32, 0_f32),
+        2 => lerp(value, 0_f32, -60_f32),
+        3 => 1_f32 + 99_f32 * value.powi(2),
+        4 => log_scale(value, 0.1, 100_f32),
+        5 => log_scale(value, 10_f32, 2_000_f32),
+        6 => lerp(value, 0_f32, 36_f32),
+        _ => value,
+    }
+}
+
+/// the static gain reduction in dB for a detector level, hard knee
+fn gain_reduction(level_db: f32, threshold_db: f32, ratio: f32) -> f32 {
+    let over = level_db - threshold_db;
+
+    if over > 0_f32 {
+        over * (1_f32 - 1_f32 / ratio)
+    } else {
+        0_f32
+    }
+}
+
+/// the one pole smoothing coefficient for a time constant in milliseconds
+fn time_coeff(ms: f32, sample_rate: f32) -> f32 {
+    (-1_f32 / (ms * 0.001 * sample_rate)).exp()
+}
+
+fn lerp(value: f32, min: f32, max: f32) -> f32 {
+    min + (max - min) * value
+}
+
+fn log_scale(value: f32, min: f32, max: f32) -> f32 {
+    min * (max / min).powf(value)
+}
+
+fn gain_to_db(gain: f32) -> f32 {
+    20_f32 * gain.max(1e-9).log10()
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10_f32.powf(db / 20_f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{open_config, process, scratch_dir};
+    use std::fs::write;
+
+    const SAMPLE_RATE: f32 = 48_000_f32;
+
+    /// a compressor without makeup, filter or input and output gain
+    fn compressor(threshold_db: f32, ratio: f32, attack_ms: f32, release_ms: f32) -> Compressor {
+        let mut compressor = Compressor::default();
+        compressor.set_sample_rate(SAMPLE_RATE);
+        let params = compressor.get_parameter_object();
+        let values = [
+            (1, 1_f32),
+            (2, threshold_db / -60_f32),
+            (3, ((ratio - 1_f32) / 99_f32).sqrt()),
+            (4, inverse_log_scale(attack_ms, 0.1, 100_f32)),
+            (5, inverse_log_scale(release_ms, 10_f32, 2_000_f32)),
+            (6, 0_f32),
+            (7, 1_f32),
+            (8, 1_f32),
+            (9, 0_f32),
+            (10, 1_f32),
+        ];
+        for (index, value) in values {
+            params.set_parameter(index, value);
+        }
+        compressor
+    }
+
+    fn inverse_log_scale(value: f32, min: f32, max: f32) -> f32 {
+        (value / min).ln() / (max / min).ln()
+    }
+
+    /// the gain reduction of every sample of a constant input level in dB
+    fn reduction(compressor: &mut Compressor, level_db: f32, frames: usize) -> Vec<f32> {
+        let level = db_to_gain(level_db);
+        let outputs = process(compressor, &[vec![level; frames], vec![level; frames]], 2);
+        outputs[0]
+            .iter()
+            .map(|output| level_db - gain_to_db(*output))
+            .collect()
+    }
+
+    #[test]
+    fn static_reduction_follows_threshold_and_ratio() {
+        for (threshold_db, ratio, level_db) in [(-20_f32, 4_f32, -10_f32), (-30_f32, 2_f32, -6_f32)]
+        {
+            let mut compressor = compressor(threshold_db, ratio, 0.1, 10_f32);
+            let settled = *reduction(&mut compressor, level_db, 4_800).last().unwrap();
+            let expected = (level_db - threshold_db) * (1_f32 - 1_f32 / ratio);
+            assert!(
+                (settled - expected).abs() < 0.05,
+                "{settled} dB instead of {expected} dB"
+            );
+        }
+    }
+
+    #[test]
+    fn below_threshold_is_untouched() {
+        let mut compressor = compressor(-20_f32, 4_f32, 0.1, 10_f32);
+        let reduction = reduction(&mut compressor, -30_f32, 4_800);
+        assert!(reduction.iter().all(|db| db.abs() < 1e-3));
+    }
+
+    #[test]
+    fn attack_and_release_are_time_constants() {
+        let (attack_ms, release_ms) = (10_f32, 100_f32);
+        let mut compressor = compressor(-20_f32, 100_f32, attack_ms, release_ms);
+        let target = 10_f32 * (1_f32 - 1_f32 / 100_f32);
+
+        // one time constant reaches 1 - 1/e of the step
+        let attack = reduction(&mut compressor, -10_f32, 48_000);
+        let after_attack = attack[(attack_ms * SAMPLE_RATE / 1000_f32) as usize - 1];
+        assert!(
+            (after_attack / target - 0.632).abs() < 0.01,
+            "{after_attack}"
+        );
+        assert!((attack.last().unwrap() - target).abs() < 0.01);
+
+        // the level drops below the threshold, the reduction decays to 1/e
+        let release = reduction(&mut compressor, -40_f32, 48_000);
+        let after_release = release[(release_ms * SAMPLE_RATE / 1000_f32) as usize - 1];
+        assert!(
+            (after_release / target - 0.368).abs() < 0.01,
+            "{after_release}"
+        );
+    }
+
+    #[test]
+    fn long_blocks_are_processed_without_growing() {
+        let mut compressor = compressor(-20_f32, 4_f32, 0.1, 10_f32);
+        compressor.set_block_size(64);
+        let reduction = reduction(&mut compressor, -10_f32, 1_000);
+        assert_eq!(compressor.gains.len(), 64);
+        assert!((reduction.last().unwrap() - 7.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn legacy_config_values_map_like_rough_rider() {
+        // an unversioned config.json from before the plugin chain, with the old defaults
+        let directory = scratch_dir("compressor_legacy");
+        write(
+            directory.join("config.json"),
+            r#"{
+                "sidechain_hpf": 20.0, "input_level": 1.0, "sensitivity": 0.48333332,
+                "ratio": 1.0, "attack": 0.0, "release": 0.09090909, "makeup": 0.33333334,
+                "mix": 1.0, "output_level": 1.0, "sidechain": 0.0, "full_bandwidth": 1.0,
+                "input_device": null, "output_device": null
+            }"#,
+        )
+        .unwrap();
+        let config = open_config(&directory);
+
+        let mut compressor = Compressor::default();
+        config.bind_parameters(0, &mut compressor);
+        let params = compressor.get_parameter_object();
+        let text = |name: &str| {
+            let index = PARAM_NAMES.iter().position(|other| *other == name).unwrap();
+            params.get_parameter_text(index as i32)
+        };
+
+        assert_eq!(text("Sidechain HPF"), "2000");
+        assert_eq!(text("Input"), "0.0");
+        assert_eq!(text("Sensitivity"), "-29.0");
+        assert_eq!(text("Ratio"), "100.0");
+        assert_eq!(text("Attack"), "0.1");
+        assert_eq!(text("Release"), "16");
+        assert_eq!(text("Makeup"), "12.0");
+        assert_eq!(text("Mix"), "100");
+        assert_eq!(text("Output"), "0.0");
+        assert_eq!(text("Sidechain"), "Off");
+        assert_eq!(text("Full Bandwidth"), "On");
+    }
+}
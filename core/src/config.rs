@@ -0,0 +1,1282 @@
+use crate::Result;
+use crate::chain::SidechainSource;
+use crate::error::ErrorKind;
+use crate::fx;
+use crate::parameters::{ParameterValues, Parameters};
+use crate::presets::{Preset, PresetStore};
+use crate::resampler::ResamplerQuality;
+use crate::routing::Routing;
+use crate::scanner::{PluginScanner, find_plugin, search_paths};
+use atomic_float::AtomicF32;
+use log::{error, info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, from_slice, from_value, json, to_vec_pretty};
+use std::cmp::PartialEq;
+use std::collections::BTreeMap;
+use std::fs::{File, copy, create_dir_all, read, rename};
+use std::io::Write;
+use std::mem::take;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::sleep;
+use std::time::{Duration, SystemTime};
+use vst::plugin::Info;
+use vst::prelude::Plugin;
+
+/// the plugin loaded when none is configured
+const DEFAULT_PLUGIN: &str = "RoughRider3.dll";
+
+/// the current config schema version
+const CONFIG_VERSION: u32 = 3;
+
+/// identifies a version of the config file by its modification time and length
+type FileStamp = Option<(SystemTime, u64)>;
+
+/// upgrades a config object by one schema version, indexed by the version it upgrades from
+type Migration = fn(&mut Map<String, Value>);
+
+/// the migrations from every older schema up to [`CONFIG_VERSION`]
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [
+    // version 0 predates the version field, everything added since has a default
+    |_| {},
+    // version 1 stored the Rough Rider 3 parameters as fields
+    |object| {
+        let parameters: Map<String, Value> = LEGACY_PARAMETERS
+            .iter()
+            .filter_map(|(field, name)| Some((name.to_string(), object.remove(*field)?)))
+            .collect();
+        object.insert("parameters".to_string(), parameters.into());
+    },
+    // version 2 held a single plugin
+    |object| {
+        let path = object
+            .remove("plugin_path")
+            .unwrap_or_else(|| DEFAULT_PLUGIN.into());
+        let parameters = object
+            .remove("parameters")
+            .unwrap_or_else(|| Map::new().into());
+        let slot = json!({ "id": 0, "path": path, "bypass": false, "parameters": parameters });
+        object.insert("plugins".to_string(), json!([slot]));
+    },
+];
+
+/// the version 1 parameter fields and the Rough Rider 3 parameter names they held
+const LEGACY_PARAMETERS: [(&str, &str); 11] = [
+    ("sidechain_hpf", "Sidechain HPF"),
+    ("input_level", "Input"),
+    ("sensitivity", "Sensitivity"),
+    ("ratio", "Ratio"),
+    ("attack", "Attack"),
+    ("release", "Release"),
+    ("makeup", "Makeup"),
+    ("mix", "Mix"),
+    ("output_level", "Output"),
+    ("sidechain", "Sidechain"),
+    ("full_bandwidth", "Full Bandwidth"),
+];
+
+/// the persisted config, any field missing from the file takes its default value
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+struct Config {
+    /// the schema version the file was written with, missing in version 0
+    version: u32,
+    /// the plugin chain in processing order
+    plugins: Vec<PluginSlot>,
+    /// the brickwall limiter ceiling in dBFS
+    limiter_ceiling: f32,
+    /// the quality used when the device sample rates differ
+    resampler_quality: ResamplerQuality,
+    /// whether to compensate for clock drift between the devices
+    drift_compensation: bool,
+    /// how the processed channels map onto the output device
+    routing: Routing,
+    input_device: Option<String>,
+    output_device: Option<String>,
+    /// the capture device keying plugins whose sidechain source is the device
+    sidechain_device: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            version: CONFIG_VERSION,
+            plugins: vec![PluginSlot {
+                id: 0,
+                path: Some(PathBuf::from(DEFAULT_PLUGIN)),
+                bypass: false,
+                sidechain: SidechainSource::default(),
+                sandbox: false,
+                // the plugin keeps its own defaults until a value is stored
+                parameters: BTreeMap::new(),
+            }],
+            limiter_ceiling: -1_f32,
+            resampler_quality: ResamplerQuality::default(),
+            drift_compensation: true,
+            routing: Routing::default(),
+            input_device: None,
+            output_device: None,
+            sidechain_device: None,
+        }
+    }
+}
+
+/// a plugin in the chain and its saved values
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+struct PluginSlot {
+    /// identifies the slot when the chain is reordered
+    id: u32,
+    /// the plugin library, relative paths are looked up in the search paths. none uses the
+    /// native compressor
+    path: Option<PathBuf>,
+    #[serde(default)]
+    bypass: bool,
+    /// what the sidechain inputs of the plugin hear
+    #[serde(default)]
+    sidechain: SidechainSource,
+    /// whether the plugin runs in a child process that may crash without taking down the app
+    #[serde(default)]
+    sandbox: bool,
+    /// the plugin parameter values by parameter name
+    #[serde(default)]
+    parameters: BTreeMap<String, f32>,
+}
+
+impl Config {
+    /// reads the config at path, falling back to the backup and then the defaults
+    ///
+    /// an unreadable config is kept next to the original for inspection
+    fn load(path: &Path) -> Self {
+        let error = match Self::read(path) {
+            Ok(config) => return config,
+            // a missing file is expected on first launch
+            Err(_) if !path.exists() => return Config::default(),
+            Err(error) => error,
+        };
+
+        let corrupt = path.with_extension("json.corrupt");
+        warn!(
+            "config file is unreadable ({}), keeping it as {}",
+            error,
+            corrupt.display()
+        );
+        if let Err(error) = copy(path, &corrupt) {
+            warn!("failed to keep the unreadable config file: {}", error);
+        }
+
+        let backup = backup_path(path);
+        match Self::read(&backup) {
+            Ok(config) => {
+                warn!("config restored from {}", backup.display());
+                config
+            }
+            Err(error) => {
+                error!(
+                    "config backup is unreadable ({}), falling back to the defaults",
+                    error
+                );
+                Config::default()
+            }
+        }
+    }
+
+    /// reads the config at path, migrating it from older schema versions
+    fn read(path: &Path) -> Result<Self> {
+        Self::parse(&read(path)?)
+    }
+
+    /// parses the text of a config file, migrating it from older schema versions
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut value: Value = from_slice(bytes)?;
+
+        if let Value::Object(object) = &mut value {
+            let version = object
+                .get("version")
+                .and_then(Value::as_u64)
+                .unwrap_or_default() as usize;
+
+            if version > CONFIG_VERSION as usize {
+                warn!(
+                    "config was written by a newer version ({}), unknown settings are ignored",
+                    version
+                );
+            }
+
+            for (from, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+                migration(object);
+                info!("config migrated from version {} to {}", from, from + 1);
+            }
+
+            object.insert("version".to_string(), CONFIG_VERSION.into());
+            *object = valid_settings(take(object));
+        }
+
+        Ok(from_value(value)?)
+    }
+
+    /// replaces the config at path without ever leaving a partially written file
+    ///
+    /// the old config is kept as the backup if it is valid, both files are replaced the
+    /// same way as described in [`replace_file`]
+    fn write(&self, path: &Path) -> Result<()> {
+        let bytes = to_vec_pretty(self)?;
+
+        // never rotate a damaged config over a good backup
+        if let Ok(current) = read(path)
+            && Self::parse(&current).is_ok()
+        {
+            replace_file(&backup_path(path), &current)?;
+        }
+
+        replace_file(path, &bytes)
+    }
+
+    fn atomic(&self, path: PathBuf, notify: Sender<()>) -> AtomicConfig {
+        AtomicConfig {
+            slots: RwLock::new(self.plugins.iter().map(Slot::new).collect()),
+            limiter_ceiling: AtomicF32::new(self.limiter_ceiling),
+            resampler_quality: Mutex::new(self.resampler_quality),
+            drift_compensation: AtomicBool::new(self.drift_compensation),
+            routing: Mutex::new(self.routing.clone()),
+            input_device: Mutex::new(self.input_device.clone()),
+            output_device: Mutex::new(self.output_device.clone()),
+            sidechain_device: Mutex::new(self.sidechain_device.clone()),
+
+            stamp: Mutex::new(file_stamp(&path)),
+            path,
+            dirty: Default::default(),
+            pending_apply: Default::default(),
+            notify,
+        }
+    }
+}
+
+/// the settings of a migrated config that parse, an invalid setting is logged and left out
+/// so it takes its default instead of discarding the whole file
+fn valid_settings(object: Map<String, Value>) -> Map<String, Value> {
+    let mut settings = Map::new();
+    for (name, value) in object {
+        let value = match (name.as_str(), value) {
+            ("plugins", Value::Array(slots)) => slots.into_iter().filter_map(valid_slot).collect(),
+            (_, value) => value,
+        };
+        merge_valid::<Config>(&mut settings, name, value, "config setting");
+    }
+    settings
+}
+
+/// the valid fields of a plugin slot, none when its id is unusable
+fn valid_slot(slot: Value) -> Option<Value> {
+    let Value::Object(mut fields) = slot else {
+        warn!("ignoring a plugin slot that is not an object in the config");
+        return None;
+    };
+
+    // the other fields are only checked once the slot has its id
+    let id = fields.remove("id").unwrap_or_default();
+    let mut slot = Map::from_iter([("id".to_string(), id.clone())]);
+    if from_value::<PluginSlot>(slot.clone().into()).is_err() {
+        warn!(
+            "ignoring the plugin slot with the invalid id {} in the config",
+            id
+        );
+        return None;
+    }
+
+    for (name, value) in fields {
+        merge_valid::<PluginSlot>(&mut slot, name, value, "plugin slot setting");
+    }
+    Some(slot.into())
+}
+
+/// adds a field to the fields when they still parse as T with it, logging it otherwise
+fn merge_valid<T: DeserializeOwned>(
+    fields: &mut Map<String, Value>,
+    name: String,
+    value: Value,
+    what: &str,
+) {
+    let mut candidate = fields.clone();
+    candidate.insert(name.clone(), value.clone());
+    match from_value::<T>(candidate.into()) {
+        Ok(_) => {
+            fields.insert(name, value);
+        }
+        Err(error) => warn!(
+            "{} {} is invalid ({}), using the default",
+            what, name, error
+        ),
+    }
+}
+
+/// a slot of the plugin chain
+struct Slot {
+    id: u32,
+    path: Option<PathBuf>,
+    /// shared with the audio thread
+    bypass: Arc<AtomicBool>,
+    sidechain: SidechainSource,
+    sandbox: bool,
+    parameters: Parameters,
+}
+
+impl Slot {
+    fn new(saved: &PluginSlot) -> Self {
+        Slot {
+            id: saved.id,
+            path: saved.path.clone(),
+            bypass: Arc::new(AtomicBool::new(saved.bypass)),
+            sidechain: saved.sidechain.clone(),
+            sandbox: saved.sandbox,
+            parameters: Parameters::new(saved.parameters.clone()),
+        }
+    }
+}
+
+pub struct AtomicConfig {
+    /// the plugin chain in order, only written when a plugin is bound, the chain changes or
+    /// a parameter a plugin lacks is stored
+    slots: RwLock<Vec<Slot>>,
+    limiter_ceiling: AtomicF32,
+    resampler_quality: Mutex<ResamplerQuality>,
+    drift_compensation: AtomicBool,
+    routing: Mutex<Routing>,
+    input_device: Mutex<Option<String>>,
+    output_device: Mutex<Option<String>>,
+    sidechain_device: Mutex<Option<String>>,
+    path: PathBuf,
+    /// the config file as last written or read by the application
+    stamp: Mutex<FileStamp>,
+    dirty: AtomicBool,
+    /// set when the parameters changed outside the plugin and must be pushed to it
+    pending_apply: AtomicBool,
+    notify: Sender<()>,
+}
+
+impl AtomicConfig {
+    /// Creates a new config instance, panics if I/O fails
+    pub fn new(notify: Sender<()>) -> Self {
+        let config_dir = dirs::config_dir().unwrap().join("WhisperWare");
+        Self::open(config_dir.join("config.json"), notify)
+    }
+
+    /// Opens the config file at path instead of the user's, panics if I/O fails
+    pub fn open(path: PathBuf, notify: Sender<()>) -> Self {
+        if let Some(config_dir) = path.parent()
+            && !config_dir.exists()
+        {
+            create_dir_all(config_dir).unwrap();
+        }
+
+        Config::load(&path).atomic(path, notify)
+    }
+
+    /// Returns the input and output device IDs
+    pub fn devices(&self) -> (Option<String>, Option<String>) {
+        let input_device = self.input_device.lock().unwrap().clone();
+        let output_device = self.output_device.lock().unwrap().clone();
+        (input_device, output_device)
+    }
+
+    /// Sets the input device ID
+    pub fn set_input_device(&self, device: Option<String>) -> Result<()> {
+        let mut input_device = self.input_device.lock().unwrap();
+        *input_device = device;
+        self.mark_dirty();
+        Ok(())
+    }
+
+    /// Sets the output device ID
+    pub fn set_output_device(&self, device: Option<String>) -> Result<()> {
+        let mut output_device = self.output_device.lock().unwrap();
+        *output_device = device;
+        self.mark_dirty();
+        Ok(())
+    }
+
+    /// Returns the sidechain device ID, none when there is no sidechain device
+    pub fn sidechain_device(&self) -> Option<String> {
+        self.sidechain_device.lock().unwrap().clone()
+    }
+
+    /// Sets the sidechain device ID
+    pub fn set_sidechain_device(&self, device: Option<String>) -> Result<()> {
+        let mut sidechain_device = self.sidechain_device.lock().unwrap();
+        *sidechain_device = device;
+        self.mark_dirty();
+        Ok(())
+    }
+
+    /// Returns the brickwall limiter ceiling in dBFS
+    pub fn limiter_ceiling(&self) -> f32 {
+        self.limiter_ceiling.load(Relaxed)
+    }
+
+    /// Sets the brickwall limiter ceiling in dBFS, clamped to 0 dBFS
+    pub fn set_limiter_ceiling(&self, ceiling: f32) {
+        self.limiter_ceiling.store(ceiling.min(0_f32), Relaxed);
+        self.mark_dirty();
+    }
+
+    /// Returns the resampler quality
+    pub fn resampler_quality(&self) -> ResamplerQuality {
+        *self.resampler_quality.lock().unwrap()
+    }
+
+    /// Sets the resampler quality, used the next time the backend starts
+    pub fn set_resampler_quality(&self, quality: ResamplerQuality) {
+        *self.resampler_quality.lock().unwrap() = quality;
+        self.mark_dirty();
+    }
+
+    /// Returns whether clock drift compensation is enabled
+    pub fn drift_compensation(&self) -> bool {
+        self.drift_compensation.load(Relaxed)
+    }
+
+    /// Enables or disables clock drift compensation, used the next time the backend starts
+    pub fn set_drift_compensation(&self, enabled: bool) {
+        self.drift_compensation.store(enabled, Relaxed);
+        self.mark_dirty();
+    }
+
+    /// Returns the channel routing
+    pub fn routing(&self) -> Routing {
+        self.routing.lock().unwrap().clone()
+    }
+
+    /// Sets the channel routing, used the next time the backend starts
+    pub fn set_routing(&self, routing: Routing) {
+        *self.routing.lock().unwrap() = routing;
+        self.mark_dirty();
+    }
+
+    /// Returns the ids of the plugin chain slots in processing order
+    pub fn slot_ids(&self) -> Vec<u32> {
+        self.slots
+            .read()
+            .unwrap()
+            .iter()
+            .map(|slot| slot.id)
+            .collect()
+    }
+
+    /// Returns the plugin path of a slot, none for the native compressor
+    pub fn plugin_path(&self, slot: u32) -> Option<PathBuf> {
+        self.with_slot(slot, |slot| slot.path.clone()).flatten()
+    }
+
+    /// Sets the plugin path of a slot, used the next time WhisperWare starts
+    pub fn set_plugin_path(&self, slot: u32, path: Option<PathBuf>) {
+        self.with_slot_mut(slot, |slot| slot.path = path);
+        self.mark_dirty();
+    }
+
+    /// Adds a plugin to the end of the chain, used the next time WhisperWare starts
+    pub fn add_slot(&self, path: Option<PathBuf>) -> u32 {
+        let mut slots = self.slots.write().unwrap();
+        let id = slots
+            .iter()
+            .map(|slot| slot.id + 1)
+            .max()
+            .unwrap_or_default();
+        slots.push(Slot::new(&PluginSlot {
+            id,
+            path,
+            bypass: false,
+            sidechain: SidechainSource::default(),
+            sandbox: false,
+            parameters: BTreeMap::new(),
+        }));
+        drop(slots);
+
+        self.mark_dirty();
+        id
+    }
+
+    /// Moves a slot by offset positions in the chain, used the next time the backend starts
+    pub fn move_slot(&self, slot: u32, offset: isize) {
+        let mut slots = self.slots.write().unwrap();
+        let Some(position) = slots.iter().position(|other| other.id == slot) else {
+            return;
+        };
+
+        let target = position.saturating_add_signed(offset).min(slots.len() - 1);
+        let moved = slots.remove(position);
+        slots.insert(target, moved);
+        drop(slots);
+
+        self.mark_dirty();
+    }
+
+    /// Returns whether a slot is bypassed
+    pub fn bypassed(&self, slot: u32) -> bool {
+        self.with_slot(slot, |slot| slot.bypass.load(Relaxed))
+            .unwrap_or_default()
+    }
+
+    /// Bypasses a slot, the running chain skips it from the next block
+    pub fn set_bypass(&self, slot: u32, bypass: bool) {
+        self.with_slot(slot, |slot| slot.bypass.store(bypass, Relaxed));
+        self.mark_dirty();
+    }
+
+    /// Returns what the sidechain inputs of a slot hear
+    pub fn sidechain(&self, slot: u32) -> Option<SidechainSource> {
+        self.with_slot(slot, |slot| slot.sidechain.clone())
+    }
+
+    /// Sets the sidechain source of a slot, used the next time the backend starts
+    pub fn set_sidechain(&self, slot: u32, source: SidechainSource) {
+        self.with_slot_mut(slot, |slot| slot.sidechain = source);
+        self.mark_dirty();
+    }
+
+    /// Returns whether the plugin of a slot runs in a sandbox process
+    pub fn sandboxed(&self, slot: u32) -> bool {
+        self.with_slot(slot, |slot| slot.sandbox)
+            .unwrap_or_default()
+    }
+
+    /// Runs the plugin of a slot in a sandbox process, used the next time WhisperWare starts
+    pub fn set_sandboxed(&self, slot: u32, sandbox: bool) {
+        self.with_slot_mut(slot, |slot| slot.sandbox = sandbox);
+        self.mark_dirty();
+    }
+
+    /// Returns the directories searched for plugins
+    pub fn plugin_search_paths(&self) -> Vec<PathBuf> {
+        search_paths(self.path.parent().unwrap())
+    }
+
+    /// Returns the plugin library of a slot if it can be found
+    pub fn resolve_plugin(&self, slot: u32) -> Option<PathBuf> {
+        find_plugin(&self.plugin_path(slot)?, &self.plugin_search_paths())
+    }
+
+    /// Returns the plugin scanner with its cache next to the config file
+    pub fn plugin_scanner(&self) -> PluginScanner {
+        PluginScanner::new(self.path.with_file_name("plugin_cache.json"))
+    }
+
+    /// Reads the parameter layout of a newly loaded plugin and applies the stored values
+    ///
+    /// plugins with chunk support get their saved state back before the parameters are applied
+    pub fn bind_parameters(&self, slot: u32, instance: &mut dyn Plugin) {
+        self.restore_plugin_state(slot, instance);
+        self.with_slot_mut(slot, |slot| slot.parameters.bind(instance));
+        self.apply_parameters(slot, instance);
+    }
+
+    /// Applies the parameters of a slot to the VST plugin or native compressor
+    pub fn apply_parameters(&self, slot: u32, instance: &mut dyn Plugin) {
+        if let Some(values) = self.parameter_values(slot) {
+            values.apply(instance);
+        }
+    }
+
+    /// Returns the lock free values of the plugin bound to a slot for the audio thread
+    pub fn parameter_values(&self, slot: u32) -> Option<Arc<ParameterValues>> {
+        self.with_slot(slot, |slot| slot.parameters.values())
+    }
+
+    /// the state of a slot the audio thread reads every block
+    pub(crate) fn slot_state(&self, slot: u32) -> Option<(Arc<AtomicBool>, Arc<ParameterValues>)> {
+        self.with_slot(slot, |slot| {
+            (Arc::clone(&slot.bypass), slot.parameters.values())
+        })
+    }
+
+    /// Returns true once after the parameters changed outside the plugin
+    pub fn take_pending_apply(&self) -> bool {
+        self.pending_apply.swap(false, Relaxed)
+    }
+
+    /// Returns the preset store next to the config file
+    pub fn presets(&self) -> PresetStore {
+        PresetStore::new(self.path.with_file_name("presets"))
+    }
+
+    /// Returns the file the watchdog records the stalls in, next to the config file
+    pub fn stall_log(&self) -> PathBuf {
+        self.path.with_file_name("stalls.jsonl")
+    }
+
+    /// Captures the current parameters of the whole chain as a preset
+    pub fn capture_preset(&self, name: &str) -> Preset {
+        let mut parameters = BTreeMap::new();
+        // the first plugin with a parameter name wins, as when the preset is applied
+        for slot in self.slots.read().unwrap().iter().rev() {
+            parameters.extend(slot.parameters.bound());
+        }
+
+        Preset {
+            name: name.to_string(),
+            parameters,
+        }
+    }
+
+    /// Stores the preset values and pushes them to the running plugins
+    ///
+    /// each value goes to every plugin with a parameter of that name, values no plugin has
+    /// are kept with the first slot
+    pub fn apply_preset(&self, preset: &Preset) {
+        let mut slots = self.slots.write().unwrap();
+        for (name, value) in &preset.parameters {
+            let mut bound = false;
+            for slot in slots.iter_mut() {
+                bound |= slot.parameters.set_bound(name, *value);
+            }
+
+            if !bound && let Some(slot) = slots.first_mut() {
+                slot.parameters.set(name, *value);
+            }
+        }
+        drop(slots);
+
+        self.pending_apply.store(true, Relaxed);
+        self.mark_dirty();
+    }
+
+    /// Reloads the config file after it was edited outside the application
+    ///
+    /// changed parameters and bypasses are pushed to the running chain. returns true when the
+    /// devices, stream settings or chain order changed and the backend must restart to use them
+    pub fn reload(&self) -> Result<bool> {
+        let config = Config::read(&self.path)?;
+        let current = self.snapshot();
+        if config == current {
+            return Ok(false);
+        }
+
+        let mut rearrange = false;
+        if config.plugins != current.plugins {
+            let mut slots = self.slots.write().unwrap();
+            let mut updated = Vec::with_capacity(config.plugins.len());
+            let mut replaced = false;
+
+            for saved in &config.plugins {
+                match slots.iter().position(|slot| slot.id == saved.id) {
+                    Some(position) => {
+                        let mut slot = slots.remove(position);
+                        replaced |= slot.path != saved.path || slot.sandbox != saved.sandbox;
+                        // the chain picks up a new sidechain source when the backend restarts
+                        rearrange |= slot.sidechain != saved.sidechain;
+                        slot.path = saved.path.clone();
+                        slot.sidechain = saved.sidechain.clone();
+                        slot.sandbox = saved.sandbox;
+                        slot.bypass.store(saved.bypass, Relaxed);
+                        for (name, value) in &saved.parameters {
+                            slot.parameters.set(name, *value);
+                        }
+                        updated.push(slot);
+                    }
+                    None => {
+                        replaced = true;
+                        updated.push(Slot::new(saved));
+                    }
+                }
+            }
+
+            let order = |plugins: &[PluginSlot]| -> Vec<u32> {
+                plugins.iter().map(|slot| slot.id).collect()
+            };
+            // removed slots are bypassed when the backend restarts
+            rearrange |= !slots.is_empty() || order(&config.plugins) != order(&current.plugins);
+            *slots = updated;
+
+            if replaced {
+                warn!("plugins added or replaced in the config are loaded on the next start");
+            }
+            self.pending_apply.store(true, Relaxed);
+        }
+
+        self.limiter_ceiling
+            .store(config.limiter_ceiling.min(0_f32), Relaxed);
+        self.drift_compensation
+            .store(config.drift_compensation, Relaxed);
+        *self.resampler_quality.lock().unwrap() = config.resampler_quality;
+        *self.routing.lock().unwrap() = config.routing.clone();
+        *self.input_device.lock().unwrap() = config.input_device.clone();
+        *self.output_device.lock().unwrap() = config.output_device.clone();
+        *self.sidechain_device.lock().unwrap() = config.sidechain_device.clone();
+
+        info!("config reloaded after an external edit");
+        Ok(rearrange
+            || config.input_device != current.input_device
+            || config.output_device != current.output_device
+            || config.sidechain_device != current.sidechain_device
+            || config.resampler_quality != current.resampler_quality
+            || config.drift_compensation != current.drift_compensation
+            || config.routing != current.routing)
+    }
+
+    /// Imports an fxp program or fxb bank into the first plugin in the chain it belongs to
+    pub fn import_fx(&self, path: &Path) -> Result<()> {
+        let bytes = read(path)?;
+        let unique_id = fx::plugin_id(&bytes)?;
+
+        let slots = self.slots.read().unwrap();
+        let slot = slots
+            .iter()
+            .find(|slot| {
+                slot.parameters
+                    .plugin()
+                    .is_some_and(|plugin| plugin.info.unique_id == unique_id)
+            })
+            .ok_or(ErrorKind::InvalidFxFile(
+                "the file belongs to a plugin that is not loaded",
+            ))?;
+        let plugin = slot.parameters.plugin().unwrap();
+
+        if is_bank(path) {
+            fx::load_bank(&plugin.info, &*plugin.object, &bytes)?;
+        } else {
+            fx::load_program(&plugin.info, &*plugin.object, &bytes)?;
+        }
+        slot.parameters.refresh();
+        drop(slots);
+
+        self.mark_dirty();
+        Ok(())
+    }
+
+    /// Exports the current program of a slot as fxp, or all its programs as fxb
+    pub fn export_fx(&self, slot: u32, path: &Path) -> Result<()> {
+        let bytes = self
+            .with_slot(slot, |slot| {
+                let plugin = slot.parameters.plugin()?;
+
+                Some(if is_bank(path) {
+                    fx::bank_bytes(&plugin.info, &*plugin.object)
+                } else {
+                    fx::program_bytes(&plugin.info, &*plugin.object)
+                })
+            })
+            .flatten()
+            .ok_or(ErrorKind::NoPluginLoaded)?;
+
+        File::create(path)?.write_all(&bytes)?;
+        Ok(())
+    }
+
+    /// Called when a plugin reports a change to state that is not exposed as parameters
+    pub fn plugin_state_changed(&self) {
+        self.mark_dirty();
+    }
+
+    /// Writes the config and the plugin state now instead of waiting for the saver
+    pub fn save(&self) -> Result<()> {
+        self.save_settings()?;
+        self.save_plugin_state()
+    }
+
+    /// Writes the config now without asking the plugins for their state, for when a plugin
+    /// may not answer
+    pub fn save_settings(&self) -> Result<()> {
+        self.dirty.store(false, Relaxed);
+        let config = self.snapshot();
+
+        // hold the stamp so the watcher never mistakes this write for an external edit
+        let mut stamp = self.stamp.lock().unwrap();
+        let result = config.write(&self.path);
+        *stamp = file_stamp(&self.path);
+        drop(stamp);
+
+        result
+    }
+
+    /// Saves the chunks of the bound plugins, plugins without chunk support are fully covered
+    /// by the parameters
+    fn save_plugin_state(&self) -> Result<()> {
+        let slots = self.slots.read().unwrap();
+        let chunks: Vec<(PathBuf, Vec<u8>)> = slots
+            .iter()
+            .filter_map(|slot| {
+                let plugin = slot.parameters.plugin()?;
+                plugin.info.preset_chunks.then(|| {
+                    (
+                        self.plugin_state_path(slot.id, &plugin.info),
+                        fx::program_bytes(&plugin.info, &*plugin.object),
+                    )
+                })
+            })
+            .collect();
+        drop(slots);
+
+        for (path, bytes) in chunks {
+            create_dir_all(path.parent().unwrap())?;
+            replace_file(&path, &bytes)?;
+        }
+
+        Ok(())
+    }
+
+    /// Loads the saved chunk into a newly loaded plugin, if there is one
+    fn restore_plugin_state(&self, slot: u32, instance: &mut dyn Plugin) {
+        let info = instance.get_info();
+        if !info.preset_chunks {
+            return;
+        }
+
+        // chunks saved before the plugin chain are named after the plugin only
+        let legacy = self
+            .path
+            .with_file_name("plugins")
+            .join(format!("{:08x}.fxp", info.unique_id as u32));
+        let Some(path) = [self.plugin_state_path(slot, &info), legacy]
+            .into_iter()
+            .find(|path| path.exists())
+        else {
+            return;
+        };
+
+        let result = read(&path).map_err(Into::into).and_then(|bytes| {
+            fx::load_program(&info, instance.get_parameter_object().as_ref(), &bytes)
+        });
+        match result {
+            Ok(()) => info!("plugin state restored from {}", path.display()),
+            Err(error) => warn!("failed to restore the plugin state: {}", error),
+        }
+    }
+
+    /// the saved chunk of the plugin in a slot
+    fn plugin_state_path(&self, slot: u32, info: &Info) -> PathBuf {
+        self.path
+            .with_file_name("plugins")
+            .join(format!("{}-{:08x}.fxp", slot, info.unique_id as u32))
+    }
+
+    /// Called when a parameter is changed in a VST plugin
+    pub fn set_parameter(&self, slot: u32, index: usize, value: f32) {
+        let values = self.parameter_values(slot);
+        if values.is_some_and(|values| values.set(index, value)) {
+            self.mark_dirty();
+        } else {
+            error!("Invalid parameter index: {} in slot {}", index, slot);
+        }
+    }
+
+    fn with_slot<T>(&self, id: u32, f: impl FnOnce(&Slot) -> T) -> Option<T> {
+        self.slots
+            .read()
+            .unwrap()
+            .iter()
+            .find(|slot| slot.id == id)
+            .map(f)
+    }
+
+    fn with_slot_mut<T>(&self, id: u32, f: impl FnOnce(&mut Slot) -> T) -> Option<T> {
+        self.slots
+            .write()
+            .unwrap()
+            .iter_mut()
+            .find(|slot| slot.id == id)
+            .map(f)
+    }
+
+    /// Returns the current state of the config as Config
+    fn snapshot(&self) -> Config {
+        Config {
+            version: CONFIG_VERSION,
+            plugins: self
+                .slots
+                .read()
+                .unwrap()
+                .iter()
+                .map(|slot| PluginSlot {
+                    id: slot.id,
+                    path: slot.path.clone(),
+                    bypass: slot.bypass.load(Relaxed),
+                    sidechain: slot.sidechain.clone(),
+                    sandbox: slot.sandbox,
+                    parameters: slot.parameters.map(),
+                })
+                .collect(),
+            limiter_ceiling: self.limiter_ceiling.load(Relaxed),
+            resampler_quality: *self.resampler_quality.lock().unwrap(),
+            drift_compensation: self.drift_compensation.load(Relaxed),
+            routing: self.routing.lock().unwrap().clone(),
+            input_device: self.input_device.lock().unwrap().clone(),
+            output_device: self.output_device.lock().unwrap().clone(),
+            sidechain_device: self.sidechain_device.lock().unwrap().clone(),
+        }
+    }
+
+    /// Notifies writer that the config has changed
+    fn mark_dirty(&self) {
+        // avoid spamming the channel on repeated writes
+        if !self.dirty.swap(true, Relaxed) {
+            let _ = self.notify.send(());
+        }
+    }
+}
+
+/// the modification time and length of a file, none if it cannot be read
+pub(crate) fn file_stamp(path: &Path) -> FileStamp {
+    let metadata = path.metadata().ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// the previous good config, kept next to the config file
+fn backup_path(path: &Path) -> PathBuf {
+    path.with_extension("json.bak")
+}
+
+/// replaces a file with the bytes so it holds either the old or the new bytes after a crash
+///
+/// the bytes are written to a temporary file and synced before it is renamed over the file,
+/// then the directory is synced so the rename is on disk as well
+fn replace_file(path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+
+    let mut file = File::create(&temporary)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+
+    rename(&temporary, path)?;
+    sync_directory(path)
+}
+
+/// syncs the directory holding path, so renames in it survive a power loss
+#[cfg(unix)]
+fn sync_directory(path: &Path) -> Result<()> {
+    let directory = path
+        .parent()
+        .filter(|directory| !directory.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    File::open(directory)?.sync_all()?;
+    Ok(())
+}
+
+/// windows cannot open a directory as a file, its file systems journal the rename itself
+#[cfg(not(unix))]
+fn sync_directory(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// whether path names an fxb bank rather than an fxp program
+fn is_bank(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("fxb"))
+}
+
+/// saves the config without blocking the main thread or spamming the disk
+pub fn config_saver(config: Arc<AtomicConfig>, receiver: Receiver<()>) -> Result<()> {
+    let interval = Duration::from_millis(200); // debounce window
+
+    loop {
+        // block until at least one change arrives
+        if receiver.recv().is_err() {
+            return Ok(());
+        }
+
+        // keep draining events for a short time
+        while receiver.recv_timeout(interval).is_ok() {
+            // do nothing, just coalesce
+        }
+
+        // write once per burst of changes
+        if let Err(error) = config.save() {
+            error!("Failed to save config file: {}", error);
+        }
+    }
+}
+
+/// reloads the config whenever the file is edited outside the application
+///
+/// the file is polled because edits are rare. invalid edits are logged and ignored until
+/// the file changes again, and restart is called when the backend must restart
+pub fn config_watcher(config: Arc<AtomicConfig>, restart: impl Fn()) {
+    let interval = Duration::from_millis(500);
+
+    loop {
+        sleep(interval);
+
+        match reload_if_edited(&config) {
+            Some(Ok(true)) => restart(),
+            Some(Ok(false)) | None => (),
+            Some(Err(error)) => warn!("ignoring invalid config edit: {}", error),
+        }
+    }
+}
+
+/// reloads the config when the file changed since the application last read or wrote it,
+/// none when it did not
+fn reload_if_edited(config: &AtomicConfig) -> Option<Result<bool>> {
+    let mut stamp = config.stamp.lock().unwrap();
+    let current = file_stamp(&config.path);
+    if current == *stamp {
+        return None;
+    }
+    *stamp = current;
+
+    Some(config.reload())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::Compressor;
+    use crate::testing::{open_config, scratch_dir};
+    use std::fs::{read_dir, write};
+
+    /// an unversioned config.json from before the plugin chain
+    const BASELINE: &str = r#"{
+        "sidechain_hpf": 20.0, "input_level": 1.0, "sensitivity": 0.48333332,
+        "ratio": 1.0, "attack": 0.0, "release": 0.09090909, "makeup": 0.33333334,
+        "mix": 1.0, "output_level": 1.0, "sidechain": 0.0, "full_bandwidth": 1.0,
+        "input_device": "wasapi:microphone", "output_device": null
+    }"#;
+
+    fn object(json: &str) -> Map<String, Value> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    /// reads the config text from a file, as the application does
+    fn read_config(name: &str, json: &str) -> Config {
+        let path = scratch_dir(name).join("config.json");
+        write(&path, json).unwrap();
+        Config::read(&path).unwrap()
+    }
+
+    #[test]
+    fn version_one_gathers_the_parameter_fields_by_name() {
+        let mut config =
+            object(r#"{ "version": 1, "ratio": 0.5, "mix": 1.0, "limiter_ceiling": -3.0 }"#);
+        MIGRATIONS[1](&mut config);
+        assert_eq!(
+            config,
+            object(
+                r#"{ "version": 1, "limiter_ceiling": -3.0,
+                     "parameters": { "Ratio": 0.5, "Mix": 1.0 } }"#
+            )
+        );
+    }
+
+    #[test]
+    fn version_two_moves_the_plugin_into_the_chain() {
+        let mut config =
+            object(r#"{ "version": 2, "plugin_path": "Eq.dll", "parameters": { "Gain": 0.25 } }"#);
+        MIGRATIONS[2](&mut config);
+        assert_eq!(
+            config,
+            object(
+                r#"{ "version": 2, "plugins": [{ "id": 0, "path": "Eq.dll", "bypass": false,
+                     "parameters": { "Gain": 0.25 } }] }"#
+            )
+        );
+
+        // a config without a plugin path used the default plugin
+        let mut config = object(r#"{ "version": 2 }"#);
+        MIGRATIONS[2](&mut config);
+        assert_eq!(config["plugins"][0]["path"], DEFAULT_PLUGIN);
+    }
+
+    #[test]
+    fn the_baseline_migrates_to_the_current_version() {
+        let config = read_config("config_baseline", BASELINE);
+
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.input_device.as_deref(), Some("wasapi:microphone"));
+        let [slot] = config.plugins.as_slice() else {
+            panic!("the baseline has a single plugin");
+        };
+        assert_eq!(slot.path.as_deref(), Some(Path::new(DEFAULT_PLUGIN)));
+        assert_eq!(slot.parameters.len(), LEGACY_PARAMETERS.len());
+        assert_eq!(slot.parameters["Sensitivity"], 0.48333332);
+        // the settings added since take their defaults
+        assert_eq!(config.limiter_ceiling, Config::default().limiter_ceiling);
+        assert!(config.drift_compensation);
+    }
+
+    #[test]
+    fn every_version_reads_to_the_same_chain() {
+        let expected = read_config("config_v0", r#"{ "ratio": 0.5 }"#).plugins;
+        for (name, json) in [
+            ("config_v1", r#"{ "version": 1, "ratio": 0.5 }"#),
+            (
+                "config_v2",
+                r#"{ "version": 2, "plugin_path": "RoughRider3.dll", "parameters": { "Ratio": 0.5 } }"#,
+            ),
+            (
+                "config_v3",
+                r#"{ "version": 3, "plugins": [{ "id": 0, "path": "RoughRider3.dll",
+                     "parameters": { "Ratio": 0.5 } }] }"#,
+            ),
+        ] {
+            assert!(read_config(name, json).plugins == expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn invalid_settings_take_their_defaults() {
+        let config = read_config(
+            "config_lenient",
+            r#"{ "version": 3, "limiter_ceiling": "loud", "drift_compensation": false,
+                 "routing": "sideways", "plugins": [
+                     { "id": 4, "path": "Eq.dll", "bypass": "yes", "sandbox": true },
+                     { "id": "five", "path": "Gate.dll" },
+                     { "id": 6, "parameters": { "Gain": "high" } }
+                 ] }"#,
+        );
+
+        let defaults = Config::default();
+        assert_eq!(config.limiter_ceiling, defaults.limiter_ceiling);
+        assert_eq!(config.routing, defaults.routing);
+        assert!(!config.drift_compensation);
+
+        let slots: Vec<_> = config
+            .plugins
+            .iter()
+            .map(|slot| (slot.id, slot.bypass, slot.sandbox, slot.parameters.len()))
+            .collect();
+        assert_eq!(slots, [(4, false, true, 0), (6, false, false, 0)]);
+        assert_eq!(config.plugins[0].path.as_deref(), Some(Path::new("Eq.dll")));
+    }
+
+    #[test]
+    fn captured_presets_apply_to_the_bound_plugin() {
+        let config = open_config(&scratch_dir("config_presets"));
+        let slot = config.add_slot(None);
+        let mut compressor = Compressor::default();
+        config.bind_parameters(slot, &mut compressor);
+
+        let mut preset = config.capture_preset("shooter");
+        assert_eq!(preset.name, "shooter");
+        assert_eq!(
+            preset.parameters.len(),
+            compressor.get_info().parameters as usize
+        );
+
+        let name = compressor.get_parameter_object().get_parameter_name(0);
+        preset.parameters.insert(name, 0.125);
+        config.apply_preset(&preset);
+        assert!(config.take_pending_apply());
+        assert_eq!(config.capture_preset("shooter"), preset);
+
+        // the backend path pushes the values to the plugin
+        config.apply_parameters(slot, &mut compressor);
+        assert_eq!(compressor.get_parameter_object().get_parameter(0), 0.125);
+    }
+
+    /// saves the config with a ceiling, so the last saved ceiling ends up in the backup
+    fn save_ceiling(config: &AtomicConfig, ceiling: f32) {
+        config.set_limiter_ceiling(ceiling);
+        config.save().unwrap();
+    }
+
+    /// edits the config file as a text editor would, outside the application
+    fn edit(config: &AtomicConfig, edit: impl FnOnce(&mut Map<String, Value>)) {
+        let mut json = object(&std::fs::read_to_string(&config.path).unwrap());
+        edit(&mut json);
+        write(&config.path, serde_json::to_string_pretty(&json).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn external_edits_are_reloaded() {
+        let config = open_config(&scratch_dir("external_edits"));
+        config.save().unwrap();
+        assert!(reload_if_edited(&config).is_none());
+
+        // the limiter is read by the running chain, the devices only when the backend starts
+        edit(&config, |json| {
+            json.insert("limiter_ceiling".into(), Value::from(-6.0));
+        });
+        assert!(!reload_if_edited(&config).unwrap().unwrap());
+        assert_eq!(config.limiter_ceiling(), -6.0);
+
+        edit(&config, |json| {
+            json.insert("input_device".into(), Value::from("wasapi:headset"));
+            json.insert("drift_compensation".into(), Value::from(false));
+        });
+        assert!(reload_if_edited(&config).unwrap().unwrap());
+        assert_eq!(config.devices().0.as_deref(), Some("wasapi:headset"));
+        assert!(!config.drift_compensation());
+        assert!(reload_if_edited(&config).is_none());
+
+        // invalid edits are ignored until the file changes again
+        write(&config.path, "{ not json").unwrap();
+        assert!(reload_if_edited(&config).unwrap().is_err());
+        assert!(reload_if_edited(&config).is_none());
+        assert_eq!(config.limiter_ceiling(), -6.0);
+    }
+
+    #[test]
+    fn the_applications_own_saves_are_not_reloaded() {
+        let config = open_config(&scratch_dir("own_saves"));
+        config.save().unwrap();
+
+        for ceiling in [-1.0, -2.0, -3.0] {
+            config.set_limiter_ceiling(ceiling);
+            config
+                .set_output_device(Some(format!("wasapi:speakers {ceiling}")))
+                .unwrap();
+            config.save().unwrap();
+            assert!(reload_if_edited(&config).is_none());
+        }
+        config.save_settings().unwrap();
+        assert!(reload_if_edited(&config).is_none());
+        assert_eq!(config.limiter_ceiling(), -3.0);
+    }
+
+    #[test]
+    fn a_corrupt_config_is_kept_and_restored_from_the_backup() {
+        let directory = scratch_dir("config_corrupt");
+        let path = directory.join("config.json");
+        let config = open_config(&directory);
+        save_ceiling(&config, -3_f32);
+        save_ceiling(&config, -6_f32);
+        assert_eq!(
+            Config::read(&backup_path(&path)).unwrap().limiter_ceiling,
+            -3_f32
+        );
+
+        write(&path, "{ \"limiter_ceiling\": -6.0, ").unwrap();
+        assert_eq!(Config::load(&path).limiter_ceiling, -3_f32);
+        assert_eq!(
+            read(path.with_extension("json.corrupt")).unwrap(),
+            b"{ \"limiter_ceiling\": -6.0, "
+        );
+
+        // saving over the corrupt file leaves the good backup alone
+        save_ceiling(&config, -9_f32);
+        assert_eq!(
+            Config::read(&backup_path(&path)).unwrap().limiter_ceiling,
+            -3_f32
+        );
+        assert_eq!(Config::read(&path).unwrap().limiter_ceiling, -9_f32);
+
+        // nothing but the config, its backup and the corrupt copy is left behind
+        let mut files: Vec<_> = read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            ["config.json", "config.json.bak", "config.json.corrupt"]
+        );
+    }
+
+    #[test]
+    fn a_corrupt_backup_falls_back_to_the_defaults() {
+        let directory = scratch_dir("config_corrupt_backup");
+        let path = directory.join("config.json");
+        write(&path, "not json").unwrap();
+        write(backup_path(&path), "{ broken").unwrap();
+
+        assert!(Config::load(&path) == Config::default());
+        assert_eq!(
+            read(path.with_extension("json.corrupt")).unwrap(),
+            b"not json"
+        );
+    }
+}
@@ -0,0 +1,436 @@
+use crate::backend::backend;
+use crate::chain::PluginChain;
+use crate::config::AtomicConfig;
+use crate::devices::{DeviceEvent, DeviceWatcher, Direction};
+use crate::error::ErrorKind;
+use crate::watchdog::Watchdog;
+use cpal::Device;
+use cpal::traits::DeviceTrait;
+use log::{debug, error, info, warn};
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::sleep;
+use std::time::Duration;
+
+/// how long the backend waits before starting again after it stopped or first failed
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+/// how often a fault is retried on its own, each wait twice as long as the one before
+const RETRIES: u32 = 6;
+/// how often a missing device is looked for while no watcher reports the device changes
+const DEVICE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// what the backend is doing
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackendState {
+    /// the backend has not started yet or was stopped
+    Idle,
+    /// the input or output device is missing, the backend starts when the devices change
+    WaitingForDevice,
+    /// opening the devices and preparing the plugins
+    Starting,
+    /// the audio is flowing
+    Running,
+    /// the stream is ending to start again, for new settings or after it stalled
+    Restarting,
+    /// the backend failed to start. it retries after a growing delay and then waits for the
+    /// devices to change or a restart. faults are compared by their kind
+    Faulted(Arc<ErrorKind>),
+}
+
+impl Display for BackendState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackendState::Idle => write!(f, "idle"),
+            BackendState::WaitingForDevice => write!(f, "waiting for a device"),
+            BackendState::Starting => write!(f, "starting"),
+            BackendState::Running => write!(f, "running"),
+            BackendState::Restarting => write!(f, "restarting"),
+            BackendState::Faulted(kind) => write!(f, "faulted, {}", kind),
+        }
+    }
+}
+
+/// drives the backend through its states and tells the subscribers about every change
+///
+/// menu actions and config edits ask for a restart here instead of ending the stream
+/// themselves
+pub struct BackendControl {
+    state: Mutex<BackendState>,
+    subscribers: Mutex<Vec<Sender<BackendState>>>,
+    /// cleared to end the current stream, watched by the processor
+    pub(crate) run: Arc<AtomicBool>,
+    /// set once the backend should not start again
+    stopped: AtomicBool,
+    /// the fault last logged, cleared once the backend runs
+    last_fault: Mutex<Option<Arc<ErrorKind>>>,
+    /// set by a requested restart, which starts again without waiting
+    restart_requested: AtomicBool,
+    /// the ids of the default devices the current stream opened, none for a configured device
+    default_input: Mutex<Option<String>>,
+    default_output: Mutex<Option<String>>,
+    /// counts the device changes, the backend waits for it to move while a device is missing
+    device_changes: Mutex<u64>,
+    device_changed: Condvar,
+    /// set while a watcher reports the device changes
+    watching: AtomicBool,
+}
+
+impl Default for BackendControl {
+    fn default() -> Self {
+        BackendControl {
+            state: Mutex::new(BackendState::Idle),
+            subscribers: Default::default(),
+            run: Arc::new(AtomicBool::new(true)),
+            stopped: Default::default(),
+            last_fault: Default::default(),
+            restart_requested: Default::default(),
+            default_input: Default::default(),
+            default_output: Default::default(),
+            device_changes: Default::default(),
+            device_changed: Default::default(),
+            watching: Default::default(),
+        }
+    }
+}
+
+impl BackendControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn state(&self) -> BackendState {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// receives the current state and then every change, until the receiver is dropped
+    pub fn subscribe(&self) -> Receiver<BackendState> {
+        let (sender, receiver) = channel();
+        // holding the state keeps a change from slipping in between
+        let state = self.state.lock().unwrap();
+        let _ = sender.send(state.clone());
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// ends the stream so the backend starts again with the current config
+    pub fn restart(&self) {
+        if matches!(self.state(), BackendState::Starting | BackendState::Running) {
+            self.transition(BackendState::Restarting);
+        }
+        self.restart_requested.store(true, Relaxed);
+        self.run.store(false, Relaxed);
+        // a backend waiting for a device tries again as well
+        self.wake();
+    }
+
+    /// ends the stream for good, the backend becomes idle
+    pub fn stop(&self) {
+        self.stopped.store(true, Relaxed);
+        self.run.store(false, Relaxed);
+        self.wake();
+    }
+
+    /// passes the changes a watcher reports to the backend, until the watcher stops
+    ///
+    /// without a watcher a missing device is looked for every few seconds
+    pub fn watch_devices(&self, watcher: &mut dyn DeviceWatcher) {
+        self.watching.store(true, Relaxed);
+        while let Some(event) = watcher.next_event() {
+            self.device_event(&event);
+        }
+        self.watching.store(false, Relaxed);
+
+        warn!("the device watcher stopped, missing devices are looked for periodically");
+        self.wake();
+    }
+
+    /// reacts to a change of the audio devices
+    ///
+    /// a stream that plays through the old default device moves to the new one, a stream
+    /// using a configured device or already on the new default is left alone. any change
+    /// wakes a backend waiting for a missing device
+    pub fn device_event(&self, event: &DeviceEvent) {
+        debug!("device event: {:?}", event);
+
+        if let DeviceEvent::DefaultChanged { id, direction } = event {
+            let current = self.default_device(*direction).lock().unwrap().clone();
+            if current.is_some() && current != *id {
+                info!(
+                    "the default {} device changed, moving the stream",
+                    direction
+                );
+                self.restart();
+            }
+        }
+
+        self.wake();
+    }
+
+    /// runs the backend until it is stopped, starting it again whenever the stream ends
+    ///
+    /// while a device is missing it waits for the device changes passed to
+    /// [`BackendControl::device_event`]
+    pub fn run(
+        &self,
+        host: &Arc<cpal::Host>,
+        config: &AtomicConfig,
+        chain: &mut PluginChain,
+        watchdog: &Watchdog,
+    ) {
+        let mut failures = 0;
+        while !self.stopped.load(Relaxed) {
+            // changes from here on end a wait below, even those made while starting
+            let changes = *self.device_changes.lock().unwrap();
+            // set before starting is published so a restart asked for right after still ends
+            // the stream
+            self.run.store(true, Relaxed);
+            self.transition(BackendState::Starting);
+
+            let result = backend(host, config, chain, watchdog, self);
+            self.follow(Direction::Input, None);
+            self.follow(Direction::Output, None);
+
+            match result {
+                // a restart was requested, the stream stalled or a device reported an error
+                Ok(()) => {
+                    failures = 0;
+                    self.transition(BackendState::Restarting);
+                }
+                Err(error) => {
+                    let delay = match error.kind {
+                        ErrorKind::NoInputDevice | ErrorKind::NoOutputDevice => {
+                            self.transition(BackendState::WaitingForDevice);
+                            None
+                        }
+                        kind => {
+                            self.transition(BackendState::Faulted(Arc::new(kind)));
+                            failures += 1;
+                            retry_delay(failures)
+                        }
+                    };
+                    if self.wait_for_change(changes, delay) {
+                        failures = 0;
+                    }
+                    self.restart_requested.store(false, Relaxed);
+                    continue;
+                }
+            }
+
+            // a requested restart starts again right away so moving devices is seamless
+            if !self.restart_requested.swap(false, Relaxed) {
+                sleep(RETRY_INTERVAL);
+            }
+        }
+
+        self.transition(BackendState::Idle);
+    }
+
+    /// records the default device the stream opened for a direction, none when it opened a
+    /// configured device or the stream ended
+    pub(crate) fn follow(&self, direction: Direction, device: Option<&Device>) {
+        self.follow_id(direction, device_id(device));
+    }
+
+    fn follow_id(&self, direction: Direction, id: Option<String>) {
+        *self.default_device(direction).lock().unwrap() = id;
+    }
+
+    /// blocks until the devices changed or a restart was asked for since the count was seen,
+    /// or until the delay passed. true when something changed
+    ///
+    /// without a delay it waits for the watcher, or a while when no watcher reports the
+    /// changes
+    fn wait_for_change(&self, seen: u64, delay: Option<Duration>) -> bool {
+        let changes = self.device_changes.lock().unwrap();
+        let unchanged = |changes: &mut u64| *changes == seen;
+
+        let timeout = match delay {
+            Some(delay) => delay,
+            None if self.watching.load(Relaxed) => {
+                return *self.device_changed.wait_while(changes, unchanged).unwrap() != seen;
+            }
+            None => DEVICE_POLL_INTERVAL,
+        };
+        let (changes, _) = self
+            .device_changed
+            .wait_timeout_while(changes, timeout, unchanged)
+            .unwrap();
+        *changes != seen
+    }
+
+    /// wakes a backend waiting for a device
+    fn wake(&self) {
+        *self.device_changes.lock().unwrap() += 1;
+        self.device_changed.notify_all();
+    }
+
+    fn default_device(&self, direction: Direction) -> &Mutex<Option<String>> {
+        match direction {
+            Direction::Input => &self.default_input,
+            Direction::Output => &self.default_output,
+        }
+    }
+
+    /// moves to a state, logging it and telling the subscribers when it changed
+    pub(crate) fn transition(&self, next: BackendState) {
+        let mut state = self.state.lock().unwrap();
+        if *state == next {
+            return;
+        }
+
+        let mut last_fault = self.last_fault.lock().unwrap();
+        match &next {
+            // a fault that repeats while retrying is only logged once
+            BackendState::Faulted(kind) if last_fault.as_ref() != Some(kind) => {
+                error!("backend error: {}", kind);
+                *last_fault = Some(Arc::clone(kind));
+            }
+            BackendState::Running => {
+                info!("backend running");
+                *last_fault = None;
+            }
+            next => info!("backend {}", next),
+        }
+        drop(last_fault);
+        *state = next.clone();
+
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(next.clone()).is_ok());
+    }
+}
+
+/// how long to wait after the backend failed a number of times in a row, none once it
+/// should stay faulted until the devices change or a restart is asked for
+fn retry_delay(failures: u32) -> Option<Duration> {
+    (failures <= RETRIES).then(|| RETRY_INTERVAL * 2_u32.pow(failures - 1))
+}
+
+fn device_id(device: Option<&Device>) -> Option<String> {
+    device
+        .and_then(|device| device.id().ok())
+        .map(|id| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::MockWatcher;
+    use std::thread::scope;
+
+    fn added(id: &str) -> DeviceEvent {
+        DeviceEvent::Added {
+            id: id.to_string(),
+            direction: Direction::Output,
+        }
+    }
+
+    fn default_changed(id: Option<&str>, direction: Direction) -> DeviceEvent {
+        DeviceEvent::DefaultChanged {
+            id: id.map(str::to_string),
+            direction,
+        }
+    }
+
+    #[test]
+    fn a_new_default_moves_a_stream_on_the_old_one() {
+        let control = BackendControl::new();
+        control.transition(BackendState::Running);
+        control.follow_id(Direction::Output, Some("alsa:a".to_string()));
+
+        control.watch_devices(&mut MockWatcher::new([default_changed(
+            Some("alsa:b"),
+            Direction::Output,
+        )]));
+        assert_eq!(control.state(), BackendState::Restarting);
+        assert!(!control.run.load(Relaxed));
+        assert!(control.restart_requested.load(Relaxed));
+    }
+
+    #[test]
+    fn a_default_that_goes_away_moves_the_stream() {
+        let control = BackendControl::new();
+        control.transition(BackendState::Running);
+        control.follow_id(Direction::Input, Some("alsa:a".to_string()));
+
+        control.watch_devices(&mut MockWatcher::new([default_changed(
+            None,
+            Direction::Input,
+        )]));
+        assert_eq!(control.state(), BackendState::Restarting);
+    }
+
+    #[test]
+    fn streams_off_the_changed_default_stay() {
+        let control = BackendControl::new();
+        control.transition(BackendState::Running);
+        // the output is on the default, the input on a configured device
+        control.follow_id(Direction::Output, Some("alsa:a".to_string()));
+
+        control.watch_devices(&mut MockWatcher::new([
+            default_changed(Some("alsa:a"), Direction::Output),
+            default_changed(Some("alsa:b"), Direction::Input),
+            added("alsa:c"),
+            DeviceEvent::StateChanged {
+                id: "alsa:a".to_string(),
+                direction: Direction::Output,
+                active: false,
+            },
+            DeviceEvent::Removed {
+                id: "alsa:c".to_string(),
+                direction: Direction::Output,
+            },
+        ]));
+        assert_eq!(control.state(), BackendState::Running);
+        assert!(control.run.load(Relaxed));
+    }
+
+    #[test]
+    fn device_changes_wake_a_backend_waiting_for_a_device() {
+        let control = BackendControl::new();
+        let (events, mut watcher) = MockWatcher::channel();
+
+        scope(|scope| {
+            scope.spawn(|| control.watch_devices(&mut watcher));
+            let waiting = scope.spawn(|| control.wait_for_change(0, None));
+            // the waiter may start after an event, so they keep coming until it returns
+            while !waiting.is_finished() {
+                events.send(added("alsa:a")).unwrap();
+                sleep(Duration::from_millis(10));
+            }
+            drop(events);
+            assert!(waiting.join().unwrap());
+        });
+        assert!(!control.watching.load(Relaxed));
+    }
+
+    #[test]
+    fn a_restart_ends_the_wait_of_a_faulted_backend() {
+        let control = BackendControl::new();
+        let seen = *control.device_changes.lock().unwrap();
+        scope(|scope| {
+            let waiting =
+                scope.spawn(|| control.wait_for_change(seen, Some(Duration::from_secs(60))));
+            while !waiting.is_finished() {
+                control.restart();
+                sleep(Duration::from_millis(10));
+            }
+            assert!(waiting.join().unwrap());
+        });
+        let seen = *control.device_changes.lock().unwrap();
+        assert!(!control.wait_for_change(seen, Some(Duration::from_millis(1))));
+    }
+
+    #[test]
+    fn faults_are_retried_less_often_and_then_left() {
+        let delays: Vec<_> = (1..=RETRIES + 1).map(retry_delay).collect();
+        assert_eq!(delays[0], Some(RETRY_INTERVAL));
+        assert_eq!(delays[1], Some(RETRY_INTERVAL * 2));
+        assert_eq!(delays[RETRIES as usize - 1], Some(RETRY_INTERVAL * 32));
+        assert_eq!(delays[RETRIES as usize], None);
+    }
+}
@@ -0,0 +1,490 @@
+use std::fmt::{Display, Formatter};
+use std::sync::mpsc::{Receiver, Sender, channel};
+
+#[cfg(target_os = "linux")]
+pub use linux::PulseWatcher;
+
+/// which way audio flows through a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Direction::Input => "input",
+            Direction::Output => "output",
+        })
+    }
+}
+
+/// a change of the audio devices
+///
+/// the ids are those the host uses for the device, which match the configured device ids
+/// when the watcher and the backend share a host
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceEvent {
+    Added {
+        id: String,
+        direction: Direction,
+    },
+    Removed {
+        id: String,
+        direction: Direction,
+    },
+    /// a device was enabled, disabled, unplugged or plugged back in
+    StateChanged {
+        id: String,
+        direction: Direction,
+        active: bool,
+    },
+    /// the system default of a direction changed, none when no device is left or the host
+    /// does not list the new default
+    DefaultChanged {
+        id: Option<String>,
+        direction: Direction,
+    },
+}
+
+impl DeviceEvent {
+    pub fn direction(&self) -> Direction {
+        match self {
+            DeviceEvent::Added { direction, .. }
+            | DeviceEvent::Removed { direction, .. }
+            | DeviceEvent::StateChanged { direction, .. }
+            | DeviceEvent::DefaultChanged { direction, .. } => *direction,
+        }
+    }
+}
+
+/// reports the changes of the audio devices, see [`crate::BackendControl::watch_devices`]
+pub trait DeviceWatcher {
+    /// blocks until the next change, none once the watcher stopped
+    fn next_event(&mut self) -> Option<DeviceEvent>;
+}
+
+/// reports scripted events, for driving the backend without real devices
+pub struct MockWatcher {
+    events: Receiver<DeviceEvent>,
+}
+
+impl MockWatcher {
+    /// reports the events in order and then stops
+    pub fn new(events: impl IntoIterator<Item = DeviceEvent>) -> Self {
+        let (sender, watcher) = Self::channel();
+        for event in events {
+            let _ = sender.send(event);
+        }
+        watcher
+    }
+
+    /// reports the events sent to it as they arrive, and stops once the sender is dropped
+    pub fn channel() -> (Sender<DeviceEvent>, Self) {
+        let (sender, events) = channel();
+        (sender, MockWatcher { events })
+    }
+}
+
+impl DeviceWatcher for MockWatcher {
+    fn next_event(&mut self) -> Option<DeviceEvent> {
+        self.events.recv().ok()
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{DeviceEvent, DeviceWatcher, Direction};
+    use crate::Result;
+    use crate::error::ErrorKind;
+    use log::warn;
+    use std::collections::{HashMap, VecDeque};
+    use std::io::{BufRead, BufReader, Lines};
+    use std::process::{Child, ChildStdout, Command, Stdio};
+
+    /// watches the sinks and sources of PulseAudio, or of PipeWire through its pulse server
+    ///
+    /// follows `pactl subscribe` and reports each sink and source by the id the alsa host
+    /// gives the card behind it. sinks without an alsa card, such as bluetooth or virtual
+    /// ones, are not reported and a default moving to one is reported as none
+    pub struct PulseWatcher {
+        child: Child,
+        lines: Lines<BufReader<ChildStdout>>,
+        /// the sinks and sources by index, monitor sources are left out
+        devices: HashMap<(Direction, u32), PulseDevice>,
+        default_input: Option<String>,
+        default_output: Option<String>,
+        /// events waiting to be reported, a server change can move both defaults
+        pending: VecDeque<DeviceEvent>,
+    }
+
+    /// a sink or source as pactl lists it
+    #[derive(Debug, Clone, PartialEq)]
+    pub(super) struct PulseDevice {
+        pub(super) name: String,
+        /// the alsa id of the card behind it, none when it has no alsa card
+        pub(super) id: Option<String>,
+        /// false while its active port is unplugged
+        pub(super) active: bool,
+    }
+
+    impl PulseWatcher {
+        pub fn new() -> Result<Self> {
+            let mut child = pactl(&["subscribe"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()?;
+            let Some(stdout) = child.stdout.take() else {
+                return Err(ErrorKind::DeviceWatcher("pactl has no output").into());
+            };
+
+            let mut watcher = PulseWatcher {
+                child,
+                lines: BufReader::new(stdout).lines(),
+                devices: HashMap::new(),
+                default_input: None,
+                default_output: None,
+                pending: VecDeque::new(),
+            };
+            for direction in [Direction::Input, Direction::Output] {
+                for (index, device) in list(direction)? {
+                    watcher.devices.insert((direction, index), device);
+                }
+            }
+            let (input, output) = defaults()?;
+            watcher.default_input = watcher.default_id(Direction::Input, input);
+            watcher.default_output = watcher.default_id(Direction::Output, output);
+            Ok(watcher)
+        }
+
+        /// picks up a new sink or source
+        fn added(&mut self, direction: Direction, index: u32) {
+            let Some(device) = self.listed(direction, index) else {
+                return;
+            };
+            if let Some(id) = device.id.clone() {
+                self.pending.push_back(DeviceEvent::Added { id, direction });
+            }
+            self.devices.insert((direction, index), device);
+        }
+
+        /// reports a sink or source whose port was unplugged or plugged back in
+        fn changed(&mut self, direction: Direction, index: u32) {
+            let Some(device) = self.listed(direction, index) else {
+                return;
+            };
+            let previous = self.devices.insert((direction, index), device.clone());
+            if let (Some(previous), Some(id)) = (previous, device.id)
+                && previous.active != device.active
+            {
+                self.pending.push_back(DeviceEvent::StateChanged {
+                    id,
+                    direction,
+                    active: device.active,
+                });
+            }
+        }
+
+        fn removed(&mut self, direction: Direction, index: u32) {
+            if let Some(PulseDevice { id: Some(id), .. }) = self.devices.remove(&(direction, index))
+            {
+                self.pending
+                    .push_back(DeviceEvent::Removed { id, direction });
+            }
+        }
+
+        /// compares the defaults to the last known ones after the server changed
+        fn server_changed(&mut self) {
+            let (input, output) = match defaults() {
+                Ok(defaults) => defaults,
+                Err(error) => {
+                    warn!("failed to read the default devices: {}", error);
+                    return;
+                }
+            };
+            let input = self.default_id(Direction::Input, input);
+            let output = self.default_id(Direction::Output, output);
+
+            for (direction, current, id) in [
+                (Direction::Input, &mut self.default_input, input),
+                (Direction::Output, &mut self.default_output, output),
+            ] {
+                if *current != id {
+                    *current = id.clone();
+                    self.pending
+                        .push_back(DeviceEvent::DefaultChanged { id, direction });
+                }
+            }
+        }
+
+        /// the id of the default sink or source with the name, listing the devices again
+        /// when the server names one not seen yet
+        fn default_id(&self, direction: Direction, name: Option<String>) -> Option<String> {
+            let name = name?;
+            let known = self
+                .devices
+                .iter()
+                .find(|((d, _), device)| *d == direction && device.name == name);
+            match known {
+                Some((_, device)) => device.id.clone(),
+                None => {
+                    list(direction)
+                        .ok()?
+                        .into_iter()
+                        .find(|(_, device)| device.name == name)?
+                        .1
+                        .id
+                }
+            }
+        }
+
+        /// the sink or source with the index as pactl lists it now
+        fn listed(&self, direction: Direction, index: u32) -> Option<PulseDevice> {
+            match list(direction) {
+                Ok(devices) => devices
+                    .into_iter()
+                    .find(|(i, _)| *i == index)
+                    .map(|(_, device)| device),
+                Err(error) => {
+                    warn!("failed to list the {} devices: {}", direction, error);
+                    None
+                }
+            }
+        }
+    }
+
+    impl DeviceWatcher for PulseWatcher {
+        fn next_event(&mut self) -> Option<DeviceEvent> {
+            loop {
+                if let Some(event) = self.pending.pop_front() {
+                    return Some(event);
+                }
+
+                let line = self.lines.next()?.ok()?;
+                let Some((action, facility, index)) = parse_event(&line) else {
+                    continue;
+                };
+                let direction = match facility {
+                    "sink" => Direction::Output,
+                    "source" => Direction::Input,
+                    "server" => {
+                        self.server_changed();
+                        continue;
+                    }
+                    _ => continue,
+                };
+
+                match action {
+                    "new" => self.added(direction, index),
+                    "change" => self.changed(direction, index),
+                    "remove" => self.removed(direction, index),
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    impl Drop for PulseWatcher {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    /// a pactl command with untranslated output
+    fn pactl(args: &[&str]) -> Command {
+        let mut command = Command::new("pactl");
+        command.args(args).env("LC_ALL", "C");
+        command
+    }
+
+    /// runs a pactl command to completion and returns its output
+    fn pactl_output(args: &[&str]) -> Result<String> {
+        let output = pactl(args).stderr(Stdio::null()).output()?;
+        if !output.status.success() {
+            return Err(ErrorKind::DeviceWatcher("pactl failed").into());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// every sink or source by index, without the monitor sources
+    fn list(direction: Direction) -> Result<Vec<(u32, PulseDevice)>> {
+        let kind = match direction {
+            Direction::Input => "sources",
+            Direction::Output => "sinks",
+        };
+        Ok(parse_list(&pactl_output(&["list", kind])?))
+    }
+
+    /// reads the output of `pactl list sinks` or `pactl list sources`
+    ///
+    /// the alsa id is built like the alsa host builds the ids of the hardware devices, from
+    /// the `alsa.card` and `alsa.device` properties
+    pub(super) fn parse_list(listing: &str) -> Vec<(u32, PulseDevice)> {
+        let mut devices = Vec::new();
+        // entries are separated by a blank line and start with a line such as `Sink #56`
+        for entry in listing.split("\n\n") {
+            let mut lines = entry.lines().map(str::trim).filter(|line| !line.is_empty());
+            let Some(index) = lines
+                .next()
+                .and_then(|header| header.split_once(" #"))
+                .and_then(|(_, index)| index.parse().ok())
+            else {
+                continue;
+            };
+            let lines: Vec<&str> = lines.collect();
+            let field = |label: &str| {
+                lines
+                    .iter()
+                    .find_map(|line| line.strip_prefix(label))
+                    .map(|value| value.trim().trim_matches('"'))
+            };
+
+            let Some(name) = field("Name:") else {
+                continue;
+            };
+            if name.ends_with(".monitor") {
+                continue;
+            }
+            let id = field("alsa.card = ")
+                .zip(field("alsa.device = "))
+                .map(|(card, device)| {
+                    cpal::DeviceId(
+                        cpal::HostId::Alsa,
+                        format!("plughw:CARD={card},DEV={device}"),
+                    )
+                    .to_string()
+                });
+            // the port lines read `name: description (..., not available)`
+            let active = field("Active Port:").is_none_or(|port| {
+                !lines.iter().any(|line| {
+                    line.strip_prefix(port).is_some_and(|rest| {
+                        rest.starts_with(": ") && rest.ends_with("not available)")
+                    })
+                })
+            });
+
+            devices.push((
+                index,
+                PulseDevice {
+                    name: name.to_string(),
+                    id,
+                    active,
+                },
+            ));
+        }
+        devices
+    }
+
+    /// the names of the default source and sink
+    fn defaults() -> Result<(Option<String>, Option<String>)> {
+        let info = pactl_output(&["info"])?;
+        let field = |label: &str| {
+            info.lines()
+                .find_map(|line| line.strip_prefix(label))
+                .map(|name| name.trim().to_string())
+        };
+        Ok((field("Default Source:"), field("Default Sink:")))
+    }
+
+    /// splits a line such as `Event 'new' on sink #56` into the action, facility and index
+    pub(super) fn parse_event(line: &str) -> Option<(&str, &str, u32)> {
+        let (action, rest) = line.strip_prefix("Event '")?.split_once("' on ")?;
+        let (facility, index) = rest.split_once(" #")?;
+        Some((action, facility, index.trim().parse().ok()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_mock_watcher_reports_its_events_in_order() {
+        let events = [
+            DeviceEvent::Added {
+                id: "alsa:a".to_string(),
+                direction: Direction::Output,
+            },
+            DeviceEvent::DefaultChanged {
+                id: None,
+                direction: Direction::Input,
+            },
+        ];
+        let mut watcher = MockWatcher::new(events.clone());
+        assert_eq!(watcher.next_event(), Some(events[0].clone()));
+        assert_eq!(watcher.next_event(), Some(events[1].clone()));
+        assert_eq!(watcher.next_event(), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pulse_events_are_parsed() {
+        assert_eq!(
+            linux::parse_event("Event 'change' on sink #56"),
+            Some(("change", "sink", 56))
+        );
+        assert_eq!(
+            linux::parse_event("Event 'new' on source-output #3"),
+            Some(("new", "source-output", 3))
+        );
+        assert_eq!(linux::parse_event("Event 'change' on server"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pulse_devices_map_to_alsa_ids() {
+        let listing = "Source #1
+\tState: SUSPENDED
+\tName: alsa_output.pci-0000_00_1f.3.analog-stereo.monitor
+\tProperties:
+\t\talsa.card = \"0\"
+\t\talsa.device = \"0\"
+
+Source #2
+\tState: RUNNING
+\tName: alsa_input.usb-Microphone-00.mono-fallback
+\tProperties:
+\t\talsa.card = \"2\"
+\t\talsa.device = \"0\"
+\tPorts:
+\t\tanalog-input-mic: Microphone (type: Mic, priority: 8700, availability unknown)
+\tActive Port: analog-input-mic
+
+Source #3
+\tState: SUSPENDED
+\tName: alsa_input.pci-0000_00_1f.3.analog-stereo
+\tProperties:
+\t\talsa.card = \"0\"
+\t\talsa.device = \"0\"
+\tPorts:
+\t\tanalog-input-internal-mic: Internal Microphone (type: Mic, priority: 8900, available)
+\t\tanalog-input-headset-mic: Headset Microphone (type: Headset, priority: 8800, availability group: Legacy 2, not available)
+\tActive Port: analog-input-headset-mic
+
+Source #4
+\tState: SUSPENDED
+\tName: bluez_input.00_11_22_33_44_55
+\tProperties:
+\t\tdevice.api = \"bluez5\"
+";
+        let devices = linux::parse_list(listing);
+        let summary: Vec<_> = devices
+            .iter()
+            .map(|(index, device)| (*index, device.id.as_deref(), device.active))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (2, Some("alsa:plughw:CARD=2,DEV=0"), true),
+                (3, Some("alsa:plughw:CARD=0,DEV=0"), false),
+                (4, None, true),
+            ]
+        );
+        assert_eq!(
+            devices[0].1.name,
+            "alsa_input.usb-Microphone-00.mono-fallback"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use crate::BLOCK_SIZE;
+
+/// the blocks to wait before locking the target fill level, about two seconds
+const WARMUP_BLOCKS: usize = 200;
+/// the smoothing applied to the measured fill level per block
+const SMOOTHING: f64 = 0.02;
+/// the proportional gain per block of fill error
+const PROPORTIONAL: f64 = 2e-3;
+/// the integral gain per block of fill error
+const INTEGRAL: f64 = 2e-6;
+/// how far the target rises after an underrun
+const HEADROOM_STEP: f64 = (BLOCK_SIZE / 2) as f64;
+/// the largest correction ever applied to the ratio, in parts per unit
+const MAX_CORRECTION: f64 = 2e-3;
+
+/// compensates for clock drift between independent input and output devices
+///
+/// the output ring buffer slowly fills or drains when the device clocks disagree. this
+/// controller locks the fill level reached after warming up and nudges the resampling
+/// ratio with a PI loop so the fill level, and therefore the latency, stays constant.
+/// when the device callbacks beat against each other and the output runs dry, the target
+/// is raised so the next beat is absorbed instead of underrunning
+pub struct DriftController {
+    /// the ratio implied by the nominal sample rates
+    nominal: f64,
+    /// the smoothed fill level in frames
+    average: f64,
+    /// the fill level the controller holds, locked after warming up
+    target: Option<f64>,
+    /// the accumulated fill error in blocks
+    integral: f64,
+    /// the output underrun count last seen
+    underruns: usize,
+    blocks: usize,
+}
+
+impl DriftController {
+    pub fn new(nominal: f64) -> Self {
+        DriftController {
+            nominal,
+            average: 0_f64,
+            target: None,
+            integral: 0_f64,
+            underruns: 0,
+            blocks: 0,
+        }
+    }
+
+    /// records the output fill level and underrun count after writing a block and returns
+    /// the ratio to resample at
+    pub fn update(&mut self, fill: usize, underruns: usize, capacity: usize) -> f64 {
+        self.average += SMOOTHING * (fill as f64 - self.average);
+        self.blocks += 1;
+
+        let Some(mut target) = self.target else {
+            // underruns while the buffers first fill up are expected
+            if self.blocks >= WARMUP_BLOCKS {
+                self.target = Some(self.average);
+                self.underruns = underruns;
+            }
+            return self.nominal;
+        };
+
+        if underruns != self.underruns {
+            // never aim above half the buffer so bursts cannot overflow it
+            target = (target + HEADROOM_STEP).min(capacity as f64 / 2_f64);
+            self.target = Some(target);
+            self.underruns = underruns;
+        }
+
+        // a fuller buffer means the output is slower, so consume more input per output frame
+        let error = (self.average - target) / BLOCK_SIZE as f64;
+        self.integral =
+            (self.integral + error).clamp(-MAX_CORRECTION / INTEGRAL, MAX_CORRECTION / INTEGRAL);
+
+        let correction = (PROPORTIONAL * error + INTEGRAL * self.integral)
+            .clamp(-MAX_CORRECTION, MAX_CORRECTION);
+        self.nominal * (1_f64 + correction)
+    }
+
+    /// the clock offset the controller settled on in parts per million, shown with the latency
+    pub fn correction_ppm(&self) -> f64 {
+        INTEGRAL * self.integral * 1e6
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the output ring in frames, as the processor sizes it for a block
+    const CAPACITY: usize = BLOCK_SIZE * 8;
+
+    /// runs the controller against an output clock offset from the input clock by ppm and
+    /// returns it with the fill level and every ratio it returned
+    fn simulate(ppm: f64, blocks: usize) -> (DriftController, f64, Vec<f64>) {
+        let mut controller = DriftController::new(1_f64);
+        let mut fill = (CAPACITY / 2) as f64;
+        let mut ratio = 1_f64;
+        let mut ratios = Vec::with_capacity(blocks);
+
+        for _ in 0..blocks {
+            // the processor writes a block resampled at the ratio, the device plays its share
+            fill += BLOCK_SIZE as f64 / ratio;
+            fill -= BLOCK_SIZE as f64 * (1_f64 + ppm * 1e-6);
+            ratio = controller.update(fill.round() as usize, 0, CAPACITY);
+            ratios.push(ratio);
+        }
+
+        (controller, fill, ratios)
+    }
+
+    #[test]
+    fn holds_the_fill_level_against_a_clock_offset() {
+        for ppm in [-100_f64, 100_f64] {
+            let (controller, fill, _) = simulate(ppm, 20_000);
+            let target = controller.target.unwrap();
+
+            assert!(
+                (fill - target).abs() < (BLOCK_SIZE / 8) as f64,
+                "{ppm}: {fill} {target}"
+            );
+            // a faster output consumes more, so each output frame takes less input
+            assert!(
+                (controller.correction_ppm() + ppm).abs() < 5_f64,
+                "{ppm}: {}",
+                controller.correction_ppm()
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_the_correction_within_its_clamp() {
+        // far more offset than the controller may correct
+        let (controller, _, ratios) = simulate(10_000_f64, 5_000);
+        assert!(
+            ratios
+                .iter()
+                .all(|ratio| (ratio - 1_f64).abs() <= MAX_CORRECTION + f64::EPSILON)
+        );
+        assert!(controller.correction_ppm().abs() <= MAX_CORRECTION * 1e6 + 1e-6);
+    }
+
+    #[test]
+    fn raises_the_target_after_an_underrun() {
+        let mut controller = DriftController::new(1_f64);
+        for _ in 0..WARMUP_BLOCKS {
+            controller.update(BLOCK_SIZE, 3, CAPACITY);
+        }
+        let target = controller.target.unwrap();
+
+        controller.update(BLOCK_SIZE, 4, CAPACITY);
+        assert_eq!(controller.target, Some(target + HEADROOM_STEP));
+        // never above half the ring
+        for underruns in 5..100 {
+            controller.update(BLOCK_SIZE, underruns, CAPACITY);
+        }
+        assert_eq!(controller.target, Some((CAPACITY / 2) as f64));
+    }
+}
@@ -0,0 +1,460 @@
+use crate::Result;
+use crate::error::ErrorKind;
+use vst::plugin::{Info, PluginParameters};
+
+/// the magic at the start of every fxp and fxb file
+const CHUNK_MAGIC: &[u8; 4] = b"CcnK";
+/// a program stored as parameter values
+const PROGRAM_PARAMETERS: &[u8; 4] = b"FxCk";
+/// a program stored as an opaque plugin chunk
+const PROGRAM_CHUNK: &[u8; 4] = b"FPCh";
+/// a bank stored as a list of parameter programs
+const BANK_PARAMETERS: &[u8; 4] = b"FxBk";
+/// a bank stored as an opaque plugin chunk
+const BANK_CHUNK: &[u8; 4] = b"FBCh";
+/// the fixed length of a program name
+const NAME_LENGTH: usize = 28;
+/// the reserved bytes after the bank header
+const BANK_RESERVED: usize = 124;
+
+/// serializes the current program as an fxp file
+///
+/// plugins with chunk support are saved as an opaque chunk, others as parameter values
+pub(crate) fn program_bytes(info: &Info, parameters: &dyn PluginParameters) -> Vec<u8> {
+    let magic = if info.preset_chunks {
+        PROGRAM_CHUNK
+    } else {
+        PROGRAM_PARAMETERS
+    };
+    let mut bytes = header(magic, 1, info, info.parameters);
+    write_program_body(&mut bytes, info, parameters);
+    finish(bytes)
+}
+
+/// serializes every program as an fxb file
+///
+/// parameter banks are read by switching through the programs and restoring the current one
+pub(crate) fn bank_bytes(info: &Info, parameters: &dyn PluginParameters) -> Vec<u8> {
+    let current = parameters.get_preset_num();
+    let magic = if info.preset_chunks {
+        BANK_CHUNK
+    } else {
+        BANK_PARAMETERS
+    };
+    let mut bytes = header(magic, 2, info, info.presets);
+    bytes.extend(current.to_be_bytes());
+    bytes.extend([0_u8; BANK_RESERVED]);
+
+    if info.preset_chunks {
+        write_chunk(&mut bytes, &parameters.get_bank_data());
+    } else {
+        for preset in 0..info.presets {
+            parameters.change_preset(preset);
+            bytes.extend(program_bytes(info, parameters));
+        }
+        parameters.change_preset(current);
+    }
+
+    finish(bytes)
+}
+
+/// the unique id of the plugin an fxp or fxb file belongs to
+pub(crate) fn plugin_id(bytes: &[u8]) -> Result<i32> {
+    let mut reader = Reader { bytes };
+    if reader.take(4)? != CHUNK_MAGIC {
+        Err(ErrorKind::InvalidFxFile("not an fxp or fxb file"))?;
+    }
+    // skip the size, magic and format version
+    reader.take(12)?;
+    reader.i32()
+}
+
+/// loads an fxp file into the current program
+pub(crate) fn load_program(
+    info: &Info,
+    parameters: &dyn PluginParameters,
+    bytes: &[u8],
+) -> Result<()> {
+    let mut reader = Reader { bytes };
+    let (magic, count) = reader.header(info)?;
+    reader.program_body(info, parameters, &magic, count)
+}
+
+/// loads an fxb file into the plugin's programs
+pub(crate) fn load_bank(
+    info: &Info,
+    parameters: &dyn PluginParameters,
+    bytes: &[u8],
+) -> Result<()> {
+    let mut reader = Reader { bytes };
+    let (magic, count) = reader.header(info)?;
+    let current = reader.i32()?;
+    reader.take(BANK_RESERVED)?;
+
+    match &magic {
+        BANK_CHUNK if info.preset_chunks => parameters.load_bank_data(reader.chunk()?),
+        BANK_PARAMETERS => {
+            for preset in 0..count.min(info.presets) {
+                parameters.change_preset(preset);
+                let (magic, count) = reader.header(info)?;
+                reader.program_body(info, parameters, &magic, count)?;
+            }
+            parameters.change_preset(current);
+        }
+        BANK_CHUNK => Err(ErrorKind::InvalidFxFile(
+            "the plugin does not support chunks",
+        ))?,
+        _ => Err(ErrorKind::InvalidFxFile("not a bank file"))?,
+    }
+
+    Ok(())
+}
+
+fn header(magic: &[u8; 4], version: i32, info: &Info, count: i32) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend(CHUNK_MAGIC);
+    // the size is filled in by finish
+    bytes.extend(0_i32.to_be_bytes());
+    bytes.extend(magic);
+    bytes.extend(version.to_be_bytes());
+    bytes.extend(info.unique_id.to_be_bytes());
+    bytes.extend(info.version.to_be_bytes());
+    bytes.extend(count.to_be_bytes());
+    bytes
+}
+
+fn write_program_body(bytes: &mut Vec<u8>, info: &Info, parameters: &dyn PluginParameters) {
+    let mut name = [0_u8; NAME_LENGTH];
+    let preset_name = parameters.get_preset_name(parameters.get_preset_num());
+    // leave room for the terminator
+    for (byte, source) in name[..NAME_LENGTH - 1].iter_mut().zip(preset_name.bytes()) {
+        *byte = source;
+    }
+    bytes.extend(name);
+
+    if info.preset_chunks {
+        write_chunk(bytes, &parameters.get_preset_data());
+    } else {
+        for index in 0..info.parameters {
+            bytes.extend(parameters.get_parameter(index).to_be_bytes());
+        }
+    }
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, chunk: &[u8]) {
+    bytes.extend((chunk.len() as i32).to_be_bytes());
+    bytes.extend(chunk);
+}
+
+/// writes the byte size after the size field
+fn finish(mut bytes: Vec<u8>) -> Vec<u8> {
+    let size = (bytes.len() - 8) as i32;
+    bytes[4..8].copy_from_slice(&size.to_be_bytes());
+    bytes
+}
+
+/// reads big endian fxp and fxb fields
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < length {
+            Err(ErrorKind::InvalidFxFile("the file is truncated"))?;
+        }
+
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn chunk(&mut self) -> Result<&'a [u8]> {
+        let length = self.i32()?.max(0) as usize;
+        self.take(length)
+    }
+
+    /// reads the common header, returning the file magic and the parameter or program count
+    fn header(&mut self, info: &Info) -> Result<([u8; 4], i32)> {
+        if self.take(4)? != CHUNK_MAGIC {
+            Err(ErrorKind::InvalidFxFile("not an fxp or fxb file"))?;
+        }
+        let _size = self.i32()?;
+        let magic: [u8; 4] = self.take(4)?.try_into().unwrap();
+        let _version = self.i32()?;
+
+        if self.i32()? != info.unique_id {
+            Err(ErrorKind::InvalidFxFile(
+                "the file belongs to a different plugin",
+            ))?;
+        }
+
+        let _plugin_version = self.i32()?;
+        let count = self.i32()?;
+        Ok((magic, count))
+    }
+
+    fn program_body(
+        &mut self,
+        info: &Info,
+        parameters: &dyn PluginParameters,
+        magic: &[u8; 4],
+        count: i32,
+    ) -> Result<()> {
+        let name = self.take(NAME_LENGTH)?;
+        let name = String::from_utf8_lossy(name.split(|byte| *byte == 0).next().unwrap_or(name));
+
+        match magic {
+            PROGRAM_CHUNK if info.preset_chunks => parameters.load_preset_data(self.chunk()?),
+            PROGRAM_PARAMETERS => {
+                for index in 0..count {
+                    let value = self.f32()?;
+                    if index < info.parameters {
+                        parameters.set_parameter(index, value);
+                    }
+                }
+            }
+            PROGRAM_CHUNK => Err(ErrorKind::InvalidFxFile(
+                "the plugin does not support chunks",
+            ))?,
+            _ => Err(ErrorKind::InvalidFxFile("not a program file"))?,
+        }
+
+        parameters.set_preset_name(name.into_owned());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::sync::atomic::AtomicI32;
+    use std::sync::atomic::Ordering::Relaxed;
+
+    /// a program's name, parameter values and chunk
+    type Program = (String, Vec<f32>, Vec<u8>);
+
+    /// a plugin's programs
+    #[derive(Default)]
+    struct Programs {
+        current: AtomicI32,
+        programs: Mutex<Vec<Program>>,
+        bank: Mutex<Vec<u8>>,
+    }
+
+    impl Programs {
+        fn new(programs: usize, parameters: usize) -> Self {
+            Programs {
+                programs: Mutex::new(vec![
+                    (String::new(), vec![0_f32; parameters], Vec::new());
+                    programs
+                ]),
+                ..Default::default()
+            }
+        }
+
+        /// the distinct contents of each program
+        fn filled(programs: usize, parameters: usize) -> Self {
+            let filled = Self::new(programs, parameters);
+            for (index, program) in filled.programs.lock().unwrap().iter_mut().enumerate() {
+                program.0 = format!("Program {index}");
+                program.1 = (0..parameters)
+                    .map(|parameter| (index * parameters + parameter) as f32 / 100_f32)
+                    .collect();
+                program.2 = vec![index as u8; index + 3];
+            }
+            *filled.bank.lock().unwrap() = b"the whole bank".to_vec();
+            filled
+        }
+
+        fn with_current<R>(&self, f: impl FnOnce(&mut Program) -> R) -> R {
+            f(&mut self.programs.lock().unwrap()[self.current.load(Relaxed) as usize])
+        }
+    }
+
+    impl PluginParameters for Programs {
+        fn change_preset(&self, preset: i32) {
+            self.current.store(preset, Relaxed);
+        }
+
+        fn get_preset_num(&self) -> i32 {
+            self.current.load(Relaxed)
+        }
+
+        fn set_preset_name(&self, name: String) {
+            self.with_current(|program| program.0 = name);
+        }
+
+        fn get_preset_name(&self, preset: i32) -> String {
+            self.programs.lock().unwrap()[preset as usize].0.clone()
+        }
+
+        fn get_parameter(&self, index: i32) -> f32 {
+            self.with_current(|program| program.1[index as usize])
+        }
+
+        fn set_parameter(&self, index: i32, value: f32) {
+            self.with_current(|program| program.1[index as usize] = value);
+        }
+
+        fn get_preset_data(&self) -> Vec<u8> {
+            self.with_current(|program| program.2.clone())
+        }
+
+        fn get_bank_data(&self) -> Vec<u8> {
+            self.bank.lock().unwrap().clone()
+        }
+
+        fn load_preset_data(&self, data: &[u8]) {
+            self.with_current(|program| program.2 = data.to_vec());
+        }
+
+        fn load_bank_data(&self, data: &[u8]) {
+            *self.bank.lock().unwrap() = data.to_vec();
+        }
+    }
+
+    fn info(chunks: bool) -> Info {
+        Info {
+            unique_id: 0x5465_7374,
+            version: 3,
+            presets: 3,
+            parameters: 4,
+            preset_chunks: chunks,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn writes_big_endian_headers() {
+        let (info, programs) = (info(false), Programs::filled(3, 4));
+        let bytes = program_bytes(&info, &programs);
+
+        assert_eq!(&bytes[0..4], CHUNK_MAGIC);
+        assert_eq!(bytes[4..8], (bytes.len() as i32 - 8).to_be_bytes());
+        assert_eq!(&bytes[8..12], PROGRAM_PARAMETERS);
+        assert_eq!(bytes[16..20], 0x5465_7374_i32.to_be_bytes());
+        assert_eq!(bytes[24..28], 4_i32.to_be_bytes());
+        assert_eq!(bytes.len(), 28 + NAME_LENGTH + 4 * 4);
+        assert_eq!(plugin_id(&bytes).unwrap(), info.unique_id);
+    }
+
+    #[test]
+    fn programs_round_trip() {
+        for chunks in [false, true] {
+            let info = info(chunks);
+            let source = Programs::filled(3, 4);
+            source.change_preset(2);
+            let bytes = program_bytes(&info, &source);
+
+            let target = Programs::new(3, 4);
+            load_program(&info, &target, &bytes).unwrap();
+            let (name, values, chunk) = source.programs.lock().unwrap()[2].clone();
+            let loaded = target.programs.lock().unwrap()[0].clone();
+            assert_eq!(loaded.0, name);
+            if chunks {
+                assert_eq!(loaded.2, chunk);
+            } else {
+                assert_eq!(loaded.1, values);
+            }
+        }
+    }
+
+    #[test]
+    fn banks_round_trip() {
+        // a parameter bank holds every program
+        let info = info(false);
+        let source = Programs::filled(3, 4);
+        source.change_preset(1);
+        let bytes = bank_bytes(&info, &source);
+        assert_eq!(source.get_preset_num(), 1);
+
+        let target = Programs::new(3, 4);
+        load_bank(&info, &target, &bytes).unwrap();
+        assert_eq!(target.get_preset_num(), 1);
+        let names_and_values = |programs: &Programs| {
+            programs
+                .programs
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(name, values, _)| (name.clone(), values.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names_and_values(&target), names_and_values(&source));
+
+        // a chunk bank is the plugin's own bank data
+        let info = self::info(true);
+        let bytes = bank_bytes(&info, &source);
+        let target = Programs::new(3, 4);
+        load_bank(&info, &target, &bytes).unwrap();
+        assert_eq!(*target.bank.lock().unwrap(), b"the whole bank");
+    }
+
+    #[test]
+    fn rejects_files_that_do_not_fit() {
+        let (info, programs) = (info(false), Programs::filled(3, 4));
+        let program = program_bytes(&info, &programs);
+        let bank = bank_bytes(&info, &programs);
+        let target = Programs::new(3, 4);
+        let error = |result: Result<()>| match result.unwrap_err().kind {
+            ErrorKind::InvalidFxFile(reason) => reason,
+            kind => panic!("unexpected error {kind:?}"),
+        };
+
+        let mut wrong_magic = program.clone();
+        wrong_magic[0..4].copy_from_slice(b"RIFF");
+        assert_eq!(
+            error(load_program(&info, &target, &wrong_magic)),
+            "not an fxp or fxb file"
+        );
+        assert!(plugin_id(&wrong_magic).is_err());
+
+        let other = Info {
+            unique_id: 1,
+            ..info.clone()
+        };
+        assert_eq!(
+            error(load_program(&other, &target, &program)),
+            "the file belongs to a different plugin"
+        );
+        assert_eq!(
+            error(load_bank(&other, &target, &bank)),
+            "the file belongs to a different plugin"
+        );
+
+        for length in [0, 3, 20, program.len() - 1] {
+            assert_eq!(
+                error(load_program(&info, &target, &program[..length])),
+                "the file is truncated"
+            );
+        }
+        assert_eq!(
+            error(load_bank(&info, &target, &bank[..bank.len() - 1])),
+            "the file is truncated"
+        );
+
+        // programs and banks are not mixed up, chunks need a plugin that supports them
+        assert_eq!(
+            error(load_program(&info, &target, &bank)),
+            "not a program file"
+        );
+        let mut padded = program.clone();
+        padded.resize(bank.len(), 0);
+        assert_eq!(error(load_bank(&info, &target, &padded)), "not a bank file");
+        let chunk = program_bytes(&self::info(true), &programs);
+        assert_eq!(
+            error(load_program(&info, &target, &chunk)),
+            "the plugin does not support chunks"
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use crate::BLOCK_SIZE;
+use crate::config::AtomicConfig;
+use crate::transport::Transport;
+use log::{debug, warn};
+use std::cell::Cell;
+use std::sync::Arc;
+use vst::api::{Events, TimeInfo, TimeInfoFlags};
+use vst::host::Host;
+
+/// the time info fields the transport cannot provide
+const UNSUPPORTED_TIME_INFO: TimeInfoFlags = TimeInfoFlags::CYCLE_POS_VALID
+    .union(TimeInfoFlags::SMPTE_VALID)
+    .union(TimeInfoFlags::VST_CLOCK_VALID);
+
+/// the host for a plugin in the chain
+///
+/// the callback of [`crate::LoadedPlugin`] answers the version, sample rate and process level
+/// queries itself and logs the opcodes no host answers once at the warn level
+pub struct CompressorHost {
+    config: Arc<AtomicConfig>,
+    /// the slot of the plugin in the chain
+    slot: u32,
+    transport: Arc<Transport>,
+    /// the unsupported time info already logged, so the audio thread logs each once
+    reported_time_info: Cell<TimeInfoFlags>,
+    reported_events: Cell<bool>,
+}
+
+impl CompressorHost {
+    pub fn new(config: Arc<AtomicConfig>, slot: u32, transport: Arc<Transport>) -> Self {
+        Self {
+            config,
+            slot,
+            transport,
+            reported_time_info: Cell::new(TimeInfoFlags::empty()),
+            reported_events: Cell::new(false),
+        }
+    }
+}
+
+impl Host for CompressorHost {
+    /// callback for parameter changes
+    fn automate(&self, index: i32, value: f32) {
+        self.config.set_parameter(self.slot, index as usize, value);
+    }
+
+    /// callback for the start of a gesture in the editor
+    fn begin_edit(&self, index: i32) {
+        debug!("slot {} began editing parameter {index}", self.slot);
+    }
+
+    /// callback for the end of a gesture in the editor
+    fn end_edit(&self, index: i32) {
+        debug!("slot {} ended editing parameter {index}", self.slot);
+    }
+
+    /// the editors are idled by the message loop of the frontend
+    fn idle(&self) {}
+
+    /// the host version, vendor and product
+    fn get_info(&self) -> (isize, String, String) {
+        let version = [
+            env!("CARGO_PKG_VERSION_MAJOR"),
+            env!("CARGO_PKG_VERSION_MINOR"),
+            env!("CARGO_PKG_VERSION_PATCH"),
+        ]
+        .map(|part| part.parse::<isize>().unwrap_or_default());
+
+        (
+            version[0] * 1000 + version[1] * 100 + version[2],
+            "WhisperWare".to_string(),
+            "WhisperWare".to_string(),
+        )
+    }
+
+    /// events sent by the plugin, such as midi output, which nothing consumes
+    fn process_events(&self, events: &Events) {
+        if !self.reported_events.replace(true) {
+            warn!(
+                "slot {} sent {} events, the host does not support plugin events",
+                self.slot, events.num_events
+            );
+        }
+    }
+
+    /// the position and tempo of the transport at the start of the current block
+    fn get_time_info(&self, mask: i32) -> Option<TimeInfo> {
+        let mask = TimeInfoFlags::from_bits_truncate(mask);
+
+        let unsupported = mask & UNSUPPORTED_TIME_INFO & !self.reported_time_info.get();
+        if !unsupported.is_empty() {
+            warn!(
+                "slot {} asked for unsupported time info: {unsupported:?}",
+                self.slot
+            );
+            self.reported_time_info
+                .set(self.reported_time_info.get() | unsupported);
+        }
+
+        Some(self.transport.time_info(mask))
+    }
+
+    fn get_block_size(&self) -> isize {
+        BLOCK_SIZE as isize
+    }
+
+    /// callback for changes to state that is not exposed as parameters, such as a loaded program
+    fn update_display(&self) {
+        self.config.plugin_state_changed();
+    }
+}
@@ -0,0 +1,61 @@
+//! Platform-agnostic audio engine for WhisperWare.
+//!
+//! Holds the ring-buffer pipeline, the block processor, the persisted config and the
+//! shared error type. The built-in [`Compressor`] stands in for the
+//! Rough Rider 3 VST when it is not installed. Frontends such as the Windows tray app own
+//! the UI and the plugin editor, and drive the engine through [`BackendControl`].
+
+pub use crate::backend::{
+    Latency, StreamFormat, StreamRings, StreamSignals, device_by_id, processor,
+};
+pub use crate::chain::{PluginChain, SidechainSource};
+pub use crate::compressor::Compressor;
+pub use crate::config::{AtomicConfig, config_saver, config_watcher};
+pub use crate::control::{BackendControl, BackendState};
+#[cfg(target_os = "linux")]
+pub use crate::devices::PulseWatcher;
+pub use crate::devices::{DeviceEvent, DeviceWatcher, Direction, MockWatcher};
+pub use crate::drift::DriftController;
+pub use crate::error::{Error, ErrorKind};
+pub use crate::host::CompressorHost;
+pub use crate::limiter::Limiter;
+pub use crate::loader::LoadedPlugin;
+pub use crate::offline::process_file;
+pub use crate::parameters::ParameterValues;
+pub use crate::presets::{Preset, PresetStore};
+pub use crate::resampler::{Resampler, ResamplerQuality};
+pub use crate::routing::{Routing, RoutingMatrix};
+pub use crate::sandbox::{SANDBOX_ARGUMENT, SandboxHost, SandboxedPlugin, serve_sandbox};
+pub use crate::scanner::{PluginInfo, PluginScanner, find_plugin, search_paths};
+pub use crate::transport::Transport;
+pub use crate::watchdog::{Heartbeats, Stage, Stall, Watchdog};
+
+mod backend;
+mod chain;
+mod compressor;
+mod config;
+mod control;
+mod devices;
+mod drift;
+mod error;
+mod fx;
+mod host;
+mod limiter;
+mod loader;
+mod offline;
+mod parameters;
+mod presets;
+mod resampler;
+mod routing;
+mod sandbox;
+mod scanner;
+mod signal;
+#[cfg(test)]
+mod testing;
+mod transport;
+mod watchdog;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// the size of the audio frames used for processing
+pub const BLOCK_SIZE: usize = 512;
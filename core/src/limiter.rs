@@ -0,0 +1,254 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+/// the lookahead time in milliseconds
+const LOOKAHEAD_MS: f32 = 1.5;
+/// the release time in milliseconds
+const RELEASE_MS: f32 = 50_f32;
+/// the oversampling factor used to estimate inter-sample peaks
+const OVERSAMPLING: usize = 4;
+/// the number of taps per interpolation phase
+const TAPS: usize = 8;
+/// the position of the analysed sample in the interpolation history
+const CENTER: usize = TAPS / 2 - 1;
+
+/// a brickwall lookahead limiter with true-peak detection, linked across all channels
+///
+/// the gain curve is the minimum required gain held over the lookahead window and then
+/// averaged over the same window, so it reaches the required gain before the peak leaves
+/// the delay line. a final clamp guarantees the ceiling is never exceeded
+pub struct Limiter {
+    /// the lookahead length in samples
+    lookahead: usize,
+    /// interpolation history per channel, the newest sample is last
+    history: Vec<[f32; TAPS]>,
+    /// interpolation coefficients for the fractional phases
+    phases: [[f32; TAPS]; OVERSAMPLING - 1],
+    /// the delayed interleaved audio waiting for its gain
+    delay: VecDeque<f32>,
+    /// the required gains in the hold window as (index, gain) with ascending gains
+    hold: VecDeque<(usize, f32)>,
+    /// the held gains in the averaging window
+    average: VecDeque<f32>,
+    /// the running sum of the averaging window
+    sum: f32,
+    /// the smoothed output gain
+    envelope: f32,
+    release_coeff: f32,
+    /// the index of the next required gain
+    index: usize,
+}
+
+impl Limiter {
+    pub fn new(sample_rate: f32, channels: usize) -> Self {
+        let lookahead = ((LOOKAHEAD_MS * 0.001 * sample_rate) as usize).max(1);
+
+        Limiter {
+            lookahead,
+            history: vec![[0_f32; TAPS]; channels],
+            phases: interpolation_phases(),
+            delay: VecDeque::from(vec![0_f32; (lookahead - 1) * channels]),
+            hold: VecDeque::with_capacity(lookahead),
+            average: VecDeque::from(vec![1_f32; lookahead]),
+            sum: lookahead as f32,
+            envelope: 1_f32,
+            release_coeff: (-1_f32 / (RELEASE_MS * 0.001 * sample_rate)).exp(),
+            index: 0,
+        }
+    }
+
+    /// the added latency in samples
+    pub fn latency(&self) -> usize {
+        self.lookahead - 1 + (TAPS - 1 - CENTER)
+    }
+
+    /// limits interleaved samples in place so no sample or inter-sample peak exceeds the ceiling
+    pub fn process(&mut self, samples: &mut [f32], ceiling_db: f32) {
+        let ceiling = 10_f32.powf(ceiling_db.min(0_f32) / 20_f32);
+        let channels = self.history.len().max(1);
+
+        for frame in samples.chunks_exact_mut(channels) {
+            let mut peak = 0_f32;
+
+            for (history, sample) in self.history.iter_mut().zip(frame.iter()) {
+                history.copy_within(1.., 0);
+                history[TAPS - 1] = finite(*sample);
+                peak = peak.max(true_peak(history, &self.phases));
+            }
+
+            let required = if peak > ceiling {
+                ceiling / peak
+            } else {
+                1_f32
+            };
+            let gain = self.next_gain(required);
+
+            for (history, sample) in self.history.iter().zip(frame.iter_mut()) {
+                self.delay.push_back(history[CENTER]);
+                let delayed = self.delay.pop_front().unwrap_or_default();
+                *sample = finite(delayed * gain).clamp(-ceiling, ceiling);
+            }
+        }
+    }
+
+    /// pushes a required gain and returns the gain for the sample leaving the delay line
+    fn next_gain(&mut self, required: f32) -> f32 {
+        // sliding window minimum over the lookahead
+        while self.hold.back().is_some_and(|&(_, gain)| gain >= required) {
+            self.hold.pop_back();
+        }
+        self.hold.push_back((self.index, required));
+        while self
+            .hold
+            .front()
+            .is_some_and(|&(index, _)| index + self.lookahead <= self.index)
+        {
+            self.hold.pop_front();
+        }
+        self.index += 1;
+        let held = self.hold.front().map(|&(_, gain)| gain).unwrap_or(1_f32);
+
+        // box average of the held gain, which ramps in over the lookahead
+        self.average.push_back(held);
+        self.sum += held - self.average.pop_front().unwrap_or(1_f32);
+        if self.index.is_multiple_of(self.lookahead) {
+            // resum periodically so rounding errors cannot accumulate
+            self.sum = self.average.iter().sum();
+        }
+        let smoothed = self.sum / self.lookahead as f32;
+
+        // instant attack, exponential release
+        self.envelope = if smoothed < self.envelope {
+            smoothed
+        } else {
+            smoothed + self.release_coeff * (self.envelope - smoothed)
+        };
+        self.envelope
+    }
+}
+
+/// replaces nan and infinity with silence, which would otherwise pass the clamp or poison the gain
+fn finite(sample: f32) -> f32 {
+    if sample.is_finite() { sample } else { 0_f32 }
+}
+
+/// the largest absolute value of the center sample and the interpolated points after it
+fn true_peak(history: &[f32; TAPS], phases: &[[f32; TAPS]; OVERSAMPLING - 1]) -> f32 {
+    phases
+        .iter()
+        .map(|phase| {
+            phase
+                .iter()
+                .zip(history)
+                .map(|(c, x)| c * x)
+                .sum::<f32>()
+                .abs()
+        })
+        .fold(history[CENTER].abs(), f32::max)
+}
+
+/// hann windowed sinc coefficients for each fractional position after the center sample
+fn interpolation_phases() -> [[f32; TAPS]; OVERSAMPLING - 1] {
+    let mut phases = [[0_f32; TAPS]; OVERSAMPLING - 1];
+    let half_width = (TAPS / 2) as f32;
+
+    for (p, phase) in phases.iter_mut().enumerate() {
+        let fraction = (p + 1) as f32 / OVERSAMPLING as f32;
+
+        for (k, coeff) in phase.iter_mut().enumerate() {
+            let x = CENTER as f32 + fraction - k as f32;
+            let sinc = (PI * x).sin() / (PI * x);
+            let window = 0.5 * (1_f32 + (PI * x / half_width).cos());
+            *coeff = sinc * window;
+        }
+
+        let sum: f32 = phase.iter().sum();
+        phase.iter_mut().for_each(|coeff| *coeff /= sum);
+    }
+
+    phases
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48_000_f32;
+    const CEILING_DB: f32 = -1_f32;
+
+    /// runs the signal through a stereo limiter in blocks and checks every output sample
+    fn assert_below_ceiling(signal: &[f32]) -> Vec<f32> {
+        let ceiling = 10_f32.powf(CEILING_DB / 20_f32);
+        let mut limiter = Limiter::new(SAMPLE_RATE, 2);
+        let mut output = Vec::with_capacity(signal.len());
+
+        for block in signal.chunks(512) {
+            let mut frames: Vec<f32> = block.iter().flat_map(|x| [*x, -x]).collect();
+            limiter.process(&mut frames, CEILING_DB);
+            for sample in &frames {
+                assert!(sample.abs() <= ceiling, "{sample} exceeds {ceiling}");
+            }
+            output.extend(frames.iter().step_by(2));
+        }
+        output
+    }
+
+    #[test]
+    fn impulses_stay_below_the_ceiling() {
+        let mut signal = vec![0_f32; 4_800];
+        for i in (100..4_800).step_by(997) {
+            signal[i] = 8_f32;
+        }
+        let output = assert_below_ceiling(&signal);
+        assert!(output.iter().any(|x| x.abs() > 0.5));
+    }
+
+    #[test]
+    fn full_scale_square_waves_stay_below_the_ceiling() {
+        for period in [2, 7, 48, 480] {
+            let signal: Vec<f32> = (0..9_600)
+                .map(|i| if (i / period) % 2 == 0 { 1_f32 } else { -1_f32 })
+                .collect();
+            assert_below_ceiling(&signal);
+        }
+    }
+
+    #[test]
+    fn inter_sample_peaks_stay_below_the_ceiling() {
+        // a sine at a quarter of the sample rate, sampled 45 degrees off its peaks
+        let signal: Vec<f32> = (0..9_600)
+            .map(|i| (PI / 2_f32 * i as f32 + PI / 4_f32).sin() * 2_f32.sqrt())
+            .collect();
+        assert!(signal.iter().all(|x| x.abs() <= 1.001));
+        assert_below_ceiling(&signal);
+
+        // alternating pairs such as 1, 1, -1, -1 peak between the samples
+        let signal: Vec<f32> = (0..9_600)
+            .map(|i| if (i / 2) % 2 == 0 { 1_f32 } else { -1_f32 })
+            .collect();
+        assert_below_ceiling(&signal);
+    }
+
+    #[test]
+    fn non_finite_samples_are_silenced() {
+        let mut signal: Vec<f32> = (0..4_800).map(|i| (i as f32 * 0.05).sin()).collect();
+        signal[1_000] = f32::NAN;
+        signal[2_000] = f32::INFINITY;
+        signal[3_000] = f32::NEG_INFINITY;
+        signal[3_001] = f32::MAX;
+        let output = assert_below_ceiling(&signal);
+        assert!(output.iter().all(|x| x.is_finite()));
+        // the limiter recovers after the bad samples
+        assert!(output[4_000..].iter().any(|x| x.abs() > 0.25));
+    }
+
+    #[test]
+    fn quiet_signals_pass_delayed_and_unchanged() {
+        let signal: Vec<f32> = (0..2_048).map(|i| (i as f32 * 0.01).sin() * 0.5).collect();
+        let latency = Limiter::new(SAMPLE_RATE, 2).latency();
+        let output = assert_below_ceiling(&signal);
+        for (output, input) in output[latency..].iter().zip(&signal) {
+            assert!((output - input).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,899 @@
+use libloading::Library;
+use log::warn;
+use std::cell::Cell;
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::mem::MaybeUninit;
+use std::path::Path;
+use std::ptr::{null, null_mut};
+use std::slice;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicBool, AtomicU32};
+use std::sync::{Arc, Mutex, PoisonError};
+use vst::api::consts::{
+    MAX_PARAM_STR_LEN, MAX_PRESET_NAME_LEN, MAX_PRODUCT_STR_LEN, MAX_VENDOR_STR_LEN,
+};
+use vst::api::{
+    AEffect, ChannelProperties, Events, HostCallbackProc, PluginFlags, Supported, TimeInfo,
+};
+use vst::buffer::AudioBuffer;
+use vst::channels::ChannelInfo;
+use vst::editor::{Editor, Rect};
+use vst::host::{Host, OpCode as HostOpCode, PluginLoadError};
+use vst::plugin::{CanDo, Category, HostCallback, Info, OpCode, Plugin, PluginParameters};
+
+/// the entry point every VST 2.4 library exports
+type PluginMain = unsafe extern "C" fn(callback: HostCallbackProc) -> *mut AEffect;
+
+/// the sample rate reported before the host sets one, the common VST default
+const DEFAULT_SAMPLE_RATE: f32 = 44_100_f32;
+/// `kVstProcessLevelUser`, a call from a thread other than the audio thread
+const PROCESS_LEVEL_USER: isize = 1;
+/// `kVstProcessLevelRealtime`, a call from within process
+const PROCESS_LEVEL_REALTIME: isize = 2;
+/// the host capabilities answered with yes
+const HOST_CAN_DO: &[&str] = &["sendVstTimeInfo"];
+/// the opcodes tracked for logging, higher ones share the last entry
+const REPORTED_OPCODES: usize = 64;
+
+thread_local! {
+    /// the host of the plugin whose entry point runs on this thread, before the effect exists
+    static LOADING: Cell<*const HostState> = const { Cell::new(null()) };
+    /// whether this thread is inside the process call of a plugin
+    static PROCESSING: Cell<bool> = const { Cell::new(false) };
+    /// the time info handed to the plugin, which must outlive the callback
+    static TIME_INFO: Cell<TimeInfo> = Cell::new(TimeInfo::default());
+}
+
+/// what the host callback of a plugin answers with, reached through the effect
+struct HostState {
+    host: Arc<Mutex<dyn Host + Send>>,
+    /// the file name of the plugin, for the log
+    name: String,
+    /// the bits of the sample rate last set on the plugin
+    sample_rate: AtomicU32,
+    /// the unhandled opcodes already logged, so the audio thread logs each once
+    reported: [AtomicBool; REPORTED_OPCODES],
+}
+
+impl HostState {
+    fn sample_rate(&self) -> f32 {
+        f32::from_bits(self.sample_rate.load(Relaxed))
+    }
+
+    /// logs an opcode the host does not answer, once per plugin
+    fn unhandled(&self, opcode: i32) {
+        let index = (opcode.max(0) as usize).min(REPORTED_OPCODES - 1);
+        if !self.reported[index].swap(true, Relaxed) {
+            match HostOpCode::try_from(opcode) {
+                Ok(opcode) => warn!(
+                    "{} sent the unsupported host opcode {:?}",
+                    self.name, opcode
+                ),
+                Err(_) => warn!("{} sent the unknown host opcode {}", self.name, opcode),
+            }
+        }
+    }
+}
+
+/// an external VST 2.4 plugin loaded from a library
+///
+/// replaces the loader of vst, whose host callback leaves the sample rate, process level and
+/// display update queries unanswered, so plugins saw a sample rate of 0
+pub struct LoadedPlugin {
+    params: Arc<EffectHandle>,
+    info: Info,
+    is_editor_active: bool,
+    /// the channel pointers handed to process, as many as the plugin reported
+    inputs: Vec<*const f32>,
+    outputs: Vec<*mut f32>,
+}
+
+// SAFETY: the effect is only dispatched to by the thread that owns the plugin, as with the
+// instances of vst, the channel pointers are only used within process
+unsafe impl Send for LoadedPlugin {}
+
+impl LoadedPlugin {
+    /// loads the library at path and creates an instance of its plugin
+    pub fn load<T: Host + Send + 'static>(
+        path: &Path,
+        host: Arc<Mutex<T>>,
+    ) -> Result<Self, PluginLoadError> {
+        // SAFETY: loading a library runs its initializers, which is what loading a plugin means
+        let library = unsafe { Library::new(path) }.map_err(|_| PluginLoadError::InvalidPath)?;
+        // SAFETY: VSTPluginMain has this signature in every VST 2.4 library
+        let main = unsafe { library.get::<PluginMain>(b"VSTPluginMain") }
+            .map(|main| *main)
+            .map_err(|_| PluginLoadError::NotAPlugin)?;
+
+        let state = Box::into_raw(Box::new(HostState {
+            host,
+            name: path.file_name().map_or_else(
+                || path.display().to_string(),
+                |name| name.to_string_lossy().into(),
+            ),
+            sample_rate: AtomicU32::new(DEFAULT_SAMPLE_RATE.to_bits()),
+            reported: [const { AtomicBool::new(false) }; REPORTED_OPCODES],
+        }));
+
+        // the plugin may call back before it returns its effect
+        LOADING.set(state);
+        // SAFETY: the callback matches the signature VST 2.4 expects
+        let effect = unsafe { main(audio_master) };
+        LOADING.set(null());
+
+        if effect.is_null() {
+            // SAFETY: no effect holds the state
+            drop(unsafe { Box::from_raw(state) });
+            return Err(PluginLoadError::InstanceFailed);
+        }
+        // SAFETY: the effect was just created and the reserved field belongs to the host
+        unsafe { (*effect).reserved1 = state as isize };
+
+        Self::from_effect(EffectHandle {
+            effect,
+            state,
+            _library: library,
+        })
+    }
+
+    /// wraps a created effect whose reserved field points at its host state
+    fn from_effect(handle: EffectHandle) -> Result<Self, PluginLoadError> {
+        let params = Arc::new(handle);
+        let info = params.info();
+        let plugin = LoadedPlugin {
+            inputs: Vec::with_capacity(info.inputs as usize),
+            outputs: Vec::with_capacity(info.outputs as usize),
+            info,
+            params,
+            is_editor_active: false,
+        };
+
+        // dropping the plugin here shuts the effect down with its only handle
+        if plugin.params.opcode(OpCode::GetApiVersion) < 2400 {
+            return Err(PluginLoadError::InvalidApiVersion);
+        }
+        Ok(plugin)
+    }
+}
+
+impl Plugin for LoadedPlugin {
+    fn get_info(&self) -> Info {
+        self.info.clone()
+    }
+
+    fn new(_host: HostCallback) -> Self {
+        unreachable!("plugins are created by LoadedPlugin::load")
+    }
+
+    fn init(&mut self) {
+        self.params.opcode(OpCode::Initialize);
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.params
+            .state()
+            .sample_rate
+            .store(rate.to_bits(), Relaxed);
+        self.params
+            .dispatch(OpCode::SetSampleRate, 0, 0, null_mut(), rate);
+    }
+
+    fn set_block_size(&mut self, size: i64) {
+        self.params
+            .dispatch(OpCode::SetBlockSize, 0, size as isize, null_mut(), 0_f32);
+    }
+
+    fn resume(&mut self) {
+        self.params
+            .dispatch(OpCode::StateChanged, 0, 1, null_mut(), 0_f32);
+    }
+
+    fn suspend(&mut self) {
+        self.params
+            .dispatch(OpCode::StateChanged, 0, 0, null_mut(), 0_f32);
+    }
+
+    fn vendor_specific(&mut self, index: i32, value: isize, ptr: *mut c_void, opt: f32) -> isize {
+        self.params
+            .dispatch(OpCode::VendorSpecific, index, value, ptr, opt)
+    }
+
+    fn can_do(&self, can_do: CanDo) -> Supported {
+        let can_do: String = can_do.into();
+        Supported::from(self.params.write_string(OpCode::CanDo, 0, &can_do))
+            .unwrap_or(Supported::Maybe)
+    }
+
+    fn get_tail_size(&self) -> isize {
+        self.params.opcode(OpCode::GetTailSize)
+    }
+
+    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        let samples = buffer.samples();
+        let (input_count, output_count) = (self.info.inputs as usize, self.info.outputs as usize);
+        let (inputs, mut outputs) = buffer.split();
+        if inputs.len() < input_count || outputs.len() < output_count {
+            return;
+        }
+        // the pointers fit the capacity reserved at load, so the audio thread never allocates
+        self.inputs.clear();
+        self.inputs
+            .extend((0..input_count).map(|i| inputs.get(i).as_ptr()));
+        self.outputs.clear();
+        self.outputs
+            .extend((0..output_count).map(|i| outputs.get_mut(i).as_mut_ptr()));
+
+        PROCESSING.set(true);
+        let effect = self.params.effect;
+        // SAFETY: every pointer covers samples frames of a channel the plugin reported
+        unsafe {
+            ((*effect).processReplacing)(
+                effect,
+                self.inputs.as_ptr(),
+                self.outputs.as_mut_ptr(),
+                samples as i32,
+            )
+        };
+        PROCESSING.set(false);
+    }
+
+    fn process_events(&mut self, events: &Events) {
+        self.params.dispatch(
+            OpCode::ProcessEvents,
+            0,
+            0,
+            events as *const _ as *mut _,
+            0_f32,
+        );
+    }
+
+    fn get_input_info(&self, input: i32) -> ChannelInfo {
+        self.params.channel_info(OpCode::GetInputInfo, input)
+    }
+
+    fn get_output_info(&self, output: i32) -> ChannelInfo {
+        self.params.channel_info(OpCode::GetOutputInfo, output)
+    }
+
+    fn get_parameter_object(&mut self) -> Arc<dyn PluginParameters> {
+        Arc::clone(&self.params) as Arc<dyn PluginParameters>
+    }
+
+    fn get_editor(&mut self) -> Option<Box<dyn Editor>> {
+        // the caller keeps using the editor it already has
+        if self.is_editor_active {
+            return None;
+        }
+        self.is_editor_active = true;
+        Some(Box::new(EffectEditor {
+            params: Arc::clone(&self.params),
+            is_open: false,
+        }))
+    }
+}
+
+/// the effect of a loaded plugin, shared by the plugin, its parameters and its editor
+///
+/// the effect is shut down and its host state freed when the last of them drops, so the
+/// config can keep reading the parameters of a plugin the chain already let go of
+struct EffectHandle {
+    effect: *mut AEffect,
+    /// the state the host callback reads through the reserved field of the effect
+    state: *const HostState,
+    /// keeps the code of the effect loaded, dropped after the effect shut down
+    _library: Library,
+}
+
+// SAFETY: the VST 2.4 parameter calls are made from the editor, automation and audio threads
+// by every host, plugins synchronize them. the effect and the host state are only freed when
+// the last clone of the handle drops, and the host state is only read through shared
+// references
+unsafe impl Send for EffectHandle {}
+unsafe impl Sync for EffectHandle {}
+
+impl Drop for EffectHandle {
+    fn drop(&mut self) {
+        self.opcode(OpCode::Shutdown);
+        // SAFETY: the effect shut down and no longer calls back, the state came from a box
+        drop(unsafe { Box::from_raw(self.state as *mut HostState) });
+    }
+}
+
+impl EffectHandle {
+    fn state(&self) -> &HostState {
+        // SAFETY: the state is freed only when the handle drops
+        unsafe { &*self.state }
+    }
+
+    fn dispatch(
+        &self,
+        opcode: OpCode,
+        index: i32,
+        value: isize,
+        ptr: *mut c_void,
+        opt: f32,
+    ) -> isize {
+        // SAFETY: the effect is valid until the handle drops
+        unsafe { ((*self.effect).dispatcher)(self.effect, opcode.into(), index, value, ptr, opt) }
+    }
+
+    fn opcode(&self, opcode: OpCode) -> isize {
+        self.dispatch(opcode, 0, 0, null_mut(), 0_f32)
+    }
+
+    /// sends a string through the pointer argument
+    fn write_string(&self, opcode: OpCode, index: i32, text: &str) -> isize {
+        let Ok(text) = CString::new(text) else {
+            return 0;
+        };
+        self.dispatch(opcode, index, 0, text.as_ptr() as *mut c_void, 0_f32)
+    }
+
+    /// reads a string the plugin writes into a buffer of max bytes
+    fn read_string(&self, opcode: OpCode, index: i32, max: usize) -> String {
+        // some plugins write past the documented lengths, so the buffer has room to spare
+        let mut buffer = vec![0_u8; max.max(256)];
+        self.dispatch(opcode, index, 0, buffer.as_mut_ptr() as *mut c_void, 0_f32);
+        let end = buffer
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(buffer.len());
+        String::from_utf8_lossy(&buffer[..end]).into_owned()
+    }
+
+    fn channel_info(&self, opcode: OpCode, index: i32) -> ChannelInfo {
+        let mut properties = MaybeUninit::<ChannelProperties>::zeroed();
+        self.dispatch(
+            opcode,
+            index,
+            0,
+            properties.as_mut_ptr() as *mut c_void,
+            0_f32,
+        );
+        // SAFETY: the properties are plain data and were zeroed when the plugin left them
+        ChannelInfo::from(unsafe { properties.assume_init() })
+    }
+
+    /// the metadata the effect reports when it is created
+    fn info(&self) -> Info {
+        // SAFETY: the effect is valid until the handle drops
+        let effect = unsafe { &*self.effect };
+        let flags = PluginFlags::from_bits_truncate(effect.flags);
+
+        Info {
+            name: self.read_string(OpCode::GetProductName, 0, MAX_PRODUCT_STR_LEN),
+            vendor: self.read_string(OpCode::GetVendorName, 0, MAX_VENDOR_STR_LEN),
+            presets: effect.numPrograms,
+            parameters: effect.numParams,
+            inputs: effect.numInputs.max(0),
+            outputs: effect.numOutputs.max(0),
+            midi_inputs: 0,
+            midi_outputs: 0,
+            unique_id: effect.uniqueId,
+            version: effect.version,
+            category: Category::try_from(self.opcode(OpCode::GetCategory))
+                .unwrap_or(Category::Unknown),
+            initial_delay: effect.initialDelay,
+            preset_chunks: flags.intersects(PluginFlags::PROGRAM_CHUNKS),
+            f64_precision: flags.intersects(PluginFlags::CAN_DOUBLE_REPLACING),
+            silent_when_stopped: flags.intersects(PluginFlags::NO_SOUND_IN_STOP),
+        }
+    }
+
+    /// reads a chunk, a preset when preset is set and the whole bank otherwise
+    fn get_chunk(&self, preset: bool) -> Vec<u8> {
+        let mut data: *mut u8 = null_mut();
+        let length = self.dispatch(
+            OpCode::GetData,
+            preset as i32,
+            0,
+            &mut data as *mut *mut u8 as *mut c_void,
+            0_f32,
+        );
+        if data.is_null() || length <= 0 {
+            return Vec::new();
+        }
+        // SAFETY: the plugin owns length bytes at data until the next call
+        unsafe { slice::from_raw_parts(data, length as usize) }.to_vec()
+    }
+
+    fn set_chunk(&self, preset: bool, data: &[u8]) {
+        self.dispatch(
+            OpCode::SetData,
+            preset as i32,
+            data.len() as isize,
+            data.as_ptr() as *mut c_void,
+            0_f32,
+        );
+    }
+}
+
+impl PluginParameters for EffectHandle {
+    fn change_preset(&self, preset: i32) {
+        self.dispatch(OpCode::ChangePreset, 0, preset as isize, null_mut(), 0_f32);
+    }
+
+    fn get_preset_num(&self) -> i32 {
+        self.opcode(OpCode::GetCurrentPresetNum) as i32
+    }
+
+    fn set_preset_name(&self, name: String) {
+        self.write_string(OpCode::SetCurrentPresetName, 0, &name);
+    }
+
+    fn get_preset_name(&self, preset: i32) -> String {
+        self.read_string(OpCode::GetPresetName, preset, MAX_PRESET_NAME_LEN)
+    }
+
+    fn get_parameter_label(&self, index: i32) -> String {
+        self.read_string(OpCode::GetParameterLabel, index, MAX_PARAM_STR_LEN)
+    }
+
+    fn get_parameter_text(&self, index: i32) -> String {
+        self.read_string(OpCode::GetParameterDisplay, index, MAX_PARAM_STR_LEN)
+    }
+
+    fn get_parameter_name(&self, index: i32) -> String {
+        self.read_string(OpCode::GetParameterName, index, MAX_PARAM_STR_LEN)
+    }
+
+    fn get_parameter(&self, index: i32) -> f32 {
+        // SAFETY: the effect is valid until the handle drops
+        unsafe { ((*self.effect).getParameter)(self.effect, index) }
+    }
+
+    fn set_parameter(&self, index: i32, value: f32) {
+        // SAFETY: the effect is valid until the handle drops
+        unsafe { ((*self.effect).setParameter)(self.effect, index, value) }
+    }
+
+    fn can_be_automated(&self, index: i32) -> bool {
+        self.dispatch(OpCode::CanBeAutomated, index, 0, null_mut(), 0_f32) > 0
+    }
+
+    fn string_to_parameter(&self, index: i32, text: String) -> bool {
+        self.write_string(OpCode::StringToParameter, index, &text) > 0
+    }
+
+    fn get_preset_data(&self) -> Vec<u8> {
+        self.get_chunk(true)
+    }
+
+    fn get_bank_data(&self) -> Vec<u8> {
+        self.get_chunk(false)
+    }
+
+    fn load_preset_data(&self, data: &[u8]) {
+        self.set_chunk(true, data);
+    }
+
+    fn load_bank_data(&self, data: &[u8]) {
+        self.set_chunk(false, data);
+    }
+}
+
+/// the editor of a loaded plugin
+struct EffectEditor {
+    params: Arc<EffectHandle>,
+    is_open: bool,
+}
+
+impl EffectEditor {
+    fn rect(&self) -> Option<Rect> {
+        let mut rect: *mut Rect = null_mut();
+        let result = self.params.dispatch(
+            OpCode::EditorGetRect,
+            0,
+            0,
+            &mut rect as *mut *mut Rect as *mut c_void,
+            0_f32,
+        );
+        // SAFETY: the plugin owns the rect it points to
+        (result != 0 && !rect.is_null()).then(|| unsafe { *rect })
+    }
+}
+
+impl Editor for EffectEditor {
+    fn size(&self) -> (i32, i32) {
+        self.rect().map_or((0, 0), |rect| {
+            (
+                (rect.right - rect.left) as i32,
+                (rect.bottom - rect.top) as i32,
+            )
+        })
+    }
+
+    fn position(&self) -> (i32, i32) {
+        self.rect()
+            .map_or((0, 0), |rect| (rect.left as i32, rect.top as i32))
+    }
+
+    fn open(&mut self, parent: *mut c_void) -> bool {
+        self.is_open = self
+            .params
+            .dispatch(OpCode::EditorOpen, 0, 0, parent, 0_f32)
+            == 1;
+        self.is_open
+    }
+
+    fn close(&mut self) {
+        self.params.opcode(OpCode::EditorClose);
+        self.is_open = false;
+    }
+
+    fn is_open(&mut self) -> bool {
+        self.is_open
+    }
+}
+
+/// the host callback every loaded plugin calls, answers from the host state of its effect
+extern "C" fn audio_master(
+    effect: *mut AEffect,
+    opcode: i32,
+    index: i32,
+    value: isize,
+    ptr: *mut c_void,
+    opt: f32,
+) -> isize {
+    // SAFETY: the reserved field holds the state from load, or the effect is still being
+    // created and the state is in LOADING
+    let state = unsafe {
+        match effect.as_ref() {
+            Some(effect) if effect.reserved1 != 0 => effect.reserved1 as *const HostState,
+            _ => LOADING.get(),
+        }
+        .as_ref()
+    };
+    let Some(state) = state else {
+        return 0;
+    };
+
+    // answered without the host lock, the audio thread asks these while processing
+    match HostOpCode::try_from(opcode) {
+        Ok(HostOpCode::Version) => return 2400,
+        Ok(HostOpCode::GetSampleRate) => return state.sample_rate() as isize,
+        Ok(HostOpCode::GetCurrentProcessLevel) => {
+            return match PROCESSING.get() {
+                true => PROCESS_LEVEL_REALTIME,
+                false => PROCESS_LEVEL_USER,
+            };
+        }
+        _ => (),
+    }
+
+    let host = state.host.lock().unwrap_or_else(PoisonError::into_inner);
+    match HostOpCode::try_from(opcode) {
+        Ok(HostOpCode::CurrentId) => host.get_plugin_id() as isize,
+        Ok(HostOpCode::Automate) => {
+            host.automate(index, opt);
+            0
+        }
+        Ok(HostOpCode::BeginEdit) => {
+            host.begin_edit(index);
+            1
+        }
+        Ok(HostOpCode::EndEdit) => {
+            host.end_edit(index);
+            1
+        }
+        Ok(HostOpCode::Idle) => {
+            host.idle();
+            1
+        }
+        Ok(HostOpCode::UpdateDisplay) => {
+            host.update_display();
+            1
+        }
+        Ok(HostOpCode::GetBlockSize) => host.get_block_size(),
+        Ok(HostOpCode::GetVendorVersion) => host.get_info().0,
+        Ok(HostOpCode::GetVendorString) => copy_string(ptr, &host.get_info().1, MAX_VENDOR_STR_LEN),
+        Ok(HostOpCode::GetProductString) => {
+            copy_string(ptr, &host.get_info().2, MAX_PRODUCT_STR_LEN)
+        }
+        Ok(HostOpCode::ProcessEvents) if !ptr.is_null() => {
+            // SAFETY: the plugin passes its events for the duration of the call
+            host.process_events(unsafe { &*(ptr as *const Events) });
+            1
+        }
+        Ok(HostOpCode::GetTime) => match host.get_time_info(value as i32) {
+            Some(time_info) => TIME_INFO.with(|cell| {
+                cell.set(time_info);
+                cell.as_ptr() as isize
+            }),
+            None => 0,
+        },
+        Ok(HostOpCode::CanDo) if !ptr.is_null() => {
+            // SAFETY: the plugin passes a null terminated string
+            let can_do = unsafe { CStr::from_ptr(ptr as *const c_char) };
+            HOST_CAN_DO
+                .iter()
+                .any(|supported| supported.as_bytes() == can_do.to_bytes()) as isize
+        }
+        _ => {
+            state.unhandled(opcode);
+            0
+        }
+    }
+}
+
+/// writes a null terminated string into a buffer of max bytes from the plugin
+fn copy_string(ptr: *mut c_void, text: &str, max: usize) -> isize {
+    if ptr.is_null() {
+        return 0;
+    }
+    let length = text.len().min(max - 1);
+    // SAFETY: the plugin passes a buffer of at least max bytes
+    unsafe {
+        let buffer = slice::from_raw_parts_mut(ptr as *mut u8, max);
+        buffer[..length].copy_from_slice(&text.as_bytes()[..length]);
+        buffer[length] = 0;
+    }
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{open_config, scratch_dir};
+    use std::sync::atomic::AtomicUsize;
+
+    /// a host that records the calls it receives
+    #[derive(Default)]
+    struct RecordingHost {
+        displays: Cell<usize>,
+    }
+
+    impl Host for RecordingHost {
+        fn update_display(&self) {
+            self.displays.set(self.displays.get() + 1);
+        }
+    }
+
+    /// runs f with the host callback answering from state, as while a plugin is created
+    fn with_state<R>(host: Arc<Mutex<RecordingHost>>, f: impl FnOnce(&HostState) -> R) -> R {
+        let state = HostState {
+            host,
+            name: "test.dll".to_string(),
+            sample_rate: AtomicU32::new(DEFAULT_SAMPLE_RATE.to_bits()),
+            reported: [const { AtomicBool::new(false) }; REPORTED_OPCODES],
+        };
+        LOADING.set(&state);
+        let result = f(&state);
+        LOADING.set(null());
+        result
+    }
+
+    fn call(opcode: HostOpCode) -> isize {
+        audio_master(null_mut(), opcode.into(), 0, 0, null_mut(), 0_f32)
+    }
+
+    #[test]
+    fn answers_the_sample_rate_and_process_level() {
+        with_state(Arc::default(), |state| {
+            assert_eq!(call(HostOpCode::GetSampleRate), 44_100);
+            state.sample_rate.store(48_000_f32.to_bits(), Relaxed);
+            assert_eq!(call(HostOpCode::GetSampleRate), 48_000);
+
+            assert_eq!(call(HostOpCode::GetCurrentProcessLevel), PROCESS_LEVEL_USER);
+            PROCESSING.set(true);
+            assert_eq!(
+                call(HostOpCode::GetCurrentProcessLevel),
+                PROCESS_LEVEL_REALTIME
+            );
+            PROCESSING.set(false);
+        });
+    }
+
+    #[test]
+    fn forwards_display_updates_and_block_size() {
+        let host = Arc::new(Mutex::new(RecordingHost::default()));
+        with_state(Arc::clone(&host), |_| {
+            assert_eq!(call(HostOpCode::UpdateDisplay), 1);
+            assert_eq!(call(HostOpCode::GetBlockSize), 0);
+            assert_eq!(call(HostOpCode::Version), 2400);
+        });
+        assert_eq!(host.lock().unwrap().displays.get(), 1);
+    }
+
+    #[test]
+    fn answers_host_capabilities() {
+        with_state(Arc::default(), |_| {
+            let can_do = |text: &str| {
+                let text = CString::new(text).unwrap();
+                let opcode = HostOpCode::CanDo.into();
+                audio_master(
+                    null_mut(),
+                    opcode,
+                    0,
+                    0,
+                    text.as_ptr() as *mut c_void,
+                    0_f32,
+                )
+            };
+            assert_eq!(can_do("sendVstTimeInfo"), 1);
+            assert_eq!(can_do("openFileSelector"), 0);
+        });
+    }
+
+    #[test]
+    fn reports_unhandled_opcodes_once() {
+        with_state(Arc::default(), |state| {
+            assert_eq!(call(HostOpCode::GetInputLatency), 0);
+            assert_eq!(call(HostOpCode::GetInputLatency), 0);
+            let index = i32::from(HostOpCode::GetInputLatency) as usize;
+            assert!(state.reported[index].load(Relaxed));
+            assert_eq!(
+                state
+                    .reported
+                    .iter()
+                    .filter(|reported| reported.load(Relaxed))
+                    .count(),
+                1
+            );
+        });
+    }
+
+    #[test]
+    fn copies_strings_within_the_buffer() {
+        let mut buffer = [0xff_u8; 8];
+        copy_string(
+            buffer.as_mut_ptr() as *mut c_void,
+            "WhisperWare",
+            buffer.len(),
+        );
+        assert_eq!(&buffer, b"Whisper\0");
+    }
+
+    /// what the fake effect was asked, reached through its user field
+    #[derive(Default)]
+    struct Calls {
+        shutdowns: AtomicUsize,
+        /// calls made after the effect was shut down and freed
+        after_shutdown: AtomicUsize,
+    }
+
+    fn calls<'a>(effect: *mut AEffect) -> &'a Calls {
+        // SAFETY: the test keeps the calls and the effect alive past the handle
+        let calls = unsafe { &*((*effect).user as *const Calls) };
+        if calls.shutdowns.load(Relaxed) > 0 {
+            calls.after_shutdown.fetch_add(1, Relaxed);
+        }
+        calls
+    }
+
+    extern "C" fn fake_dispatcher(
+        effect: *mut AEffect,
+        opcode: i32,
+        _: i32,
+        _: isize,
+        _: *mut c_void,
+        _: f32,
+    ) -> isize {
+        let calls = calls(effect);
+        match OpCode::try_from(opcode) {
+            Ok(OpCode::Shutdown) => _ = calls.shutdowns.fetch_add(1, Relaxed),
+            Ok(OpCode::GetApiVersion) => return 2400,
+            _ => (),
+        }
+        0
+    }
+
+    extern "C" fn fake_get_parameter(effect: *mut AEffect, _: i32) -> f32 {
+        calls(effect);
+        0.5
+    }
+
+    extern "C" fn fake_set_parameter(effect: *mut AEffect, _: i32, _: f32) {
+        calls(effect);
+    }
+
+    extern "C" fn fake_process(_: *mut AEffect, _: *const *const f32, _: *mut *mut f32, _: i32) {}
+
+    extern "C" fn fake_process_f64(
+        _: *mut AEffect,
+        _: *const *const f64,
+        _: *mut *mut f64,
+        _: i32,
+    ) {
+    }
+
+    /// an effect with two parameters that records its calls
+    fn fake_effect(calls: &Calls) -> Box<AEffect> {
+        Box::new(AEffect {
+            magic: vst::api::consts::VST_MAGIC,
+            dispatcher: fake_dispatcher,
+            _process: fake_process,
+            setParameter: fake_set_parameter,
+            getParameter: fake_get_parameter,
+            numPrograms: 0,
+            numParams: 2,
+            numInputs: 2,
+            numOutputs: 2,
+            flags: 0,
+            reserved1: 0,
+            reserved2: 0,
+            initialDelay: 0,
+            _realQualities: 0,
+            _offQualities: 0,
+            _ioRatio: 0_f32,
+            object: null_mut(),
+            user: calls as *const Calls as *mut c_void,
+            uniqueId: 0x5465_7374,
+            version: 1,
+            processReplacing: fake_process,
+            processReplacingF64: fake_process_f64,
+            future: [0; 56],
+        })
+    }
+
+    /// wraps the effect like a loaded library, the effect must outlive the plugin
+    fn fake_plugin(effect: &mut AEffect) -> LoadedPlugin {
+        let state = Box::into_raw(Box::new(HostState {
+            host: Arc::new(Mutex::new(RecordingHost::default())),
+            name: "fake.dll".to_string(),
+            sample_rate: AtomicU32::new(DEFAULT_SAMPLE_RATE.to_bits()),
+            reported: [const { AtomicBool::new(false) }; REPORTED_OPCODES],
+        }));
+        effect.reserved1 = state as isize;
+
+        #[cfg(unix)]
+        let library = Library::from(libloading::os::unix::Library::this());
+        #[cfg(windows)]
+        let library = Library::from(libloading::os::windows::Library::this().unwrap());
+
+        LoadedPlugin::from_effect(EffectHandle {
+            effect,
+            state,
+            _library: library,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn the_effect_shuts_down_with_its_last_handle() {
+        let calls = Calls::default();
+        let mut effect = fake_effect(&calls);
+        let mut plugin = fake_plugin(&mut effect);
+        let parameters = plugin.get_parameter_object();
+        let editor = plugin.get_editor().unwrap();
+
+        drop(plugin);
+        assert_eq!(calls.shutdowns.load(Relaxed), 0);
+        assert_eq!(parameters.get_parameter(0), 0.5);
+        drop(editor);
+        parameters.set_parameter(1, 0.25);
+        drop(parameters);
+
+        assert_eq!(calls.shutdowns.load(Relaxed), 1);
+        assert_eq!(calls.after_shutdown.load(Relaxed), 0);
+    }
+
+    #[test]
+    fn a_bound_config_outlives_its_plugin() {
+        let calls = Calls::default();
+        let mut effect = fake_effect(&calls);
+        let directory = scratch_dir("loader_bound");
+        let config = open_config(&directory);
+        let slot = config.add_slot(None);
+
+        let mut plugin = fake_plugin(&mut effect);
+        config.bind_parameters(slot, &mut plugin);
+        // the chain lets go of the plugin when it is replaced or rebuilt
+        drop(plugin);
+
+        config.export_fx(slot, &directory.join("bank.fxb")).unwrap();
+        config.save().unwrap();
+        assert_eq!(calls.shutdowns.load(Relaxed), 0);
+
+        drop(config);
+        assert_eq!(calls.shutdowns.load(Relaxed), 1);
+        assert_eq!(calls.after_shutdown.load(Relaxed), 0);
+    }
+
+    #[test]
+    fn libraries_without_a_plugin_fail_to_load() {
+        let host = Arc::new(Mutex::new(RecordingHost::default()));
+        let result = LoadedPlugin::load(Path::new("does_not_exist.dll"), host);
+        assert!(matches!(result, Err(PluginLoadError::InvalidPath)));
+    }
+}
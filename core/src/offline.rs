@@ -0,0 +1,233 @@
+use crate::Result;
+use crate::backend::{StreamFormat, StreamRings, StreamSignals, processor};
+use crate::chain::PluginChain;
+use crate::config::AtomicConfig;
+use crate::limiter::Limiter;
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use rtrb::RingBuffer;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+/// runs a WAV file through the same processing chain as the live backend
+///
+/// the plugins should be freshly loaded with the saved parameters applied. the whole file
+/// is buffered so no frames are dropped, and the plugin and limiter latency is trimmed from
+/// the output
+pub fn process_file(
+    input: &Path,
+    output: &Path,
+    chain: &mut PluginChain,
+    config: &AtomicConfig,
+) -> Result<()> {
+    let reader = WavReader::open(input)?;
+    let spec = reader.spec();
+    let channels = spec.channels as usize;
+
+    let samples: Vec<f32> = match spec.sample_format {
+        SampleFormat::Float => reader.into_samples::<f32>().collect::<hound::Result<_>>()?,
+        SampleFormat::Int => {
+            let scale = 1_f32 / (1_i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .into_samples::<i32>()
+                .map(|sample| sample.map(|sample| sample as f32 * scale))
+                .collect::<hound::Result<_>>()?
+        }
+    };
+
+    let sample_rate = spec.sample_rate as f32;
+    let frames = samples.len() / channels;
+    let output_channels = config.routing().preferred_channels(channels);
+
+    // plugins may only report their delay once they know the sample rate
+    chain.arrange(config);
+    chain.prepare(sample_rate);
+
+    // the plugins and the limiter delay the signal, so extra silence flushes the tail out
+    let latency = chain.latency() + Limiter::new(sample_rate, output_channels).latency();
+    let total = frames + latency;
+
+    let (mut input_producer, input_consumer) = RingBuffer::<f32>::new((total * channels).max(1));
+    let (output_producer, mut output_consumer) =
+        RingBuffer::<f32>::new((total * output_channels).max(1));
+
+    let chunk = input_producer.write_chunk_uninit(total * channels)?;
+    chunk.fill_from_iter(
+        samples[..frames * channels]
+            .iter()
+            .copied()
+            .chain(std::iter::repeat(0_f32)),
+    );
+    // abandoning the producer lets the processor flush the trailing partial block
+    drop(input_producer);
+
+    processor(
+        StreamRings {
+            input: input_consumer,
+            sidechain: None,
+            output: output_producer,
+        },
+        Arc::new(StreamSignals::default()),
+        chain,
+        config,
+        StreamFormat {
+            input_rate: sample_rate,
+            output_rate: sample_rate,
+            input_channels: channels,
+            output_channels,
+            sidechain_channels: 0,
+            compensate_drift: false,
+        },
+        &Arc::new(AtomicBool::new(true)),
+    )?;
+
+    let mut writer = WavWriter::create(
+        output,
+        WavSpec {
+            channels: output_channels as u16,
+            sample_rate: spec.sample_rate,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        },
+    )?;
+
+    let chunk = output_consumer.read_chunk(output_consumer.slots())?;
+    for sample in chunk.into_iter().skip(latency * output_channels) {
+        writer.write_sample(sample)?;
+    }
+
+    writer.finalize()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BLOCK_SIZE;
+    use crate::compressor::Compressor;
+    use crate::testing::{open_config, scratch_dir};
+    use std::sync::mpsc::channel;
+    use std::thread::spawn;
+    use std::time::Duration;
+    use vst::buffer::AudioBuffer;
+    use vst::plugin::{HostCallback, Info, Plugin};
+
+    /// writes a quiet stereo sine and returns its interleaved samples
+    fn write_sine(path: &Path, frames: usize) -> Vec<f32> {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 48_000,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        let samples: Vec<f32> = (0..frames * 2)
+            .map(|index| 0.1 * (index as f32 / 2_f32 * 0.05).sin())
+            .collect();
+        let mut writer = WavWriter::create(path, spec).unwrap();
+        for sample in &samples {
+            writer.write_sample(*sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        samples
+    }
+
+    fn read(path: &Path) -> Vec<f32> {
+        WavReader::open(path)
+            .unwrap()
+            .into_samples::<f32>()
+            .collect::<hound::Result<_>>()
+            .unwrap()
+    }
+
+    /// processes the file on another thread, failing instead of waiting on a lost block
+    fn process_in_time(name: &str, frames: usize, chain: PluginChain) -> (Vec<f32>, Vec<f32>) {
+        let directory = scratch_dir(name);
+        let (input, output) = (directory.join("in.wav"), directory.join("out.wav"));
+        let samples = write_sine(&input, frames);
+
+        let (done, finished) = channel();
+        spawn(move || {
+            let mut chain = chain;
+            let config = open_config(&directory);
+            let _ = done.send(process_file(&input, &output, &mut chain, &config).map(|()| output));
+        });
+        let output = finished
+            .recv_timeout(Duration::from_secs(10))
+            .expect("the file was not processed in time")
+            .unwrap();
+        (samples, read(&output))
+    }
+
+    #[test]
+    fn a_partial_last_block_is_processed() {
+        let frames = BLOCK_SIZE * 3 + 100;
+        let (samples, processed) = process_in_time("offline_partial", frames, PluginChain::new());
+
+        // the limiter passes quiet audio unchanged and its latency is trimmed
+        assert_eq!(processed.len(), samples.len());
+        for (processed, sample) in processed.iter().zip(&samples) {
+            assert!((processed - sample).abs() < 1e-6);
+        }
+    }
+
+    /// a plugin that delays its audio, reporting the delay only once it knows the sample rate
+    #[derive(Default)]
+    struct LateDelay {
+        delay: i32,
+        lines: Vec<std::collections::VecDeque<f32>>,
+    }
+
+    impl Plugin for LateDelay {
+        fn get_info(&self) -> Info {
+            Info {
+                inputs: 2,
+                outputs: 2,
+                initial_delay: self.delay,
+                ..Default::default()
+            }
+        }
+
+        fn new(_host: HostCallback) -> Self {
+            Self::default()
+        }
+
+        fn set_sample_rate(&mut self, _rate: f32) {
+            self.delay = 100;
+            self.lines = vec![vec![0_f32; 100].into(); 2];
+        }
+
+        fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+            for ((input, output), line) in buffer.zip().zip(&mut self.lines) {
+                for (input, output) in input.iter().zip(output) {
+                    line.push_back(*input);
+                    *output = line.pop_front().unwrap_or_default();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn the_latency_is_read_once_the_plugins_are_prepared() {
+        let frames = BLOCK_SIZE * 2;
+        let mut chain = PluginChain::new();
+        chain.push(0, Box::new(LateDelay::default()));
+        let (samples, processed) = process_in_time("offline_latency", frames, chain);
+
+        assert_eq!(processed.len(), samples.len());
+        for (processed, sample) in processed.iter().zip(&samples) {
+            assert!((processed - sample).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn every_frame_passes_through_the_plugins() {
+        let frames = BLOCK_SIZE * 2 + 1;
+        let mut chain = PluginChain::new();
+        chain.push(0, Box::new(Compressor::default()));
+        let (samples, processed) = process_in_time("offline_plugins", frames, chain);
+
+        assert_eq!(processed.len(), samples.len());
+        assert!(processed.iter().all(|sample| sample.is_finite()));
+        assert!(processed.iter().any(|sample| *sample != 0_f32));
+    }
+}
@@ -0,0 +1,166 @@
+use atomic_float::AtomicF32;
+use std::collections::BTreeMap;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::sync::atomic::Ordering::Relaxed;
+use vst::plugin::{Info, PluginParameters};
+use vst::prelude::Plugin;
+
+/// lock free parameter values in plugin order, shared with the audio thread
+#[derive(Default)]
+pub struct ParameterValues {
+    values: Box<[AtomicF32]>,
+}
+
+impl ParameterValues {
+    pub fn get(&self, index: usize) -> Option<f32> {
+        self.values.get(index).map(|value| value.load(Relaxed))
+    }
+
+    /// stores a value, returns false when the index is out of range
+    pub fn set(&self, index: usize, value: f32) -> bool {
+        self.values
+            .get(index)
+            .map(|atomic| atomic.store(value, Relaxed))
+            .is_some()
+    }
+
+    /// pushes every value to the plugin
+    pub fn apply(&self, instance: &mut dyn Plugin) {
+        let parameters = instance.get_parameter_object();
+
+        for (index, value) in self.values.iter().enumerate() {
+            parameters.set_parameter(index as i32, value.load(Relaxed));
+        }
+    }
+}
+
+/// the info and parameter object of the loaded plugin, used to reach its state off the audio
+/// thread
+pub(crate) struct BoundPlugin {
+    pub(crate) info: Info,
+    pub(crate) object: ParameterObject,
+}
+
+/// the parameter object of a plugin, which the config reaches from the threads that save and
+/// load its state
+pub(crate) struct ParameterObject(Arc<dyn PluginParameters>);
+
+// SAFETY: PluginParameters requires Sync, as the object is meant to be shared with the host
+// threads while the plugin processes, but the trait object lacks Send only because the trait
+// does not name it. Send matters here for the last clone dropping the object on another
+// thread, often the config's after the chain let go of the plugin. every object that gets
+// bound is Send: the EffectHandle of loaded plugins, which owns the effect and shuts it down
+// in its own drop from whichever thread holds the last clone, the sandbox, whose state sits
+// behind mutexes and atomics, and the atomics of the native compressor
+unsafe impl Send for ParameterObject {}
+unsafe impl Sync for ParameterObject {}
+
+impl Deref for ParameterObject {
+    type Target = dyn PluginParameters;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+/// the parameter values by name, bound to the index layout of the loaded plugin
+pub(crate) struct Parameters {
+    /// the names of the loaded plugin's parameters in plugin order
+    names: Vec<String>,
+    values: Arc<ParameterValues>,
+    /// the stored values, including those of parameters the loaded plugin lacks
+    stored: BTreeMap<String, f32>,
+    plugin: Option<BoundPlugin>,
+}
+
+impl Parameters {
+    pub(crate) fn new(stored: BTreeMap<String, f32>) -> Self {
+        Parameters {
+            names: Vec::new(),
+            values: Default::default(),
+            stored,
+            plugin: None,
+        }
+    }
+
+    /// reads the parameter layout from the plugin
+    ///
+    /// stored values are used where the names match, the plugin keeps its own value otherwise
+    pub(crate) fn bind(&mut self, instance: &mut dyn Plugin) {
+        // keep the values of the previously bound plugin
+        self.stored = self.map();
+
+        let info = instance.get_info();
+        let count = info.parameters.max(0);
+        let parameters = instance.get_parameter_object();
+
+        self.names = (0..count)
+            .map(|index| parameters.get_parameter_name(index))
+            .collect();
+        let values = self
+            .names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| {
+                let value = self.stored.get(name).copied();
+                AtomicF32::new(value.unwrap_or_else(|| parameters.get_parameter(index as i32)))
+            })
+            .collect();
+
+        self.values = Arc::new(ParameterValues { values });
+        self.plugin = Some(BoundPlugin {
+            info,
+            object: ParameterObject(parameters),
+        });
+    }
+
+    pub(crate) fn plugin(&self) -> Option<&BoundPlugin> {
+        self.plugin.as_ref()
+    }
+
+    /// reads the values back after the plugin changed them itself
+    pub(crate) fn refresh(&self) {
+        if let Some(plugin) = &self.plugin {
+            for (index, value) in self.values.values.iter().enumerate() {
+                value.store(plugin.object.get_parameter(index as i32), Relaxed);
+            }
+        }
+    }
+
+    pub(crate) fn values(&self) -> Arc<ParameterValues> {
+        Arc::clone(&self.values)
+    }
+
+    /// stores a value by name, whether or not the loaded plugin has the parameter
+    pub(crate) fn set(&mut self, name: &str, value: f32) {
+        match self.names.iter().position(|bound| bound == name) {
+            Some(index) => _ = self.values.set(index, value),
+            None => _ = self.stored.insert(name.to_string(), value),
+        }
+    }
+
+    /// stores a value only if the loaded plugin has the parameter, returns whether it does
+    pub(crate) fn set_bound(&mut self, name: &str, value: f32) -> bool {
+        self.names
+            .iter()
+            .position(|bound| bound == name)
+            .is_some_and(|index| self.values.set(index, value))
+    }
+
+    /// the values of the loaded plugin's parameters
+    pub(crate) fn bound(&self) -> BTreeMap<String, f32> {
+        self.names
+            .iter()
+            .enumerate()
+            .filter_map(|(index, name)| Some((name.clone(), self.values.get(index)?)))
+            .collect()
+    }
+
+    /// every stored value, with the live values of the loaded plugin
+    pub(crate) fn map(&self) -> BTreeMap<String, f32> {
+        let mut map = self.stored.clone();
+        map.extend(self.bound());
+        map
+    }
+}
@@ -0,0 +1,364 @@
+use crate::Result;
+use crate::error::ErrorKind;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_slice, to_writer_pretty};
+use std::collections::BTreeMap;
+use std::fs::{File, create_dir_all, read, read_dir, remove_file, rename};
+use std::io;
+use std::path::{Path, PathBuf};
+use vst::prelude::Plugin;
+
+/// the extension of preset files
+const EXTENSION: &str = "json";
+/// the extensions of the program and bank files shared with DAWs
+const FX_EXTENSIONS: [&str; 2] = ["fxp", "fxb"];
+
+/// a named set of normalized plugin parameter values
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Preset {
+    pub name: String,
+    /// the values by parameter name
+    pub parameters: BTreeMap<String, f32>,
+}
+
+impl Preset {
+    /// reads a single preset file
+    pub fn read(path: &Path) -> Result<Self> {
+        Ok(from_slice(&read(path)?)?)
+    }
+
+    /// writes a single preset file
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut file = File::create(path)?;
+        to_writer_pretty(&mut file, self)?;
+        Ok(())
+    }
+
+    /// sets the values on a plugin without touching the saved config
+    ///
+    /// values for parameters the plugin lacks are ignored
+    pub fn apply(&self, instance: &mut dyn Plugin) {
+        let count = instance.get_info().parameters;
+        let parameters = instance.get_parameter_object();
+
+        for index in 0..count {
+            if let Some(value) = self.parameters.get(&parameters.get_parameter_name(index)) {
+                parameters.set_parameter(index, *value);
+            }
+        }
+    }
+}
+
+/// the preset files in the presets directory, one file per preset named after it
+pub struct PresetStore {
+    directory: PathBuf,
+}
+
+impl PresetStore {
+    pub fn new(directory: PathBuf) -> Self {
+        PresetStore { directory }
+    }
+
+    /// the directory the presets are stored in
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// the names of the stored presets in alphabetical order
+    pub fn list(&self) -> Result<Vec<String>> {
+        if !self.directory.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut names = Vec::new();
+        for entry in read_dir(&self.directory)? {
+            let path = entry?.path();
+
+            if path
+                .extension()
+                .is_some_and(|extension| extension == EXTENSION)
+                && let Some(name) = path.file_stem().and_then(|stem| stem.to_str())
+            {
+                names.push(name.to_string());
+            }
+        }
+
+        names.sort_by_key(|name| name.to_lowercase());
+        Ok(names)
+    }
+
+    /// the fxp and fxb files in the presets directory in alphabetical order
+    pub fn fx_files(&self) -> Result<Vec<PathBuf>> {
+        if !self.directory.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut paths = Vec::new();
+        for entry in read_dir(&self.directory)? {
+            let path = entry?.path();
+
+            if path.extension().is_some_and(|extension| {
+                FX_EXTENSIONS
+                    .iter()
+                    .any(|fx| extension.eq_ignore_ascii_case(fx))
+            }) {
+                paths.push(path);
+            }
+        }
+
+        paths.sort_by_key(|path| path.to_string_lossy().to_lowercase());
+        Ok(paths)
+    }
+
+    pub fn load(&self, name: &str) -> Result<Preset> {
+        let path = self.path(name)?;
+        if !path.exists() {
+            Err(ErrorKind::PresetNotFound)?;
+        }
+
+        let mut preset = Preset::read(&path)?;
+        // the file name is authoritative
+        preset.name = name.to_string();
+        Ok(preset)
+    }
+
+    /// saves a preset, replacing any preset with the same name
+    pub fn save(&self, preset: &Preset) -> Result<()> {
+        let path = self.path(&preset.name)?;
+        create_dir_all(&self.directory)?;
+        preset.write(&path)
+    }
+
+    /// renames a preset, failing if the new name is taken
+    pub fn rename(&self, name: &str, new_name: &str) -> Result<()> {
+        let mut preset = self.load(name)?;
+        let (path, new_path) = (self.path(name)?, self.path(new_name)?);
+        // changing only the case is allowed on case insensitive file systems
+        if !name.eq_ignore_ascii_case(new_name) && new_path.exists() {
+            Err(io::Error::from(io::ErrorKind::AlreadyExists))?;
+        }
+
+        preset.name = new_name.to_string();
+        preset.write(&path)?;
+        rename(path, new_path)?;
+        Ok(())
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let path = self.path(name)?;
+        if !path.exists() {
+            Err(ErrorKind::PresetNotFound)?;
+        }
+
+        remove_file(path)?;
+        Ok(())
+    }
+
+    /// copies a preset file into the store and returns its name
+    ///
+    /// the name stored in the file is used, or the file name when it is not a valid name. a
+    /// number is added to the name when a preset already has it
+    pub fn import(&self, path: &Path) -> Result<String> {
+        let mut preset = Preset::read(path)?;
+
+        if validate(&preset.name).is_err() {
+            preset.name = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or_default()
+                .to_string();
+        }
+
+        preset.name = self.unused_name(&preset.name)?;
+        self.save(&preset)?;
+        Ok(preset.name)
+    }
+
+    /// writes a stored preset to a file outside the store
+    pub fn export(&self, name: &str, path: &Path) -> Result<()> {
+        self.load(name)?.write(path)
+    }
+
+    /// the name, or the name with the first free number after it when a preset has it
+    ///
+    /// names differing only in case are taken, as on the case insensitive Windows file systems
+    fn unused_name(&self, name: &str) -> Result<String> {
+        validate(name)?;
+        let taken: Vec<String> = self
+            .list()?
+            .iter()
+            .map(|name| name.to_lowercase())
+            .collect();
+        let unused = |name: &String| !taken.contains(&name.to_lowercase());
+
+        Ok(Some(name.to_string())
+            .into_iter()
+            .chain((2..).map(|number| format!("{name} ({number})")))
+            .find(unused)
+            .unwrap())
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf> {
+        validate(name)?;
+        Ok(self.directory.join(format!("{name}.{EXTENSION}")))
+    }
+}
+
+/// rejects names that cannot be used as a file name on Windows
+fn validate(name: &str) -> Result<()> {
+    let invalid = name.trim().is_empty()
+        || name != name.trim()
+        || name.ends_with('.')
+        || name
+            .chars()
+            .any(|c| c.is_control() || "<>:\"/\\|?*".contains(c))
+        || is_device_name(name);
+
+    if invalid {
+        Err(ErrorKind::InvalidPresetName)?;
+    }
+
+    Ok(())
+}
+
+/// whether Windows opens a device rather than a file for the name, whatever its extension
+fn is_device_name(name: &str) -> bool {
+    // windows ignores spaces before the extension, "nul .json" is the device as well
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    let numbered = |prefix: &str| {
+        stem.len() == 4
+            && stem
+                .get(..3)
+                .is_some_and(|stem| stem.eq_ignore_ascii_case(prefix))
+            && matches!(stem.as_bytes()[3], b'1'..=b'9')
+    };
+
+    ["CON", "PRN", "AUX", "NUL"]
+        .iter()
+        .any(|device| stem.eq_ignore_ascii_case(device))
+        || numbered("COM")
+        || numbered("LPT")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch_dir;
+
+    fn preset(name: &str, gain: f32) -> Preset {
+        Preset {
+            name: name.to_string(),
+            parameters: BTreeMap::from([("Gain".to_string(), gain)]),
+        }
+    }
+
+    #[test]
+    fn saves_lists_and_loads_presets() {
+        let store = PresetStore::new(scratch_dir("presets_save").join("presets"));
+        assert!(store.list().unwrap().is_empty());
+
+        store.save(&preset("shooter", 0.25)).unwrap();
+        store.save(&preset("Music", 0.5)).unwrap();
+        assert_eq!(store.list().unwrap(), ["Music", "shooter"]);
+
+        // saving again replaces the preset
+        store.save(&preset("shooter", 0.75)).unwrap();
+        assert_eq!(store.load("shooter").unwrap(), preset("shooter", 0.75));
+        assert_eq!(
+            store.load("missing").unwrap_err().kind,
+            ErrorKind::PresetNotFound
+        );
+    }
+
+    #[test]
+    fn renames_and_deletes_presets() {
+        let store = PresetStore::new(scratch_dir("presets_rename"));
+        store.save(&preset("shooter", 0.25)).unwrap();
+        store.save(&preset("music", 0.5)).unwrap();
+
+        assert!(store.rename("shooter", "music").is_err());
+        store.rename("shooter", "battle royale").unwrap();
+        assert_eq!(store.list().unwrap(), ["battle royale", "music"]);
+        assert_eq!(
+            Preset::read(&store.directory().join("battle royale.json")).unwrap(),
+            preset("battle royale", 0.25)
+        );
+
+        store.delete("music").unwrap();
+        assert_eq!(store.list().unwrap(), ["battle royale"]);
+        assert_eq!(
+            store.delete("music").unwrap_err().kind,
+            ErrorKind::PresetNotFound
+        );
+    }
+
+    #[test]
+    fn imports_and_exports_single_files() {
+        let directory = scratch_dir("presets_import");
+        let store = PresetStore::new(directory.join("presets"));
+        store.save(&preset("shooter", 0.25)).unwrap();
+
+        let exported = directory.join("shared.json");
+        store.export("shooter", &exported).unwrap();
+        assert_eq!(Preset::read(&exported).unwrap(), preset("shooter", 0.25));
+
+        // the name in the file wins, the file name stands in for an invalid one
+        preset("music", 0.5).write(&exported).unwrap();
+        assert_eq!(store.import(&exported).unwrap(), "music");
+        preset("a:b", 0.75).write(&exported).unwrap();
+        assert_eq!(store.import(&exported).unwrap(), "shared");
+        assert_eq!(store.list().unwrap(), ["music", "shared", "shooter"]);
+        assert_eq!(store.load("shared").unwrap(), preset("shared", 0.75));
+    }
+
+    #[test]
+    fn rejects_names_that_are_not_file_names() {
+        let store = PresetStore::new(scratch_dir("presets_names"));
+        for name in [
+            "",
+            " ",
+            " padded",
+            "dot.",
+            "a/b",
+            "a:b",
+            "tab\t",
+            "CON",
+            "nul",
+            "Aux.txt",
+            "prn .x",
+            "com1",
+            "LPT9",
+            "lpt1.backup",
+        ] {
+            assert_eq!(
+                store.save(&preset(name, 0.5)).unwrap_err().kind,
+                ErrorKind::InvalidPresetName,
+                "{name:?}"
+            );
+        }
+
+        // only the exact device names are reserved
+        for name in [
+            "console", "com0", "com10", "lpt", "my aux", "nul-ish", "coé",
+        ] {
+            store.save(&preset(name, 0.5)).unwrap();
+        }
+    }
+
+    #[test]
+    fn imports_do_not_replace_presets() {
+        let directory = scratch_dir("presets_import_taken");
+        let store = PresetStore::new(directory.join("presets"));
+        store.save(&preset("music", 0.25)).unwrap();
+
+        let shared = directory.join("shared.json");
+        for gain in [0.5, 0.75] {
+            preset("Music", gain).write(&shared).unwrap();
+            store.import(&shared).unwrap();
+        }
+        assert_eq!(store.list().unwrap(), ["music", "Music (2)", "Music (3)"]);
+        assert_eq!(store.load("music").unwrap(), preset("music", 0.25));
+        assert_eq!(store.load("Music (2)").unwrap(), preset("Music (2)", 0.5));
+        assert_eq!(store.load("Music (3)").unwrap(), preset("Music (3)", 0.75));
+    }
+}
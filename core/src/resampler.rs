@@ -0,0 +1,279 @@
+use crate::BLOCK_SIZE;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+/// the number of table entries per input sample
+const PHASES: usize = 256;
+/// the furthest the ratio may be moved from the nominal one, in parts per unit, well beyond
+/// the drift correction
+const MAX_DEVIATION: f64 = 0.01;
+
+/// trades resampler CPU time and latency for stopband rejection
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResamplerQuality {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl ResamplerQuality {
+    /// the number of sinc taps for this quality
+    fn taps(self) -> usize {
+        match self {
+            ResamplerQuality::Low => 8,
+            ResamplerQuality::Medium => 32,
+            ResamplerQuality::High => 64,
+        }
+    }
+}
+
+/// a multichannel windowed-sinc resampler with an adjustable ratio
+///
+/// the kernel is tabulated at a fixed number of phases and interpolated linearly between
+/// them, so any ratio works and the ratio may change while running. the buffers are sized
+/// for a block at the furthest ratio up front, so processing never allocates
+pub struct Resampler {
+    /// input samples advanced per output sample
+    ratio: f64,
+    /// the ratio implied by the sample rates
+    nominal: f64,
+    /// the read position in the pending input
+    position: f64,
+    /// interleaved input frames that have not been fully consumed
+    pending: Vec<f32>,
+    /// the interleaved frames produced from the last block
+    output: Vec<f32>,
+    /// the tabulated kernel from -taps/2 to taps/2
+    table: Vec<f32>,
+    taps: usize,
+    channels: usize,
+}
+
+impl Resampler {
+    pub fn new(
+        input_rate: f32,
+        output_rate: f32,
+        channels: usize,
+        quality: ResamplerQuality,
+    ) -> Self {
+        let taps = quality.taps();
+        let half = (taps / 2) as f64;
+        let ratio = input_rate as f64 / output_rate as f64;
+        // lower the cutoff when downsampling so nothing aliases
+        let cutoff = 0.95 * (1_f64 / ratio).min(1_f64);
+
+        let table = (0..=taps * PHASES + 1)
+            .map(|i| {
+                let x = i as f64 / PHASES as f64 - half;
+                let sinc = if x == 0_f64 {
+                    1_f64
+                } else {
+                    (PI * cutoff * x).sin() / (PI * cutoff * x)
+                };
+                // blackman window
+                let n = (x / half + 1_f64) / 2_f64;
+                let window = 0.42 - 0.5 * (2_f64 * PI * n).cos() + 0.08 * (4_f64 * PI * n).cos();
+                (cutoff * sinc * window.max(0_f64)) as f32
+            })
+            .collect();
+
+        // the kernel keeps fewer than taps frames between blocks
+        let mut pending = Vec::with_capacity((taps + BLOCK_SIZE) * channels);
+        pending.resize((taps / 2 - 1) * channels, 0_f32);
+        // a block yields a frame per step of the smallest ratio, and one the position carries
+        let frames = (BLOCK_SIZE as f64 / (ratio * (1_f64 - MAX_DEVIATION))).ceil() as usize + 1;
+
+        Resampler {
+            ratio,
+            nominal: ratio,
+            position: (taps / 2 - 1) as f64,
+            pending,
+            output: Vec::with_capacity(frames * channels),
+            table,
+            taps,
+            channels,
+        }
+    }
+
+    /// the nominal input samples advanced per output sample
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    /// changes the ratio, used to nudge the rate while running
+    ///
+    /// the ratio stays within a percent of the nominal one the buffers are sized for
+    pub fn set_ratio(&mut self, ratio: f64) {
+        self.ratio = ratio.clamp(
+            self.nominal * (1_f64 - MAX_DEVIATION),
+            self.nominal * (1_f64 + MAX_DEVIATION),
+        );
+    }
+
+    /// the added latency in input samples
+    pub fn latency(&self) -> usize {
+        self.taps / 2
+    }
+
+    /// resamples a block of at most [`BLOCK_SIZE`] interleaved input frames and returns the
+    /// interleaved frames produced from it
+    pub fn process(&mut self, input: &[f32]) -> &mut [f32] {
+        debug_assert!(input.len() <= BLOCK_SIZE * self.channels);
+        self.pending.extend_from_slice(input);
+        self.output.clear();
+        let half = self.taps / 2;
+        let channels = self.channels;
+
+        while self.position as usize + half < self.pending.len() / channels {
+            let base = self.position as usize;
+            let fraction = self.position - base as f64;
+            let start = self.output.len();
+            self.output.resize(start + channels, 0_f32);
+
+            for j in 0..self.taps {
+                // the distance from the sample to the read position, offset into the table
+                let distance = fraction + (self.taps - 1 - j) as f64;
+                let index = distance * PHASES as f64;
+                let whole = index as usize;
+                let blend = (index - whole as f64) as f32;
+                let coeff = self.table[whole] + (self.table[whole + 1] - self.table[whole]) * blend;
+
+                let frame = (base + j + 1 - half) * channels;
+                for (out, sample) in self.output[start..]
+                    .iter_mut()
+                    .zip(&self.pending[frame..frame + channels])
+                {
+                    *out += sample * coeff;
+                }
+            }
+
+            self.position += self.ratio;
+        }
+
+        // drop the frames the kernel can no longer reach
+        let consumed = (self.position as usize + 1).saturating_sub(half);
+        let consumed = consumed.min(self.pending.len() / channels);
+        self.pending.drain(..consumed * channels);
+        self.position -= consumed as f64;
+
+        &mut self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// blocks of a mono sine at frequency for rate, blocks long
+    fn sine(frequency: f32, rate: f32, blocks: usize) -> Vec<Vec<f32>> {
+        (0..blocks)
+            .map(|block| {
+                (0..BLOCK_SIZE)
+                    .map(|frame| {
+                        let time = (block * BLOCK_SIZE + frame) as f32 / rate;
+                        (2_f32 * std::f32::consts::PI * frequency * time).sin()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// resamples every block and returns the whole output
+    fn resample(resampler: &mut Resampler, blocks: &[Vec<f32>]) -> Vec<f32> {
+        blocks
+            .iter()
+            .flat_map(|block| resampler.process(block).to_vec())
+            .collect()
+    }
+
+    /// the peak of the output once the filter settled
+    fn peak(output: &[f32]) -> f32 {
+        output[output.len() / 2..]
+            .iter()
+            .fold(0_f32, |peak, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn produces_frames_in_proportion_to_the_rates() {
+        for (input_rate, output_rate) in [(48_000_f32, 44_100_f32), (44_100_f32, 96_000_f32)] {
+            let mut resampler =
+                Resampler::new(input_rate, output_rate, 2, ResamplerQuality::Medium);
+            let blocks = vec![vec![0_f32; BLOCK_SIZE * 2]; 100];
+            let frames = resample(&mut resampler, &blocks).len() / 2;
+
+            let expected = (100 * BLOCK_SIZE) as f32 * output_rate / input_rate;
+            // the kernel holds back the input frames of its latency at the end
+            let held = resampler.latency() as f32 * output_rate / input_rate;
+            let missing = expected - frames as f32;
+            assert!(
+                (0_f32..=held + 1_f32).contains(&missing),
+                "{frames} {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn passes_audio_through_at_the_same_rate() {
+        for quality in [
+            ResamplerQuality::Low,
+            ResamplerQuality::Medium,
+            ResamplerQuality::High,
+        ] {
+            let mut resampler = Resampler::new(48_000_f32, 48_000_f32, 1, quality);
+            let input = sine(1_000_f32, 48_000_f32, 8);
+            let output = resample(&mut resampler, &input);
+            let input: Vec<f32> = input.concat();
+
+            assert_eq!(output.len(), input.len() - resampler.latency());
+            let error = output
+                .iter()
+                .zip(&input)
+                .skip(BLOCK_SIZE)
+                .fold(0_f32, |error, (output, input)| {
+                    error.max((output - input).abs())
+                });
+            assert!(error < 0.02, "{quality:?} {error}");
+        }
+    }
+
+    #[test]
+    fn higher_qualities_reject_more_above_the_cutoff() {
+        // a tone above the output nyquist frequency when halving the rate
+        let rejection = |quality| {
+            let mut resampler = Resampler::new(96_000_f32, 48_000_f32, 1, quality);
+            peak(&resample(&mut resampler, &sine(30_000_f32, 96_000_f32, 16)))
+        };
+        let (low, medium, high) = (
+            rejection(ResamplerQuality::Low),
+            rejection(ResamplerQuality::Medium),
+            rejection(ResamplerQuality::High),
+        );
+        assert!(high < medium && medium < low, "{low} {medium} {high}");
+        assert!(high < 0.01, "{high}");
+
+        // while a tone well below it passes
+        let mut resampler = Resampler::new(96_000_f32, 48_000_f32, 1, ResamplerQuality::High);
+        let passed = peak(&resample(&mut resampler, &sine(1_000_f32, 96_000_f32, 16)));
+        assert!((passed - 1_f32).abs() < 0.02, "{passed}");
+    }
+
+    #[test]
+    fn keeps_to_its_buffers_at_the_furthest_ratios() {
+        let mut resampler = Resampler::new(48_000_f32, 44_100_f32, 2, ResamplerQuality::High);
+        let capacities = (resampler.pending.capacity(), resampler.output.capacity());
+        let block = vec![0.5_f32; BLOCK_SIZE * 2];
+
+        for ratio in [0_f64, 10_f64] {
+            resampler.set_ratio(ratio);
+            for _ in 0..50 {
+                resampler.process(&block);
+            }
+        }
+        assert_eq!(
+            (resampler.pending.capacity(), resampler.output.capacity()),
+            capacities
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::FRAC_1_SQRT_2;
+
+/// how the processed input channels are mapped onto the output channels
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Routing {
+    /// channels map by position, outputs the input lacks are silent. a mono input feeds
+    /// every output
+    #[default]
+    Direct,
+    /// ITU-R BS.775 downmix of quad, 5.1 and 7.1 to stereo
+    ItuDownmix,
+    /// every output carries the average of all inputs
+    MonoSum,
+    /// swaps the left and right channels
+    SwapStereo,
+    /// repeats the stereo pair across all outputs, e.g. front and rear of a 4 channel device
+    DuplicateStereo,
+    /// one row of input gains per output channel
+    Custom(Vec<Vec<f32>>),
+}
+
+/// the role of an input channel in the ITU downmix
+#[derive(Clone, Copy)]
+enum Speaker {
+    Left,
+    Right,
+    Center,
+    Lfe,
+    SurroundLeft,
+    SurroundRight,
+}
+
+impl Routing {
+    /// the output channel count this routing is designed for, used when there is no device
+    pub fn preferred_channels(&self, inputs: usize) -> usize {
+        match self {
+            Routing::Direct | Routing::SwapStereo => inputs,
+            Routing::ItuDownmix => inputs.min(2),
+            Routing::MonoSum => 1,
+            Routing::DuplicateStereo => 4,
+            Routing::Custom(rows) => rows.len().max(1),
+        }
+    }
+
+    /// builds the gain matrix for the given channel counts, silent without inputs
+    pub fn matrix(&self, inputs: usize, outputs: usize) -> RoutingMatrix {
+        let mut rows = vec![vec![0_f32; inputs]; outputs];
+        if inputs == 0 {
+            return RoutingMatrix { rows };
+        }
+
+        match self {
+            Routing::Direct if inputs == 1 => {
+                for row in &mut rows {
+                    row[0] = 1_f32;
+                }
+            }
+            Routing::Direct => identity(&mut rows),
+            Routing::ItuDownmix => match speakers(inputs) {
+                Some(speakers) if outputs >= 2 => {
+                    for (input, speaker) in speakers.iter().enumerate() {
+                        let (left, right) = match speaker {
+                            Speaker::Left => (1_f32, 0_f32),
+                            Speaker::Right => (0_f32, 1_f32),
+                            Speaker::Center => (FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+                            Speaker::Lfe => (0_f32, 0_f32),
+                            Speaker::SurroundLeft => (FRAC_1_SQRT_2, 0_f32),
+                            Speaker::SurroundRight => (0_f32, FRAC_1_SQRT_2),
+                        };
+                        rows[0][input] = left;
+                        rows[1][input] = right;
+                    }
+                }
+                // nothing to fold down
+                _ => identity(&mut rows),
+            },
+            Routing::MonoSum => {
+                for row in &mut rows {
+                    row.fill(1_f32 / inputs as f32);
+                }
+            }
+            Routing::SwapStereo => {
+                identity(&mut rows);
+                if inputs >= 2 && outputs >= 2 {
+                    rows[0].swap(0, 1);
+                    rows[1].swap(0, 1);
+                }
+            }
+            Routing::DuplicateStereo => {
+                for (output, row) in rows.iter_mut().enumerate() {
+                    if let Some(gain) = row.get_mut(output % inputs.min(2)) {
+                        *gain = 1_f32;
+                    }
+                }
+            }
+            Routing::Custom(custom) => {
+                for (row, gains) in rows.iter_mut().zip(custom) {
+                    for (gain, value) in row.iter_mut().zip(gains) {
+                        *gain = *value;
+                    }
+                }
+            }
+        }
+
+        RoutingMatrix { rows }
+    }
+}
+
+/// the speaker roles for the common WAVEFORMATEXTENSIBLE layouts
+fn speakers(channels: usize) -> Option<&'static [Speaker]> {
+    use Speaker::*;
+
+    match channels {
+        3 => Some(&[Left, Right, Center]),
+        4 => Some(&[Left, Right, SurroundLeft, SurroundRight]),
+        5 => Some(&[Left, Right, Center, SurroundLeft, SurroundRight]),
+        6 => Some(&[Left, Right, Center, Lfe, SurroundLeft, SurroundRight]),
+        8 => Some(&[
+            Left,
+            Right,
+            Center,
+            Lfe,
+            SurroundLeft,
+            SurroundRight,
+            SurroundLeft,
+            SurroundRight,
+        ]),
+        _ => None,
+    }
+}
+
+fn identity(rows: &mut [Vec<f32>]) {
+    for (channel, row) in rows.iter_mut().enumerate() {
+        if let Some(gain) = row.get_mut(channel) {
+            *gain = 1_f32;
+        }
+    }
+}
+
+/// a gain matrix with one row of input gains per output channel
+pub struct RoutingMatrix {
+    rows: Vec<Vec<f32>>,
+}
+
+impl RoutingMatrix {
+    /// mixes the input channels into the output channels
+    pub fn process(&self, inputs: &[Vec<f32>], outputs: &mut [Vec<f32>]) {
+        for (row, output) in self.rows.iter().zip(outputs.iter_mut()) {
+            output.fill(0_f32);
+
+            for (gain, input) in row.iter().zip(inputs) {
+                if *gain != 0_f32 {
+                    for (output, input) in output.iter_mut().zip(input) {
+                        *output += input * gain;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(routing: Routing, inputs: usize, outputs: usize) -> Vec<Vec<f32>> {
+        routing.matrix(inputs, outputs).rows
+    }
+
+    #[test]
+    fn direct_maps_by_position_and_spreads_mono() {
+        assert_eq!(
+            rows(Routing::Direct, 2, 3),
+            [[1_f32, 0_f32], [0_f32, 1_f32], [0_f32, 0_f32]]
+        );
+        assert_eq!(rows(Routing::Direct, 1, 2), [[1_f32], [1_f32]]);
+    }
+
+    #[test]
+    fn itu_downmix_uses_the_standard_coefficients() {
+        let h = FRAC_1_SQRT_2;
+        // left, right, center, lfe, surround left, surround right
+        assert_eq!(
+            rows(Routing::ItuDownmix, 6, 2),
+            [
+                [1_f32, 0_f32, h, 0_f32, h, 0_f32],
+                [0_f32, 1_f32, h, 0_f32, 0_f32, h]
+            ]
+        );
+        assert_eq!(
+            rows(Routing::ItuDownmix, 4, 2),
+            [[1_f32, 0_f32, h, 0_f32], [0_f32, 1_f32, 0_f32, h]]
+        );
+        // stereo has nothing to fold down
+        assert_eq!(
+            rows(Routing::ItuDownmix, 2, 2),
+            [[1_f32, 0_f32], [0_f32, 1_f32]]
+        );
+    }
+
+    #[test]
+    fn mono_sum_averages_every_input() {
+        assert_eq!(rows(Routing::MonoSum, 4, 2), [[0.25_f32; 4], [0.25_f32; 4]]);
+    }
+
+    #[test]
+    fn swap_stereo_crosses_the_pair_only() {
+        assert_eq!(
+            rows(Routing::SwapStereo, 3, 3),
+            [
+                [0_f32, 1_f32, 0_f32],
+                [1_f32, 0_f32, 0_f32],
+                [0_f32, 0_f32, 1_f32]
+            ]
+        );
+        assert_eq!(rows(Routing::SwapStereo, 1, 2), [[1_f32], [0_f32]]);
+    }
+
+    #[test]
+    fn duplicate_stereo_repeats_the_pair() {
+        assert_eq!(
+            rows(Routing::DuplicateStereo, 2, 4),
+            [
+                [1_f32, 0_f32],
+                [0_f32, 1_f32],
+                [1_f32, 0_f32],
+                [0_f32, 1_f32]
+            ]
+        );
+        assert_eq!(rows(Routing::DuplicateStereo, 1, 2), [[1_f32], [1_f32]]);
+    }
+
+    #[test]
+    fn custom_rows_are_clipped_to_the_layout() {
+        let custom = Routing::Custom(vec![vec![0.5, 0.5, 9.0], vec![2.0]]);
+        assert_eq!(
+            rows(custom, 2, 3),
+            [[0.5_f32, 0.5_f32], [2_f32, 0_f32], [0_f32, 0_f32]]
+        );
+    }
+
+    #[test]
+    fn no_inputs_route_to_silence() {
+        for routing in [
+            Routing::Direct,
+            Routing::ItuDownmix,
+            Routing::MonoSum,
+            Routing::SwapStereo,
+            Routing::DuplicateStereo,
+            Routing::Custom(vec![vec![1.0]]),
+        ] {
+            let matrix = routing.matrix(0, 2);
+            let mut outputs = vec![vec![1_f32; 4]; 2];
+            matrix.process(&[], &mut outputs);
+            assert!(outputs.iter().flatten().all(|sample| *sample == 0_f32));
+        }
+    }
+
+    #[test]
+    fn the_matrix_mixes_the_inputs() {
+        let matrix = Routing::MonoSum.matrix(2, 1);
+        let mut outputs = vec![vec![0_f32; 2]];
+        matrix.process(&[vec![1_f32, 0_f32], vec![0_f32, 1_f32]], &mut outputs);
+        assert_eq!(outputs, [[0.5_f32, 0.5_f32]]);
+    }
+}
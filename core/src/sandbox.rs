@@ -0,0 +1,1435 @@
+use crate::error::ErrorKind;
+use crate::signal::Signal;
+use crate::{BLOCK_SIZE, Result};
+use log::{error, info, warn};
+use memmap2::MmapRaw;
+use serde::{Deserialize, Serialize};
+use std::env::{current_exe, temp_dir};
+use std::ffi::c_void;
+use std::fs::{OpenOptions, remove_file};
+use std::io::{BufRead, BufReader, Write, stdin, stdout};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, ChildStdin, Stdio};
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, channel};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+use vst::buffer::AudioBuffer;
+use vst::editor::Editor;
+use vst::host::{Host, HostBuffer};
+use vst::plugin::{HostCallback, Info, Plugin, PluginParameters};
+
+/// the argument that starts the executable as a plugin sandbox, followed by the plugin path
+pub const SANDBOX_ARGUMENT: &str = "sandbox";
+
+/// how long the audio thread waits for a block before the sandbox counts as hung
+const BLOCK_TIMEOUT: Duration = Duration::from_millis(50);
+/// how long a command may take, loading a chunk can be slow
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+/// how often the supervisor checks the sandbox
+const SUPERVISE_INTERVAL: Duration = Duration::from_millis(100);
+/// a sandbox that fails within this time of its last restart counts as crashing in a loop
+const STABLE_RUN: Duration = Duration::from_secs(10);
+/// the restarts in a loop after which the plugin stays bypassed
+const MAX_RESTARTS: usize = 3;
+/// how long the sandbox sleeps waiting for a block before it looks again
+const IDLE_WAIT: Duration = Duration::from_millis(100);
+/// how often the sandbox idles an open editor and dispatches its window messages
+const EDITOR_IDLE: Duration = Duration::from_millis(15);
+/// the blocks between refreshes of the parameter values the host reads
+const REFRESH_BLOCKS: usize = 16;
+
+/// the offsets of the block counters and the frame count in the shared memory, the counters
+/// wrap around and are only compared for equality
+const REQUEST: usize = 0;
+const RESPONSE: usize = 8;
+const FRAMES: usize = 16;
+/// the bytes before the parameter tables
+const HEADER_SIZE: usize = 64;
+
+/// the plugin metadata the sandbox reports once the plugin has loaded
+#[derive(Serialize, Deserialize, Clone)]
+struct SandboxInfo {
+    name: String,
+    vendor: String,
+    unique_id: i32,
+    version: i32,
+    presets: i32,
+    inputs: i32,
+    outputs: i32,
+    initial_delay: i32,
+    preset_chunks: bool,
+    parameter_names: Vec<String>,
+    editor: bool,
+}
+
+impl SandboxInfo {
+    fn new(info: &Info, parameters: &dyn PluginParameters, editor: bool) -> Self {
+        SandboxInfo {
+            name: info.name.clone(),
+            vendor: info.vendor.clone(),
+            unique_id: info.unique_id,
+            version: info.version,
+            presets: info.presets,
+            inputs: info.inputs,
+            outputs: info.outputs,
+            initial_delay: info.initial_delay,
+            preset_chunks: info.preset_chunks,
+            parameter_names: (0..info.parameters.max(0))
+                .map(|index| parameters.get_parameter_name(index))
+                .collect(),
+            editor,
+        }
+    }
+
+    fn info(&self) -> Info {
+        Info {
+            name: self.name.clone(),
+            vendor: self.vendor.clone(),
+            unique_id: self.unique_id,
+            version: self.version,
+            presets: self.presets,
+            parameters: self.parameter_names.len() as i32,
+            inputs: self.inputs,
+            outputs: self.outputs,
+            initial_delay: self.initial_delay,
+            preset_chunks: self.preset_chunks,
+            ..Default::default()
+        }
+    }
+
+    fn layout(&self) -> Layout {
+        Layout {
+            parameters: self.parameter_names.len(),
+            inputs: self.inputs.max(0) as usize,
+            outputs: self.outputs.max(0) as usize,
+        }
+    }
+}
+
+/// the calls the host makes into the sandbox, sent as json lines with an id
+#[derive(Serialize, Deserialize)]
+enum Command {
+    /// maps the shared memory at the path
+    Attach(PathBuf),
+    SetSampleRate(f32),
+    SetBlockSize(i64),
+    Init,
+    Resume,
+    Suspend,
+    GetPresetNum,
+    ChangePreset(i32),
+    GetPresetName(i32),
+    SetPresetName(String),
+    GetParameterLabel(i32),
+    GetParameterText(i32),
+    GetPresetData,
+    GetBankData,
+    LoadPresetData(Vec<u8>),
+    LoadBankData(Vec<u8>),
+    EditorRect,
+    /// opens the editor inside the window of the host with the handle
+    OpenEditor(u64),
+    CloseEditor,
+}
+
+/// what the sandbox sends back, as json lines
+#[derive(Serialize, Deserialize)]
+enum Message {
+    /// the answer to the command with the id, the info is sent unasked with id zero
+    Reply(u64, Reply),
+    Automate(i32, f32),
+    UpdateDisplay,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Reply {
+    Done,
+    Info(SandboxInfo),
+    Number(i32),
+    Text(String),
+    Data(Vec<u8>),
+    /// the position and size of the editor
+    Rect(i32, i32, i32, i32),
+}
+
+/// the byte offsets of the tables in the shared memory
+///
+/// the header holds the block counters, followed by the parameter values the host requested,
+/// their dirty flags, the values the plugin currently has and a block of audio each way
+#[derive(Clone, Copy, PartialEq)]
+struct Layout {
+    parameters: usize,
+    inputs: usize,
+    outputs: usize,
+}
+
+impl Layout {
+    fn requested(&self, index: usize) -> usize {
+        HEADER_SIZE + index * 4
+    }
+
+    fn dirty(&self, index: usize) -> usize {
+        self.requested(self.parameters) + index * 4
+    }
+
+    fn current(&self, index: usize) -> usize {
+        self.dirty(self.parameters) + index * 4
+    }
+
+    fn input(&self, channel: usize) -> usize {
+        self.current(self.parameters) + channel * BLOCK_SIZE * 4
+    }
+
+    fn output(&self, channel: usize) -> usize {
+        self.input(self.inputs) + channel * BLOCK_SIZE * 4
+    }
+
+    fn size(&self) -> usize {
+        self.output(self.outputs)
+    }
+}
+
+/// memory mapped by the host and its sandbox, only ever accessed through atomics
+struct SharedMemory {
+    map: MmapRaw,
+    layout: Layout,
+    /// raised by the host when it requested a block
+    requested: Signal,
+    /// raised by the sandbox when it answered a block
+    responded: Signal,
+    /// declared after the mapping, windows cannot remove a file while it is mapped
+    file: SharedFile,
+}
+
+/// the file behind the shared memory, removed by the host once the plugin is dropped
+struct SharedFile {
+    path: PathBuf,
+    owner: bool,
+}
+
+impl SharedMemory {
+    /// creates a zeroed file in the temporary directory for the host
+    fn create(layout: Layout) -> Result<Self> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = temp_dir().join(format!(
+            "whisper_ware_{}_{}.shm",
+            process::id(),
+            COUNT.fetch_add(1, Relaxed)
+        ));
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        file.set_len(layout.size() as u64)?;
+        let map = MmapRaw::map_raw(&file)?;
+        let (requested, responded) = signal_names(&path);
+
+        Ok(SharedMemory {
+            map,
+            layout,
+            requested: Signal::create(&requested)?,
+            responded: Signal::create(&responded)?,
+            file: SharedFile { path, owner: true },
+        })
+    }
+
+    /// maps the file the host created
+    fn open(path: &Path, layout: Layout) -> Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let map = MmapRaw::map_raw(&file)?;
+        if map.len() < layout.size() {
+            Err(ErrorKind::Sandbox(
+                "the shared memory is too small for the plugin",
+            ))?;
+        }
+        let (requested, responded) = signal_names(path);
+
+        Ok(SharedMemory {
+            map,
+            layout,
+            requested: Signal::open(&requested)?,
+            responded: Signal::open(&responded)?,
+            file: SharedFile {
+                path: path.to_path_buf(),
+                owner: false,
+            },
+        })
+    }
+
+    fn word(&self, offset: usize) -> &AtomicU32 {
+        assert!(offset + 4 <= self.map.len());
+        // SAFETY: the mapping is page aligned and lives as long as self, every table starts at
+        // a multiple of four and both processes only access the words atomically
+        unsafe { AtomicU32::from_ptr(self.map.as_mut_ptr().add(offset).cast()) }
+    }
+
+    fn write(&self, offset: usize, samples: &[f32]) {
+        for (index, sample) in samples.iter().enumerate() {
+            self.word(offset + index * 4)
+                .store(sample.to_bits(), Relaxed);
+        }
+    }
+
+    fn read(&self, offset: usize, samples: &mut [f32]) {
+        for (index, sample) in samples.iter_mut().enumerate() {
+            *sample = f32::from_bits(self.word(offset + index * 4).load(Relaxed));
+        }
+    }
+
+    fn parameter(&self, offset: usize) -> f32 {
+        f32::from_bits(self.word(offset).load(Relaxed))
+    }
+
+    /// requests a parameter value the sandbox sets before its next block
+    fn request_parameter(&self, index: usize, value: f32) {
+        self.word(self.layout.requested(index))
+            .store(value.to_bits(), Relaxed);
+        self.word(self.layout.dirty(index)).store(1, Release);
+        // reads see the value before the sandbox confirms it
+        self.word(self.layout.current(index))
+            .store(value.to_bits(), Relaxed);
+    }
+
+    /// sets the requested parameters on the plugin, returns whether there were any
+    fn apply(&self, parameters: &dyn PluginParameters) -> bool {
+        let mut applied = false;
+        for index in 0..self.layout.parameters {
+            if self.word(self.layout.dirty(index)).swap(0, Acquire) != 0 {
+                let value = self.parameter(self.layout.requested(index));
+                parameters.set_parameter(index as i32, value);
+                applied = true;
+            }
+        }
+        applied
+    }
+
+    /// stores the values the plugin has for the host to read
+    fn publish(&self, parameters: &dyn PluginParameters) {
+        for index in 0..self.layout.parameters {
+            let value = parameters.get_parameter(index as i32);
+            self.word(self.layout.current(index))
+                .store(value.to_bits(), Relaxed);
+        }
+    }
+}
+
+/// the names of the signals of the shared memory at path, which both processes derive
+fn signal_names(path: &Path) -> (String, String) {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    (format!("{stem}_request"), format!("{stem}_response"))
+}
+
+impl Drop for SharedFile {
+    fn drop(&mut self) {
+        if self.owner {
+            let _ = remove_file(&self.path);
+        }
+    }
+}
+
+/// the running sandbox process and the pipe its commands go through
+struct Link {
+    child: Child,
+    commands: ChildStdin,
+}
+
+/// the stream settings and state replayed to a restarted sandbox
+#[derive(Default)]
+struct Replay {
+    sample_rate: Option<f32>,
+    block_size: Option<i64>,
+    initialized: bool,
+    resumed: bool,
+    /// the chunk last saved or loaded, restored before the parameter values
+    chunk: Option<Vec<u8>>,
+    /// the window the editor is open in
+    editor: Option<u64>,
+}
+
+/// builds the command that starts a sandbox for the plugin
+type Launcher = fn(&Path) -> Result<process::Command>;
+
+/// the host side of a sandbox, shared by the plugin, its parameter object, its editor and the
+/// supervisor
+struct Sandbox {
+    plugin: PathBuf,
+    launcher: Launcher,
+    info: SandboxInfo,
+    shared: SharedMemory,
+    host: Arc<Mutex<dyn Host + Send>>,
+    /// none once the sandbox failed too often to restart
+    link: Mutex<Option<Link>>,
+    replies: Mutex<Receiver<(u64, Reply)>>,
+    sender: Sender<(u64, Reply)>,
+    next_command: AtomicU64,
+    /// set when the sandbox stopped answering or exited, the audio passes through until it is
+    /// restarted
+    failed: AtomicBool,
+    replay: Mutex<Replay>,
+}
+
+impl Sandbox {
+    /// sends a command and waits for its reply
+    fn request(&self, command: Command) -> Result<Reply> {
+        if self.failed.load(Acquire) {
+            Err(ErrorKind::Sandbox("the plugin sandbox is not running"))?;
+        }
+
+        let mut link = self.link.lock().unwrap();
+        let link = link
+            .as_mut()
+            .ok_or(ErrorKind::Sandbox("the plugin sandbox is not running"))?;
+        self.call(link, command)
+    }
+
+    fn call(&self, link: &mut Link, command: Command) -> Result<Reply> {
+        let id = self.next_command.fetch_add(1, Relaxed);
+        let mut line = serde_json::to_vec(&(id, command))?;
+        line.push(b'\n');
+        link.commands.write_all(&line)?;
+        link.commands.flush()?;
+
+        receive(&self.replies.lock().unwrap(), id)
+    }
+
+    /// sends a command, logging when the sandbox does not answer
+    fn ask(&self, command: Command) -> Option<Reply> {
+        self.request(command)
+            .inspect_err(|error| {
+                warn!(
+                    "the sandbox of {} did not answer: {}",
+                    self.plugin.display(),
+                    error
+                )
+            })
+            .ok()
+    }
+
+    /// starts a new sandbox and brings it to the state of the last one
+    fn restart(&self) -> Result<Link> {
+        let mut link = launch(self.launcher, &self.plugin, &self.host, &self.sender)?;
+        let result = self.restore(&mut link);
+        if result.is_err() {
+            let _ = link.child.kill();
+            let _ = link.child.wait();
+        }
+        result.map(|()| link)
+    }
+
+    fn restore(&self, link: &mut Link) -> Result<()> {
+        match receive(&self.replies.lock().unwrap(), 0)? {
+            Reply::Info(info) if info.layout() == self.info.layout() => {}
+            _ => Err(ErrorKind::Sandbox("the plugin changed since it was loaded"))?,
+        }
+
+        // the values the plugin last had are set again once its chunk is loaded
+        let layout = self.shared.layout;
+        for index in 0..layout.parameters {
+            let value = self.shared.parameter(layout.current(index));
+            self.shared.request_parameter(index, value);
+        }
+        self.call(link, Command::Attach(self.shared.file.path.clone()))?;
+
+        let replay = self.replay.lock().unwrap();
+        if let Some(rate) = replay.sample_rate {
+            self.call(link, Command::SetSampleRate(rate))?;
+        }
+        if let Some(size) = replay.block_size {
+            self.call(link, Command::SetBlockSize(size))?;
+        }
+        if replay.initialized {
+            self.call(link, Command::Init)?;
+        }
+        if let Some(chunk) = replay.chunk.clone()
+            && self.info.preset_chunks
+        {
+            self.call(link, Command::LoadPresetData(chunk))?;
+        }
+        if replay.resumed {
+            self.call(link, Command::Resume)?;
+        }
+        if let Some(parent) = replay.editor {
+            self.call(link, Command::OpenEditor(parent))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        if let Some(link) = self.link.get_mut().unwrap().as_mut() {
+            let _ = link.child.kill();
+            let _ = link.child.wait();
+        }
+    }
+}
+
+impl PluginParameters for Sandbox {
+    fn get_parameter_label(&self, index: i32) -> String {
+        match self.ask(Command::GetParameterLabel(index)) {
+            Some(Reply::Text(label)) => label,
+            _ => String::new(),
+        }
+    }
+
+    fn get_parameter_text(&self, index: i32) -> String {
+        match self.ask(Command::GetParameterText(index)) {
+            Some(Reply::Text(text)) => text,
+            _ => String::new(),
+        }
+    }
+
+    fn get_parameter_name(&self, index: i32) -> String {
+        self.info
+            .parameter_names
+            .get(index as usize)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn get_parameter(&self, index: i32) -> f32 {
+        let layout = self.shared.layout;
+        if (0..layout.parameters as i32).contains(&index) {
+            self.shared.parameter(layout.current(index as usize))
+        } else {
+            0_f32
+        }
+    }
+
+    /// stored in the shared memory, so the audio thread can set parameters without a command
+    fn set_parameter(&self, index: i32, value: f32) {
+        if (0..self.shared.layout.parameters as i32).contains(&index) {
+            self.shared.request_parameter(index as usize, value);
+        }
+    }
+
+    fn change_preset(&self, preset: i32) {
+        self.ask(Command::ChangePreset(preset));
+    }
+
+    fn get_preset_num(&self) -> i32 {
+        match self.ask(Command::GetPresetNum) {
+            Some(Reply::Number(preset)) => preset,
+            _ => 0,
+        }
+    }
+
+    fn set_preset_name(&self, name: String) {
+        self.ask(Command::SetPresetName(name));
+    }
+
+    fn get_preset_name(&self, preset: i32) -> String {
+        match self.ask(Command::GetPresetName(preset)) {
+            Some(Reply::Text(name)) => name,
+            _ => String::new(),
+        }
+    }
+
+    fn get_preset_data(&self) -> Vec<u8> {
+        match self.ask(Command::GetPresetData) {
+            Some(Reply::Data(chunk)) => {
+                self.replay.lock().unwrap().chunk = Some(chunk.clone());
+                chunk
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn get_bank_data(&self) -> Vec<u8> {
+        match self.ask(Command::GetBankData) {
+            Some(Reply::Data(chunk)) => chunk,
+            _ => Vec::new(),
+        }
+    }
+
+    fn load_preset_data(&self, data: &[u8]) {
+        self.replay.lock().unwrap().chunk = Some(data.to_vec());
+        self.ask(Command::LoadPresetData(data.to_vec()));
+    }
+
+    fn load_bank_data(&self, data: &[u8]) {
+        self.ask(Command::LoadBankData(data.to_vec()));
+    }
+}
+
+/// a plugin hosted in a child process, so a crash inside the plugin cannot take down
+/// WhisperWare
+///
+/// the audio and parameter values are exchanged through shared memory, everything else is a
+/// command over the pipes of the child. when the child exits or stops answering, the audio
+/// passes through unprocessed while it is restarted with the sample rate, chunk and parameter
+/// values it had. a plugin that keeps crashing stays bypassed. the editor opens inside the
+/// window the host passes, the child runs it
+pub struct SandboxedPlugin {
+    sandbox: Arc<Sandbox>,
+    is_editor_active: bool,
+}
+
+impl SandboxedPlugin {
+    /// starts the executable with [`SANDBOX_ARGUMENT`] and the plugin path, it is expected to
+    /// load the plugin and call [`serve_sandbox`]
+    pub fn spawn<T: Host + Send + 'static>(plugin: &Path, host: Arc<Mutex<T>>) -> Result<Self> {
+        Self::spawn_with(sandbox_command, plugin, host)
+    }
+
+    fn spawn_with<T: Host + Send + 'static>(
+        launcher: Launcher,
+        plugin: &Path,
+        host: Arc<Mutex<T>>,
+    ) -> Result<Self> {
+        let host: Arc<Mutex<dyn Host + Send>> = host;
+        let (sender, replies) = channel();
+        let mut link = launch(launcher, plugin, &host, &sender)?;
+
+        let info = match receive(&replies, 0) {
+            Ok(Reply::Info(info)) => info,
+            result => {
+                let _ = link.child.kill();
+                let _ = link.child.wait();
+                result?;
+                return Err(
+                    ErrorKind::Sandbox("the plugin sandbox did not report the plugin").into(),
+                );
+            }
+        };
+        let shared = SharedMemory::create(info.layout())?;
+
+        let sandbox = Arc::new(Sandbox {
+            plugin: plugin.to_path_buf(),
+            launcher,
+            info,
+            shared,
+            host,
+            link: Mutex::new(Some(link)),
+            replies: Mutex::new(replies),
+            sender,
+            next_command: AtomicU64::new(1),
+            failed: AtomicBool::new(false),
+            replay: Mutex::new(Replay::default()),
+        });
+        sandbox.request(Command::Attach(sandbox.shared.file.path.clone()))?;
+
+        let weak = Arc::downgrade(&sandbox);
+        spawn(move || supervise(weak));
+
+        info!("{} loaded in a sandbox", plugin.display());
+        Ok(SandboxedPlugin {
+            sandbox,
+            is_editor_active: false,
+        })
+    }
+
+    /// sends a command that changes the stream state, logging when it fails
+    fn forward(&self, command: Command) {
+        self.sandbox.ask(command);
+    }
+}
+
+impl Plugin for SandboxedPlugin {
+    fn get_info(&self) -> Info {
+        self.sandbox.info.info()
+    }
+
+    fn new(_host: HostCallback) -> Self {
+        // sandboxed plugins are only created by the host with SandboxedPlugin::spawn
+        unreachable!()
+    }
+
+    fn init(&mut self) {
+        self.sandbox.replay.lock().unwrap().initialized = true;
+        self.forward(Command::Init);
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.sandbox.replay.lock().unwrap().sample_rate = Some(rate);
+        self.forward(Command::SetSampleRate(rate));
+    }
+
+    fn set_block_size(&mut self, size: i64) {
+        self.sandbox.replay.lock().unwrap().block_size = Some(size);
+        self.forward(Command::SetBlockSize(size));
+    }
+
+    fn resume(&mut self) {
+        self.sandbox.replay.lock().unwrap().resumed = true;
+        self.forward(Command::Resume);
+    }
+
+    fn suspend(&mut self) {
+        self.sandbox.replay.lock().unwrap().resumed = false;
+        self.forward(Command::Suspend);
+    }
+
+    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        let sandbox = &self.sandbox;
+        let shared = &sandbox.shared;
+        let layout = shared.layout;
+        let frames = buffer.samples().min(BLOCK_SIZE);
+        let (inputs, mut outputs) = buffer.split();
+
+        if !sandbox.failed.load(Acquire) {
+            for (channel, input) in inputs.into_iter().enumerate().take(layout.inputs) {
+                shared.write(layout.input(channel), &input[..frames]);
+            }
+            shared.word(FRAMES).store(frames as u32, Relaxed);
+            let request = shared.word(REQUEST).load(Relaxed).wrapping_add(1);
+            shared.word(REQUEST).store(request, Release);
+            shared.requested.raise(shared.word(REQUEST));
+
+            if wait_for_response(sandbox, request) {
+                for (channel, output) in (&mut outputs).into_iter().enumerate() {
+                    shared.read(layout.output(channel), &mut output[..frames]);
+                }
+                return;
+            }
+            // the supervisor already logged a sandbox that exited
+            if !sandbox.failed.swap(true, AcqRel) {
+                error!(
+                    "the sandbox of {} did not process a block in time",
+                    sandbox.plugin.display()
+                );
+            }
+        }
+
+        // the main channels pass through while the sandbox restarts
+        for (channel, output) in (&mut outputs).into_iter().enumerate() {
+            if channel < inputs.len() {
+                output.copy_from_slice(inputs.get(channel));
+            } else {
+                output.fill(0_f32);
+            }
+        }
+    }
+
+    fn get_parameter_object(&mut self) -> Arc<dyn PluginParameters> {
+        Arc::clone(&self.sandbox) as Arc<dyn PluginParameters>
+    }
+
+    fn get_editor(&mut self) -> Option<Box<dyn Editor>> {
+        // the caller keeps using the editor it already has
+        if !self.sandbox.info.editor || self.is_editor_active {
+            return None;
+        }
+        self.is_editor_active = true;
+        Some(Box::new(SandboxEditor {
+            sandbox: Arc::clone(&self.sandbox),
+            is_open: false,
+        }))
+    }
+}
+
+/// the editor of a sandboxed plugin, a window of the child inside the window of the host
+struct SandboxEditor {
+    sandbox: Arc<Sandbox>,
+    is_open: bool,
+}
+
+impl SandboxEditor {
+    fn rect(&self) -> (i32, i32, i32, i32) {
+        match self.sandbox.ask(Command::EditorRect) {
+            Some(Reply::Rect(x, y, width, height)) => (x, y, width, height),
+            _ => (0, 0, 0, 0),
+        }
+    }
+}
+
+impl Editor for SandboxEditor {
+    fn size(&self) -> (i32, i32) {
+        let (_, _, width, height) = self.rect();
+        (width, height)
+    }
+
+    fn position(&self) -> (i32, i32) {
+        let (x, y, _, _) = self.rect();
+        (x, y)
+    }
+
+    /// reopened in the same window when the sandbox restarts
+    fn open(&mut self, parent: *mut c_void) -> bool {
+        let parent = parent as u64;
+        self.is_open = matches!(
+            self.sandbox.ask(Command::OpenEditor(parent)),
+            Some(Reply::Number(1))
+        );
+        if self.is_open {
+            self.sandbox.replay.lock().unwrap().editor = Some(parent);
+        }
+        self.is_open
+    }
+
+    fn close(&mut self) {
+        self.sandbox.replay.lock().unwrap().editor = None;
+        self.sandbox.ask(Command::CloseEditor);
+        self.is_open = false;
+    }
+
+    fn is_open(&mut self) -> bool {
+        self.is_open
+    }
+}
+
+/// sleeps until the sandbox answered the request, false when it took too long or exited
+fn wait_for_response(sandbox: &Sandbox, request: u32) -> bool {
+    let deadline = Instant::now() + BLOCK_TIMEOUT;
+    let response = sandbox.shared.word(RESPONSE);
+    loop {
+        let current = response.load(Acquire);
+        if current == request {
+            return true;
+        }
+        // the supervisor fails the sandbox when it exits and raises the signal
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() || sandbox.failed.load(Acquire) {
+            return false;
+        }
+        sandbox.shared.responded.wait(response, current, remaining);
+    }
+}
+
+/// loads a plugin in a sandbox just long enough to read its info, so a library that crashes
+/// or hangs while loading cannot take down WhisperWare
+pub(crate) fn probe<T: Host + Send + 'static>(plugin: &Path, host: Arc<Mutex<T>>) -> Result<Info> {
+    probe_with(sandbox_command, plugin, host)
+}
+
+fn probe_with<T: Host + Send + 'static>(
+    launcher: Launcher,
+    plugin: &Path,
+    host: Arc<Mutex<T>>,
+) -> Result<Info> {
+    let host: Arc<Mutex<dyn Host + Send>> = host;
+    let (sender, replies) = channel();
+    let mut link = launch(launcher, plugin, &host, &sender)?;
+    // a child that exits disconnects the replies instead of leaving them to time out
+    drop(sender);
+
+    let reply = receive(&replies, 0);
+    let _ = link.child.kill();
+    let _ = link.child.wait();
+    match reply? {
+        Reply::Info(info) => Ok(info.info()),
+        _ => Err(ErrorKind::Sandbox("the plugin sandbox did not report the plugin").into()),
+    }
+}
+
+/// the command that starts the executable as the sandbox of a plugin
+fn sandbox_command(plugin: &Path) -> Result<process::Command> {
+    let mut command = process::Command::new(current_exe()?);
+    command.arg(SANDBOX_ARGUMENT).arg(plugin);
+    Ok(command)
+}
+
+/// starts a sandbox process and the thread reading its messages
+fn launch(
+    launcher: Launcher,
+    plugin: &Path,
+    host: &Arc<Mutex<dyn Host + Send>>,
+    replies: &Sender<(u64, Reply)>,
+) -> Result<Link> {
+    let mut child = launcher(plugin)?
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let commands = child.stdin.take().unwrap();
+    let messages = child.stdout.take().unwrap();
+
+    let host = Arc::clone(host);
+    let replies = replies.clone();
+    spawn(move || {
+        for line in BufReader::new(messages).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            match serde_json::from_str(&line) {
+                Ok(Message::Reply(id, reply)) => {
+                    let _ = replies.send((id, reply));
+                }
+                Ok(Message::Automate(index, value)) => host.lock().unwrap().automate(index, value),
+                Ok(Message::UpdateDisplay) => host.lock().unwrap().update_display(),
+                // plugins sometimes print to the standard output themselves
+                Err(_) => warn!("the plugin sandbox printed: {}", line),
+            }
+        }
+    });
+
+    Ok(Link { child, commands })
+}
+
+/// waits for the reply to a command, skipping the late replies to commands that timed out
+fn receive(replies: &Receiver<(u64, Reply)>, id: u64) -> Result<Reply> {
+    let deadline = Instant::now() + COMMAND_TIMEOUT;
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match replies.recv_timeout(timeout) {
+            Ok((reply_id, reply)) if reply_id == id => return Ok(reply),
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => Err(ErrorKind::Sandbox(
+                "the plugin sandbox did not answer in time",
+            ))?,
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(ErrorKind::Sandbox("the plugin sandbox exited"))?
+            }
+        }
+    }
+}
+
+/// restarts the sandbox whenever it exits or stops answering, until the plugin is dropped or
+/// fails too often
+fn supervise(sandbox: Weak<Sandbox>) {
+    let mut restarts = 0;
+    let mut last_restart = Instant::now();
+
+    loop {
+        sleep(SUPERVISE_INTERVAL);
+        let Some(sandbox) = sandbox.upgrade() else {
+            return;
+        };
+        let mut link = sandbox.link.lock().unwrap();
+        let Some(current) = link.as_mut() else {
+            return;
+        };
+
+        let problem = match current.child.try_wait() {
+            Ok(Some(status)) => format!("exited with {}", status),
+            Ok(None) if sandbox.failed.load(Acquire) => {
+                let _ = current.child.kill();
+                let _ = current.child.wait();
+                "stopped responding".to_string()
+            }
+            Ok(None) => continue,
+            Err(error) => format!("could not be checked ({})", error),
+        };
+        sandbox.failed.store(true, Release);
+        // the audio thread stops waiting for the block
+        let shared = &sandbox.shared;
+        shared.responded.raise(shared.word(RESPONSE));
+        error!(
+            "the sandbox of {} {}, passing the audio through until it restarts",
+            sandbox.plugin.display(),
+            problem
+        );
+
+        restarts = if last_restart.elapsed() < STABLE_RUN {
+            restarts + 1
+        } else {
+            1
+        };
+        last_restart = Instant::now();
+        if restarts > MAX_RESTARTS {
+            error!(
+                "the sandbox of {} keeps failing, the plugin stays bypassed",
+                sandbox.plugin.display()
+            );
+            *link = None;
+            return;
+        }
+
+        match sandbox.restart() {
+            Ok(restarted) => {
+                *link = Some(restarted);
+                sandbox.failed.store(false, Release);
+                warn!("the sandbox of {} restarted", sandbox.plugin.display());
+            }
+            Err(error) => error!(
+                "failed to restart the sandbox of {}: {}",
+                sandbox.plugin.display(),
+                error
+            ),
+        }
+    }
+}
+
+/// the host of a plugin inside a sandbox, forwarding its callbacks to the host process
+#[derive(Default)]
+pub struct SandboxHost;
+
+impl Host for SandboxHost {
+    fn automate(&self, index: i32, value: f32) {
+        let _ = send(&Message::Automate(index, value));
+    }
+
+    fn get_block_size(&self) -> isize {
+        BLOCK_SIZE as isize
+    }
+
+    fn update_display(&self) {
+        let _ = send(&Message::UpdateDisplay);
+    }
+}
+
+/// writes a message to the host process
+fn send(message: &Message) -> Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    let mut stdout = stdout().lock();
+    stdout.write_all(&line)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// runs a plugin for a [`SandboxedPlugin`] in the host process until the host goes away
+///
+/// called in the process the host started with [`SANDBOX_ARGUMENT`], with the plugin loaded
+/// with a [`SandboxHost`]. the standard input and output carry the commands, so nothing else
+/// may write to the standard output. the blocks are processed on their own thread, which
+/// sleeps until the host requests one, so slow commands such as saving a chunk never hold up
+/// the audio. the commands and the editor run on the calling thread, which calls pump to
+/// dispatch the window messages of the editor while it is open
+pub fn serve_sandbox(mut instance: Box<dyn Plugin>, mut pump: impl FnMut()) -> Result<()> {
+    let parameters = instance.get_parameter_object();
+    let mut editor = instance.get_editor();
+    let info = SandboxInfo::new(&instance.get_info(), parameters.as_ref(), editor.is_some());
+    let layout = info.layout();
+    send(&Message::Reply(0, Reply::Info(info)))?;
+
+    // the reader ends when the host closes the pipe, which ends the sandbox
+    let (sender, commands) = channel();
+    spawn(move || {
+        for line in stdin().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            match serde_json::from_str::<(u64, Command)>(&line) {
+                Ok(command) => {
+                    if sender.send(command).is_err() {
+                        break;
+                    }
+                }
+                Err(error) => warn!("unreadable command from the host: {}", error),
+            }
+        }
+    });
+
+    let instance = Arc::new(Mutex::new(instance));
+    let mut shared: Option<Arc<SharedMemory>> = None;
+    let mut editor_open = false;
+
+    loop {
+        let next = match editor_open {
+            true => commands.recv_timeout(EDITOR_IDLE),
+            false => commands.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        let (id, command) = match next {
+            Ok(command) => command,
+            Err(RecvTimeoutError::Timeout) => {
+                if let Some(editor) = editor.as_mut() {
+                    editor.idle();
+                }
+                pump();
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+
+        let reply = match command {
+            Command::Attach(path) => {
+                let memory = Arc::new(SharedMemory::open(&path, layout)?);
+                memory.publish(parameters.as_ref());
+                // read before the reply, the host requests blocks as soon as it has it
+                let served = memory.word(REQUEST).load(Acquire);
+                let (blocks_memory, blocks_instance) = (Arc::clone(&memory), Arc::clone(&instance));
+                spawn(move || serve_blocks(&blocks_memory, &blocks_instance, served));
+                shared = Some(memory);
+                Reply::Done
+            }
+            Command::SetSampleRate(rate) => {
+                instance.lock().unwrap().set_sample_rate(rate);
+                Reply::Done
+            }
+            Command::SetBlockSize(size) => {
+                instance.lock().unwrap().set_block_size(size);
+                Reply::Done
+            }
+            Command::Init => {
+                instance.lock().unwrap().init();
+                Reply::Done
+            }
+            Command::Resume => {
+                instance.lock().unwrap().resume();
+                Reply::Done
+            }
+            Command::Suspend => {
+                instance.lock().unwrap().suspend();
+                Reply::Done
+            }
+            Command::GetPresetNum => Reply::Number(parameters.get_preset_num()),
+            Command::ChangePreset(preset) => {
+                parameters.change_preset(preset);
+                Reply::Done
+            }
+            Command::GetPresetName(preset) => Reply::Text(parameters.get_preset_name(preset)),
+            Command::SetPresetName(name) => {
+                parameters.set_preset_name(name);
+                Reply::Done
+            }
+            Command::GetParameterLabel(index) => Reply::Text(parameters.get_parameter_label(index)),
+            Command::GetParameterText(index) => Reply::Text(parameters.get_parameter_text(index)),
+            Command::GetPresetData => Reply::Data(parameters.get_preset_data()),
+            Command::GetBankData => Reply::Data(parameters.get_bank_data()),
+            Command::LoadPresetData(chunk) => {
+                parameters.load_preset_data(&chunk);
+                Reply::Done
+            }
+            Command::LoadBankData(chunk) => {
+                parameters.load_bank_data(&chunk);
+                Reply::Done
+            }
+            Command::EditorRect => match editor.as_ref() {
+                Some(editor) => {
+                    let ((x, y), (width, height)) = (editor.position(), editor.size());
+                    Reply::Rect(x, y, width, height)
+                }
+                None => Reply::Rect(0, 0, 0, 0),
+            },
+            Command::OpenEditor(parent) => {
+                editor_open = editor
+                    .as_mut()
+                    .is_some_and(|editor| editor.open(parent as *mut c_void));
+                Reply::Number(editor_open as i32)
+            }
+            Command::CloseEditor => {
+                if let Some(editor) = editor.as_mut() {
+                    editor.close();
+                }
+                editor_open = false;
+                Reply::Done
+            }
+        };
+        // programs and chunks change the parameters
+        if let Some(memory) = &shared {
+            memory.publish(parameters.as_ref());
+        }
+        send(&Message::Reply(id, reply))?;
+        if editor_open {
+            pump();
+        }
+    }
+}
+
+/// processes the blocks the host requests, sleeping between them
+///
+/// served is the last request answered before the sandbox attached
+fn serve_blocks(memory: &SharedMemory, instance: &Mutex<Box<dyn Plugin>>, mut served: u32) {
+    // the parameter object is not sendable, this thread asks for its own
+    let parameters = instance.lock().unwrap().get_parameter_object();
+    let parameters = parameters.as_ref();
+    let layout = memory.layout;
+    let mut buffer = HostBuffer::new(layout.inputs, layout.outputs);
+    let mut inputs = vec![vec![0_f32; BLOCK_SIZE]; layout.inputs];
+    let mut outputs = vec![vec![0_f32; BLOCK_SIZE]; layout.outputs];
+    let mut blocks = 0_usize;
+
+    loop {
+        let request = memory.word(REQUEST).load(Acquire);
+        if request == served {
+            memory
+                .requested
+                .wait(memory.word(REQUEST), served, IDLE_WAIT);
+            continue;
+        }
+
+        let applied = memory.apply(parameters);
+        let frames = (memory.word(FRAMES).load(Relaxed) as usize).min(BLOCK_SIZE);
+        for (channel, input) in inputs.iter_mut().enumerate() {
+            memory.read(layout.input(channel), &mut input[..frames]);
+        }
+
+        let mut audio_buffer = buffer.bind(&inputs, &mut outputs);
+        instance.lock().unwrap().process(&mut audio_buffer);
+
+        for (channel, output) in outputs.iter().enumerate() {
+            memory.write(layout.output(channel), &output[..frames]);
+        }
+        if applied || blocks.is_multiple_of(REFRESH_BLOCKS) {
+            memory.publish(parameters);
+        }
+        memory.word(RESPONSE).store(request, Release);
+        memory.responded.raise(memory.word(RESPONSE));
+
+        served = request;
+        blocks += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::process;
+    use atomic_float::AtomicF32;
+    use std::env::var_os;
+    use std::thread::scope;
+    use vst::plugin::Category;
+
+    /// set in the environment of the test binary when it runs as a sandbox
+    const CHILD: &str = "WHISPER_WARE_SANDBOX_CHILD";
+    /// the sample that makes the test plugin crash
+    const CRASH: f32 = 1234_f32;
+    /// how long saving a chunk of the test plugin takes
+    const SLOW_CHUNK: Duration = Duration::from_millis(200);
+
+    /// starts the test binary running only the child test, as a sandbox
+    fn child_command(plugin: &Path) -> Result<process::Command> {
+        let mut command = process::Command::new(current_exe()?);
+        command
+            .args(["--exact", "sandbox::tests::child", "--nocapture", "--quiet"])
+            .env(CHILD, plugin);
+        Ok(command)
+    }
+
+    /// serves the test plugin when the test binary was started as a sandbox
+    #[test]
+    fn child() {
+        if var_os(CHILD).is_some() {
+            let result = serve_sandbox(Box::new(TestPlugin::default()), || {});
+            process::exit(result.is_err() as i32);
+        }
+    }
+
+    /// a gain with a chunk that is slow to save, which aborts on the crash sample
+    #[derive(Default)]
+    struct TestPlugin {
+        parameters: Arc<TestParameters>,
+    }
+
+    struct TestParameters {
+        gain: AtomicF32,
+    }
+
+    impl Default for TestParameters {
+        fn default() -> Self {
+            TestParameters {
+                gain: AtomicF32::new(1_f32),
+            }
+        }
+    }
+
+    impl Plugin for TestPlugin {
+        fn new(_host: HostCallback) -> Self {
+            TestPlugin::default()
+        }
+
+        fn get_info(&self) -> Info {
+            Info {
+                name: "Test Gain".to_string(),
+                parameters: 1,
+                inputs: 2,
+                outputs: 2,
+                preset_chunks: true,
+                category: Category::Effect,
+                ..Default::default()
+            }
+        }
+
+        fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+            let gain = self.parameters.gain.load(Relaxed);
+            for (input, output) in buffer.zip() {
+                if input.contains(&CRASH) {
+                    process::abort();
+                }
+                for (input, output) in input.iter().zip(output) {
+                    *output = input * gain;
+                }
+            }
+        }
+
+        fn get_parameter_object(&mut self) -> Arc<dyn PluginParameters> {
+            Arc::clone(&self.parameters) as Arc<dyn PluginParameters>
+        }
+
+        fn get_editor(&mut self) -> Option<Box<dyn Editor>> {
+            Some(Box::new(TestEditor))
+        }
+    }
+
+    impl PluginParameters for TestParameters {
+        fn get_parameter_name(&self, _index: i32) -> String {
+            "Gain".to_string()
+        }
+
+        fn get_parameter_text(&self, _index: i32) -> String {
+            format!("{:.2}", self.gain.load(Relaxed))
+        }
+
+        fn get_parameter(&self, _index: i32) -> f32 {
+            self.gain.load(Relaxed)
+        }
+
+        fn set_parameter(&self, _index: i32, value: f32) {
+            self.gain.store(value, Relaxed);
+        }
+
+        fn get_preset_data(&self) -> Vec<u8> {
+            sleep(SLOW_CHUNK);
+            self.gain.load(Relaxed).to_le_bytes().to_vec()
+        }
+
+        fn load_preset_data(&self, data: &[u8]) {
+            if let Ok(bytes) = data.try_into() {
+                self.gain.store(f32::from_le_bytes(bytes), Relaxed);
+            }
+        }
+    }
+
+    /// an editor without a window
+    struct TestEditor;
+
+    impl Editor for TestEditor {
+        fn size(&self) -> (i32, i32) {
+            (300, 200)
+        }
+
+        fn position(&self) -> (i32, i32) {
+            (0, 0)
+        }
+
+        fn open(&mut self, _parent: *mut c_void) -> bool {
+            true
+        }
+
+        fn is_open(&mut self) -> bool {
+            true
+        }
+    }
+
+    struct TestHost;
+
+    impl Host for TestHost {}
+
+    fn spawn_test_plugin() -> SandboxedPlugin {
+        let host = Arc::new(Mutex::new(TestHost));
+        SandboxedPlugin::spawn_with(child_command, Path::new("test_gain"), host).unwrap()
+    }
+
+    /// a stereo block of the value, and the left channel the plugin returned
+    fn block(plugin: &mut SandboxedPlugin, value: f32) -> Vec<f32> {
+        let input = vec![value; BLOCK_SIZE];
+        process(plugin, &[input.clone(), input], 2).swap_remove(0)
+    }
+
+    #[test]
+    fn probing_reads_the_info_in_a_child() {
+        let host = Arc::new(Mutex::new(TestHost));
+        let info = probe_with(child_command, Path::new("test_gain"), host).unwrap();
+        assert_eq!((info.name.as_str(), info.parameters), ("Test Gain", 1));
+    }
+
+    #[test]
+    fn a_child_that_exits_while_probing_is_no_plugin() {
+        // runs no test, so the child exits without reporting a plugin
+        fn exiting_command(_plugin: &Path) -> Result<process::Command> {
+            let mut command = process::Command::new(current_exe()?);
+            command.args(["--exact", "no_such_test", "--quiet"]);
+            Ok(command)
+        }
+
+        let host = Arc::new(Mutex::new(TestHost));
+        let start = Instant::now();
+        assert!(probe_with(exiting_command, Path::new("test_gain"), host).is_err());
+        assert!(start.elapsed() < COMMAND_TIMEOUT);
+    }
+
+    #[test]
+    fn commands_blocks_and_the_editor_reach_the_child() {
+        let mut plugin = spawn_test_plugin();
+        assert_eq!(plugin.get_info().name, "Test Gain");
+        let parameters = plugin.get_parameter_object();
+        assert_eq!(parameters.get_parameter_name(0), "Gain");
+
+        parameters.set_parameter(0, 0.5);
+        assert!(
+            block(&mut plugin, 0.25)
+                .iter()
+                .all(|sample| *sample == 0.125)
+        );
+        assert_eq!(parameters.get_parameter_text(0), "0.50");
+
+        let chunk = parameters.get_preset_data();
+        parameters.load_preset_data(&0.25_f32.to_le_bytes());
+        assert!(
+            block(&mut plugin, 1_f32)
+                .iter()
+                .all(|sample| *sample == 0.25)
+        );
+        parameters.load_preset_data(&chunk);
+        assert!(
+            block(&mut plugin, 1_f32)
+                .iter()
+                .all(|sample| *sample == 0.5)
+        );
+
+        let mut editor = plugin.get_editor().unwrap();
+        assert!(plugin.get_editor().is_none());
+        assert_eq!(editor.size(), (300, 200));
+        assert!(editor.open(std::ptr::null_mut()));
+        assert!(editor.is_open());
+        editor.close();
+    }
+
+    #[test]
+    fn slow_commands_do_not_hold_up_blocks() {
+        let mut plugin = spawn_test_plugin();
+        let parameters = plugin.get_parameter_object();
+        parameters.set_parameter(0, 0.5);
+
+        let sandbox = Arc::clone(&plugin.sandbox);
+        scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..3 {
+                    sandbox.get_preset_data();
+                }
+            });
+            // blocks keep flowing while the chunks are saved, none pass through
+            let start = Instant::now();
+            while start.elapsed() < SLOW_CHUNK * 3 {
+                assert!(
+                    block(&mut plugin, 1_f32)
+                        .iter()
+                        .all(|sample| *sample == 0.5)
+                );
+                sleep(Duration::from_millis(5));
+            }
+        });
+        assert!(!plugin.sandbox.failed.load(Acquire));
+    }
+
+    #[test]
+    fn a_crashed_child_restarts_with_its_parameters() {
+        let mut plugin = spawn_test_plugin();
+        let parameters = plugin.get_parameter_object();
+        parameters.set_parameter(0, 0.5);
+        assert!(
+            block(&mut plugin, 1_f32)
+                .iter()
+                .all(|sample| *sample == 0.5)
+        );
+
+        // the crashing block passes through unprocessed
+        assert!(
+            block(&mut plugin, CRASH)
+                .iter()
+                .all(|sample| *sample == CRASH)
+        );
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let output = block(&mut plugin, 1_f32);
+            if output.iter().all(|sample| *sample == 0.5) {
+                break;
+            }
+            assert!(output.iter().all(|sample| *sample == 1_f32));
+            assert!(Instant::now() < deadline, "the sandbox did not restart");
+            sleep(Duration::from_millis(20));
+        }
+        assert_eq!(parameters.get_parameter(0), 0.5);
+        assert_eq!(parameters.get_parameter_text(0), "0.50");
+    }
+}
@@ -0,0 +1,235 @@
+use crate::config::file_stamp;
+use crate::sandbox;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{from_slice, to_writer_pretty};
+use std::fs::{File, read, read_dir};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use vst::host::Host;
+
+/// the file extension of plugin libraries on this platform
+#[cfg(target_os = "windows")]
+const LIBRARY_EXTENSION: &str = "dll";
+#[cfg(target_os = "macos")]
+const LIBRARY_EXTENSION: &str = "vst";
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const LIBRARY_EXTENSION: &str = "so";
+
+/// how deep the scanner descends into vendor folders below a search path
+const MAX_DEPTH: usize = 3;
+
+/// the metadata of a plugin found by the scanner
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PluginInfo {
+    pub path: PathBuf,
+    pub name: String,
+    pub vendor: String,
+    pub unique_id: i32,
+    pub inputs: i32,
+    pub outputs: i32,
+    pub parameters: i32,
+}
+
+/// a scanned library, kept until the file changes so it is never loaded twice
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    path: PathBuf,
+    /// the modification time and length of the library when it was scanned
+    stamp: (SystemTime, u64),
+    /// none if the library is not a plugin
+    plugin: Option<PluginInfo>,
+}
+
+/// finds plugins in the search paths, caching their metadata next to the config
+pub struct PluginScanner {
+    cache_path: PathBuf,
+    cache: Vec<CacheEntry>,
+}
+
+impl PluginScanner {
+    /// reads the cache at path, starting empty if it is missing or unreadable
+    pub fn new(cache_path: PathBuf) -> Self {
+        let cache = read(&cache_path)
+            .ok()
+            .and_then(|bytes| from_slice(&bytes).ok())
+            .unwrap_or_default();
+
+        PluginScanner { cache_path, cache }
+    }
+
+    /// the plugins found by the last scan
+    pub fn cached(&self) -> Vec<PluginInfo> {
+        self.cache
+            .iter()
+            .filter_map(|entry| entry.plugin.clone())
+            .collect()
+    }
+
+    /// enumerates the plugins in the directories, only loading libraries that changed
+    ///
+    /// each library is loaded in a sandbox, so one that crashes is only skipped. failing to
+    /// write the cache is logged, the next scan loads the libraries again
+    pub fn scan(&mut self, directories: &[PathBuf]) -> Vec<PluginInfo> {
+        let mut libraries = Vec::new();
+        for directory in directories {
+            find_libraries(directory, 0, &mut libraries);
+        }
+        libraries.sort();
+        libraries.dedup();
+
+        let mut cache = Vec::new();
+        for path in libraries {
+            let Some(stamp) = file_stamp(&path) else {
+                continue;
+            };
+
+            let position = self
+                .cache
+                .iter()
+                .position(|entry| entry.path == path && entry.stamp == stamp);
+            let entry = match position {
+                Some(position) => self.cache.swap_remove(position),
+                None => CacheEntry {
+                    plugin: probe(&path),
+                    path,
+                    stamp,
+                },
+            };
+            cache.push(entry);
+        }
+        self.cache = cache;
+
+        let written = File::create(&self.cache_path)
+            .map_err(serde_json::Error::io)
+            .and_then(|file| to_writer_pretty(file, &self.cache));
+        if let Err(error) = written {
+            warn!(
+                "failed to write the plugin cache {}: {}",
+                self.cache_path.display(),
+                error
+            );
+        }
+
+        let plugins = self.cached();
+        info!("found {} plugins", plugins.len());
+        plugins
+    }
+}
+
+/// the directories searched for plugins in order: next to the executable, the config
+/// directory and the standard vst directories
+pub fn search_paths(config_dir: &Path) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    if let Some(directory) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+    {
+        paths.push(directory);
+    }
+    paths.push(config_dir.to_path_buf());
+    paths.extend(standard_paths());
+    paths
+}
+
+/// resolves a plugin path, relative paths are looked up in the search paths
+pub fn find_plugin(path: &Path, search_paths: &[PathBuf]) -> Option<PathBuf> {
+    if path.is_absolute() {
+        return path.exists().then(|| path.to_path_buf());
+    }
+
+    search_paths
+        .iter()
+        .map(|directory| directory.join(path))
+        .find(|candidate| candidate.exists())
+}
+
+#[cfg(target_os = "windows")]
+fn standard_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    if let Some(program_files) = std::env::var_os("ProgramFiles").map(PathBuf::from) {
+        paths.push(program_files.join("VSTPlugins"));
+        paths.push(program_files.join("Steinberg").join("VSTPlugins"));
+    }
+    if let Some(common_files) = std::env::var_os("CommonProgramFiles").map(PathBuf::from) {
+        paths.push(common_files.join("VST2"));
+        paths.push(common_files.join("Steinberg").join("VST2"));
+    }
+
+    paths
+}
+
+#[cfg(target_os = "macos")]
+fn standard_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    if let Some(home) = dirs::home_dir() {
+        paths.push(home.join("Library/Audio/Plug-Ins/VST"));
+    }
+    paths.push(PathBuf::from("/Library/Audio/Plug-Ins/VST"));
+    paths
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn standard_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    if let Some(home) = dirs::home_dir() {
+        paths.push(home.join(".vst"));
+    }
+    paths.push(PathBuf::from("/usr/local/lib/vst"));
+    paths.push(PathBuf::from("/usr/lib/vst"));
+    paths
+}
+
+/// collects the libraries below a directory, missing directories are skipped
+fn find_libraries(directory: &Path, depth: usize, libraries: &mut Vec<PathBuf>) {
+    let Ok(entries) = read_dir(directory) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        if path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case(LIBRARY_EXTENSION))
+        {
+            libraries.push(path);
+        } else if depth < MAX_DEPTH && path.is_dir() {
+            find_libraries(&path, depth + 1, libraries);
+        }
+    }
+}
+
+/// a host that ignores every callback while a plugin is probed
+struct ScanHost;
+
+impl Host for ScanHost {}
+
+/// loads a library in a sandbox to read its metadata, none if it is not a plugin or it
+/// crashed
+fn probe(path: &Path) -> Option<PluginInfo> {
+    match sandbox::probe(path, Arc::new(Mutex::new(ScanHost))) {
+        Ok(info) => {
+            debug!("found plugin {} at {}", info.name, path.display());
+
+            Some(PluginInfo {
+                path: path.to_path_buf(),
+                name: info.name,
+                vendor: info.vendor,
+                unique_id: info.unique_id,
+                inputs: info.inputs,
+                outputs: info.outputs,
+                parameters: info.parameters,
+            })
+        }
+        Err(error) => {
+            warn!("skipping {}: {}", path.display(), error);
+            None
+        }
+    }
+}
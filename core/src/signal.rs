@@ -0,0 +1,218 @@
+use crate::Result;
+use std::sync::atomic::AtomicU32;
+use std::time::Duration;
+
+/// wakes a thread of another process that waits for a word in shared memory to change
+///
+/// linux waits on the word itself with a futex, windows raises a named event next to it.
+/// elsewhere the waiter polls the word, which costs latency but not a busy core
+pub(crate) struct Signal {
+    #[cfg(windows)]
+    event: windows::Event,
+}
+
+impl Signal {
+    /// creates the signal with the name for the process that waits or raises it first
+    pub(crate) fn create(name: &str) -> Result<Self> {
+        #[cfg(not(windows))]
+        let _ = name;
+        Ok(Signal {
+            #[cfg(windows)]
+            event: windows::Event::create(name)?,
+        })
+    }
+
+    /// opens the signal another process created with the name
+    pub(crate) fn open(name: &str) -> Result<Self> {
+        #[cfg(not(windows))]
+        let _ = name;
+        Ok(Signal {
+            #[cfg(windows)]
+            event: windows::Event::open(name)?,
+        })
+    }
+
+    /// wakes the waiter, called after the word changed
+    pub(crate) fn raise(&self, word: &AtomicU32) {
+        #[cfg(target_os = "linux")]
+        linux::wake(word);
+        #[cfg(windows)]
+        {
+            let _ = word;
+            self.event.set();
+        }
+        #[cfg(not(any(target_os = "linux", windows)))]
+        let _ = word;
+    }
+
+    /// blocks until the word no longer holds seen, the signal is raised or the timeout passed
+    ///
+    /// may return early, the caller checks the word again
+    pub(crate) fn wait(&self, word: &AtomicU32, seen: u32, timeout: Duration) {
+        #[cfg(target_os = "linux")]
+        linux::wait(word, seen, timeout);
+        #[cfg(windows)]
+        {
+            let _ = (word, seen);
+            self.event.wait(timeout);
+        }
+        #[cfg(not(any(target_os = "linux", windows)))]
+        {
+            let _ = (word, seen);
+            std::thread::sleep(timeout.min(Duration::from_micros(200)));
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::ptr::null;
+    use std::sync::atomic::AtomicU32;
+    use std::time::Duration;
+
+    /// waits on the word as long as it holds seen, shared between processes as the flag
+    /// FUTEX_PRIVATE_FLAG is left out
+    pub(super) fn wait(word: &AtomicU32, seen: u32, timeout: Duration) {
+        let timeout = libc::timespec {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_nsec: timeout.subsec_nanos() as libc::c_long,
+        };
+        // SAFETY: the word is a valid aligned u32 for the duration of the call
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                word.as_ptr(),
+                libc::FUTEX_WAIT,
+                seen,
+                &timeout as *const libc::timespec,
+                null::<u32>(),
+                0,
+            );
+        }
+    }
+
+    /// wakes every process waiting on the word
+    pub(super) fn wake(word: &AtomicU32) {
+        // SAFETY: the word is a valid aligned u32 for the duration of the call
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                word.as_ptr(),
+                libc::FUTEX_WAKE,
+                i32::MAX,
+                null::<libc::timespec>(),
+                null::<u32>(),
+                0,
+            );
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use crate::Result;
+    use crate::error::ErrorKind;
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr::null_mut;
+    use std::time::Duration;
+    use winapi::shared::minwindef::FALSE;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::synchapi::{CreateEventW, OpenEventW, SetEvent, WaitForSingleObject};
+    use winapi::um::winnt::{EVENT_MODIFY_STATE, HANDLE, SYNCHRONIZE};
+
+    /// a named auto reset event
+    pub(super) struct Event(HANDLE);
+
+    // SAFETY: event handles may be used from any thread
+    unsafe impl Send for Event {}
+    unsafe impl Sync for Event {}
+
+    impl Event {
+        pub(super) fn create(name: &str) -> Result<Self> {
+            let name = wide(name);
+            // SAFETY: the name is null terminated and outlives the call
+            let handle = unsafe { CreateEventW(null_mut(), FALSE, FALSE, name.as_ptr()) };
+            Self::checked(handle)
+        }
+
+        pub(super) fn open(name: &str) -> Result<Self> {
+            let name = wide(name);
+            // SAFETY: the name is null terminated and outlives the call
+            let handle =
+                unsafe { OpenEventW(EVENT_MODIFY_STATE | SYNCHRONIZE, FALSE, name.as_ptr()) };
+            Self::checked(handle)
+        }
+
+        fn checked(handle: HANDLE) -> Result<Self> {
+            if handle.is_null() {
+                Err(ErrorKind::Sandbox("failed to create the sandbox event"))?;
+            }
+            Ok(Event(handle))
+        }
+
+        pub(super) fn set(&self) {
+            // SAFETY: the handle is open until the event drops
+            unsafe { SetEvent(self.0) };
+        }
+
+        pub(super) fn wait(&self, timeout: Duration) {
+            let milliseconds = timeout.as_millis().clamp(1, u32::MAX as u128 - 1) as u32;
+            // SAFETY: the handle is open until the event drops
+            unsafe { WaitForSingleObject(self.0, milliseconds) };
+        }
+    }
+
+    impl Drop for Event {
+        fn drop(&mut self) {
+            // SAFETY: the handle is open and closed only here
+            unsafe { CloseHandle(self.0) };
+        }
+    }
+
+    /// a null terminated wide string for the event names
+    fn wide(name: &str) -> Vec<u16> {
+        OsStr::new(&format!("Local\\{name}"))
+            .encode_wide()
+            .chain(Some(0))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering::{Acquire, Release};
+    use std::thread::spawn;
+    use std::time::Instant;
+
+    #[test]
+    fn wakes_a_waiting_thread() {
+        let signal = Arc::new(Signal::create("whisper_ware_test_signal").unwrap());
+        let word = Arc::new(AtomicU32::new(0));
+
+        let waiter = {
+            let (signal, word) = (Arc::clone(&signal), Arc::clone(&word));
+            spawn(move || {
+                let deadline = Instant::now() + Duration::from_secs(5);
+                while word.load(Acquire) == 0 && Instant::now() < deadline {
+                    signal.wait(&word, 0, Duration::from_secs(1));
+                }
+                word.load(Acquire)
+            })
+        };
+        word.store(1, Release);
+        signal.raise(&word);
+        assert_eq!(waiter.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn waits_no_longer_than_the_timeout() {
+        let signal = Signal::create("whisper_ware_test_timeout").unwrap();
+        let word = AtomicU32::new(0);
+        let start = Instant::now();
+        signal.wait(&word, 0, Duration::from_millis(20));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
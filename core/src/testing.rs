@@ -0,0 +1,36 @@
+//! helpers shared by the unit tests
+
+use crate::config::AtomicConfig;
+use std::fs::{create_dir_all, remove_dir_all};
+use std::path::PathBuf;
+use std::sync::mpsc::channel;
+use vst::host::HostBuffer;
+use vst::plugin::Plugin;
+
+/// a fresh empty directory for a test, left behind for inspection when it fails
+pub(crate) fn scratch_dir(name: &str) -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("whisper_ware_test_{}_{}", name, std::process::id()));
+    let _ = remove_dir_all(&directory);
+    create_dir_all(&directory).unwrap();
+    directory
+}
+
+/// opens the config.json in directory, nothing listens for saves
+pub(crate) fn open_config(directory: &std::path::Path) -> AtomicConfig {
+    AtomicConfig::open(directory.join("config.json"), channel().0)
+}
+
+/// runs one block through a plugin and returns its outputs
+pub(crate) fn process(
+    plugin: &mut dyn Plugin,
+    inputs: &[Vec<f32>],
+    outputs: usize,
+) -> Vec<Vec<f32>> {
+    let frames = inputs.first().map_or(0, Vec::len);
+    let mut outputs = vec![vec![0_f32; frames]; outputs];
+    let mut buffer = HostBuffer::new(inputs.len(), outputs.len());
+    let mut audio_buffer = buffer.bind(inputs, &mut outputs);
+    plugin.process(&mut audio_buffer);
+    outputs
+}
@@ -0,0 +1,120 @@
+use crate::backend::Latency;
+use atomic_float::AtomicF32;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::time::{SystemTime, UNIX_EPOCH};
+use vst::api::{TimeInfo, TimeInfoFlags};
+
+/// the tempo reported to plugins that sync to the host, there is no song to follow
+const TEMPO: f64 = 120_f64;
+/// the reported time signature is 4/4
+const BEATS_PER_BAR: i32 = 4;
+
+/// the playback position the chain reports to its plugins and the latency of the stream
+///
+/// the transport plays while a stream runs and never rewinds, so tempo synced plugins see
+/// time moving forward steadily across backend restarts
+#[derive(Default)]
+pub struct Transport {
+    sample_rate: AtomicF32,
+    /// the frames processed before the current block
+    position: AtomicU64,
+    playing: AtomicBool,
+    /// set when playback starts or stops until a plugin next reads the time info
+    changed: AtomicBool,
+    latency: Mutex<Latency>,
+}
+
+impl Transport {
+    /// the sample rate of the current or last stream, zero before the first
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate.load(Relaxed)
+    }
+
+    /// the position of the current block in samples
+    pub fn position(&self) -> u64 {
+        self.position.load(Relaxed)
+    }
+
+    pub fn playing(&self) -> bool {
+        self.playing.load(Relaxed)
+    }
+
+    /// the latency of the current or last stream, measured every block
+    pub fn latency(&self) -> Latency {
+        *self.latency.lock().unwrap()
+    }
+
+    /// records the latency of the current block, skipped while a reader holds it
+    pub(crate) fn set_latency(&self, latency: Latency) {
+        if let Ok(mut current) = self.latency.try_lock() {
+            *current = latency;
+        }
+    }
+
+    /// starts playback for a stream at sample rate
+    pub(crate) fn start(&self, sample_rate: f32) {
+        self.sample_rate.store(sample_rate, Relaxed);
+        if !self.playing.swap(true, Relaxed) {
+            self.changed.store(true, Relaxed);
+        }
+    }
+
+    /// stops playback when the stream ends
+    pub(crate) fn stop(&self) {
+        if self.playing.swap(false, Relaxed) {
+            self.changed.store(true, Relaxed);
+        }
+    }
+
+    /// moves to the next block
+    pub(crate) fn advance(&self, frames: usize) {
+        self.position.fetch_add(frames as u64, Relaxed);
+    }
+
+    /// the time info of the current block with the fields the mask asks for
+    pub(crate) fn time_info(&self, mask: TimeInfoFlags) -> TimeInfo {
+        let sample_rate = self.sample_rate() as f64;
+        let sample_pos = self.position() as f64;
+        let mut flags = TimeInfoFlags::empty();
+        let mut info = TimeInfo {
+            sample_pos,
+            sample_rate,
+            ..Default::default()
+        };
+
+        if self.changed.swap(false, Relaxed) {
+            flags |= TimeInfoFlags::TRANSPORT_CHANGED;
+        }
+        if self.playing() {
+            flags |= TimeInfoFlags::TRANSPORT_PLAYING;
+        }
+
+        if mask.contains(TimeInfoFlags::NANOSECONDS_VALID) {
+            info.nanoseconds = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0_f64, |time| time.as_nanos() as f64);
+            flags |= TimeInfoFlags::NANOSECONDS_VALID;
+        }
+
+        // musical time is derived from the samples so it never jumps
+        if sample_rate > 0_f64 {
+            let quarter_notes = sample_pos / sample_rate * TEMPO / 60_f64;
+            let bar = BEATS_PER_BAR as f64;
+            info.ppq_pos = quarter_notes;
+            info.tempo = TEMPO;
+            info.bar_start_pos = (quarter_notes / bar).floor() * bar;
+            info.time_sig_numerator = BEATS_PER_BAR;
+            info.time_sig_denominator = 4;
+            flags |= mask
+                & (TimeInfoFlags::PPQ_POS_VALID
+                    | TimeInfoFlags::TEMPO_VALID
+                    | TimeInfoFlags::BARS_VALID
+                    | TimeInfoFlags::TIME_SIG_VALID);
+        }
+
+        info.flags = flags.bits();
+        info
+    }
+}
@@ -0,0 +1,289 @@
+use crate::backend::StreamSignals;
+use crate::chain::NOT_PROCESSING;
+use crate::config::AtomicConfig;
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::fs::{OpenOptions, read_to_string};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64};
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime};
+
+/// how long a stage may go without a heartbeat before the stream counts as stalled
+const STALL_TIMEOUT: Duration = Duration::from_secs(2);
+/// how often the watchdog checks the heartbeats
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// a part of the stream that beats while audio flows
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Stage {
+    /// the input device callback
+    Input,
+    /// the processor, once per block
+    Processor,
+    /// the output device callback
+    Output,
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Stage::Input => "input stream",
+            Stage::Processor => "processor",
+            Stage::Output => "output stream",
+        })
+    }
+}
+
+/// counts the callbacks and blocks of each stage, so the watchdog sees them stop
+#[derive(Default)]
+pub struct Heartbeats {
+    input: AtomicU64,
+    processor: AtomicU64,
+    output: AtomicU64,
+}
+
+impl Heartbeats {
+    pub fn beat(&self, stage: Stage) {
+        self.counter(stage).fetch_add(1, Relaxed);
+    }
+
+    fn counter(&self, stage: Stage) -> &AtomicU64 {
+        match stage {
+            Stage::Input => &self.input,
+            Stage::Processor => &self.processor,
+            Stage::Output => &self.output,
+        }
+    }
+}
+
+/// a stream that stopped and was restarted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stall {
+    /// the first stage that stopped beating
+    pub stage: Stage,
+    pub at: SystemTime,
+    /// the slot whose plugin was inside process when the processor stalled
+    pub slot: Option<u32>,
+}
+
+/// notices when a stage of the stream stops beating and stops the stream so the backend
+/// restarts, such as an input device that silently stops delivering audio
+///
+/// a plugin hung inside process cannot be interrupted. its slot is switched to run isolated,
+/// where the sandbox ends a hung plugin on its own, and the hang handler is called to start
+/// the application again
+#[derive(Default)]
+pub struct Watchdog {
+    /// every stall since launch, oldest first
+    stalls: Mutex<Vec<Stall>>,
+    /// the file every stall is appended to, one json line each
+    log: Option<PathBuf>,
+    /// the stalls earlier launches appended to the log
+    earlier: usize,
+    /// called once a plugin hung inside process
+    on_hang: Option<Box<dyn Fn() + Send + Sync>>,
+}
+
+impl Watchdog {
+    /// a watchdog that keeps the stalls in memory
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// a watchdog that appends the stalls to a log, counting those already in it
+    pub fn open(log: PathBuf) -> Self {
+        let earlier = read_to_string(&log).map_or(0, |text| {
+            text.lines()
+                .filter(|line| serde_json::from_str::<Stall>(line).is_ok())
+                .count()
+        });
+        Watchdog {
+            log: Some(log),
+            earlier,
+            ..Self::default()
+        }
+    }
+
+    /// calls a handler once a plugin hung inside process, the stream cannot start again
+    /// before the application does
+    pub fn on_hang(mut self, handler: impl Fn() + Send + Sync + 'static) -> Self {
+        self.on_hang = Some(Box::new(handler));
+        self
+    }
+
+    /// the stalls recorded since launch, oldest first
+    pub fn stalls(&self) -> Vec<Stall> {
+        self.stalls.lock().unwrap().clone()
+    }
+
+    /// the stalls recorded since launch and by earlier launches sharing the log
+    pub fn total_stalls(&self) -> usize {
+        self.earlier + self.stalls.lock().unwrap().len()
+    }
+
+    /// watches the heartbeats of a stream until it ends or stalls
+    ///
+    /// a stalled stream is stopped by clearing run, the processor is woken in case it waits
+    /// for input that never comes. processing holds the slot the chain is processing
+    pub(crate) fn watch(
+        &self,
+        signals: &StreamSignals,
+        run: &AtomicBool,
+        done: &AtomicBool,
+        processing: &AtomicU32,
+        config: &AtomicConfig,
+    ) {
+        let stages = [Stage::Input, Stage::Processor, Stage::Output];
+        // the beat count of each stage and when it last changed
+        let mut last = stages.map(|stage| {
+            (
+                signals.heartbeats.counter(stage).load(Relaxed),
+                Instant::now(),
+            )
+        });
+
+        while !done.load(Relaxed) && run.load(Relaxed) {
+            sleep(CHECK_INTERVAL);
+
+            for (stage, (beats, changed)) in stages.iter().zip(last.iter_mut()) {
+                let current = signals.heartbeats.counter(*stage).load(Relaxed);
+                if current != *beats {
+                    (*beats, *changed) = (current, Instant::now());
+                } else if changed.elapsed() > STALL_TIMEOUT {
+                    let slot = match processing.load(Relaxed) {
+                        NOT_PROCESSING => None,
+                        slot => (*stage == Stage::Processor).then_some(slot),
+                    };
+                    self.stall(*stage, slot, changed.elapsed());
+                    run.store(false, Relaxed);
+                    signals.input_ready.notify_all();
+                    if let Some(slot) = slot {
+                        self.hung(slot, config);
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    fn stall(&self, stage: Stage, slot: Option<u32>, silent_for: Duration) {
+        let stall = Stall {
+            stage,
+            at: SystemTime::now(),
+            slot,
+        };
+        if let Some(log) = &self.log {
+            let appended = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log)
+                .and_then(|mut file| writeln!(file, "{}", serde_json::to_string(&stall)?));
+            if let Err(error) = appended {
+                warn!("failed to record the stall in {}: {}", log.display(), error);
+            }
+        }
+
+        let mut stalls = self.stalls.lock().unwrap();
+        stalls.push(stall);
+        error!(
+            "the {} stalled for {:.1} s, restarting the backend ({} stalls since launch, {} in total)",
+            stage,
+            silent_for.as_secs_f32(),
+            stalls.len(),
+            self.earlier + stalls.len()
+        );
+    }
+
+    /// isolates the plugin of a slot hung inside process and calls the hang handler
+    fn hung(&self, slot: u32, config: &AtomicConfig) {
+        if !config.sandboxed(slot) {
+            error!(
+                "the plugin of slot {} is hung inside process, it runs isolated from now on",
+                slot
+            );
+            config.set_sandboxed(slot, true);
+        }
+        match &self.on_hang {
+            Some(handler) => handler(),
+            None => error!("the backend restarts if the plugin returns"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{open_config, scratch_dir};
+    use std::sync::Arc;
+
+    #[test]
+    fn stalls_are_counted_across_launches() {
+        let directory = scratch_dir("watchdog_log");
+        let log = directory.join("stalls.jsonl");
+
+        let watchdog = Watchdog::open(log.clone());
+        watchdog.stall(Stage::Input, None, STALL_TIMEOUT);
+        watchdog.stall(Stage::Processor, Some(3), STALL_TIMEOUT);
+        assert_eq!(watchdog.total_stalls(), 2);
+
+        let relaunched = Watchdog::open(log);
+        assert!(relaunched.stalls().is_empty());
+        relaunched.stall(Stage::Output, None, STALL_TIMEOUT);
+        assert_eq!(relaunched.total_stalls(), 3);
+    }
+
+    #[test]
+    fn a_hung_plugin_runs_isolated_and_calls_the_handler() {
+        let directory = scratch_dir("watchdog_hang");
+        let config = open_config(&directory);
+        let slot = config.slot_ids()[0];
+        let calls = Arc::new(AtomicU32::new(0));
+        let watchdog = {
+            let calls = Arc::clone(&calls);
+            Watchdog::new().on_hang(move || {
+                calls.fetch_add(1, Relaxed);
+            })
+        };
+
+        watchdog.hung(slot, &config);
+        assert!(config.sandboxed(slot));
+        assert_eq!(calls.load(Relaxed), 1);
+    }
+
+    #[test]
+    fn a_stalled_processor_names_the_plugin_inside_process() {
+        let directory = scratch_dir("watchdog_watch");
+        let config = open_config(&directory);
+        let slot = config.slot_ids()[0];
+        let signals = StreamSignals::default();
+        let (run, done) = (AtomicBool::new(true), AtomicBool::new(false));
+        let processing = AtomicU32::new(slot);
+
+        let watchdog = Watchdog::new();
+        // the device callbacks keep beating while the processor is stuck
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                while run.load(Relaxed) {
+                    signals.heartbeats.beat(Stage::Input);
+                    signals.heartbeats.beat(Stage::Output);
+                    sleep(CHECK_INTERVAL / 4);
+                }
+            });
+            watchdog.watch(&signals, &run, &done, &processing, &config);
+        });
+
+        assert!(!run.load(Relaxed));
+        let stalls = watchdog.stalls();
+        assert_eq!(stalls.len(), 1);
+        assert_eq!(
+            (stalls[0].stage, stalls[0].slot),
+            (Stage::Processor, Some(slot))
+        );
+        assert!(config.sandboxed(slot));
+    }
+}
@@ -1,9 +1,13 @@
+use crate::Result;
+use crate::error::ErrorKind;
+use cpal::{DeviceId, HostId};
 use log::warn;
 use std::{
     ptr,
     sync::atomic::{AtomicU32, Ordering},
-    sync::mpsc::{Sender, channel},
+    sync::mpsc::{Receiver, Sender, channel},
 };
+use whisper_ware_core::{DeviceEvent, DeviceWatcher, Direction};
 use winapi::shared::ntdef::LPCWSTR;
 use winapi::um::combaseapi::{CLSCTX_ALL, CoInitializeEx, CoUninitialize};
 use winapi::{
@@ -16,8 +20,8 @@ use winapi::{
     um::{
         combaseapi::CoCreateInstance,
         mmdeviceapi::{
-            CLSID_MMDeviceEnumerator, EDataFlow, ERole, IMMDeviceEnumerator, IMMNotificationClient,
-            IMMNotificationClientVtbl,
+            CLSID_MMDeviceEnumerator, DEVICE_STATE_ACTIVE, EDataFlow, ERole, IMMDeviceEnumerator,
+            IMMNotificationClient, IMMNotificationClientVtbl, eCapture, eConsole,
         },
         objbase::COINIT_APARTMENTTHREADED,
         unknwnbase::{IUnknown, IUnknownVtbl},
@@ -48,69 +52,93 @@ static AUDIO_DEVICE_CALLBACK_VTBL: IMMNotificationClientVtbl = IMMNotificationCl
 struct AudioDeviceCallback {
     vtable: *const IMMNotificationClientVtbl,
     ref_count: AtomicU32,
-    tx: Sender<()>,
+    tx: Sender<DeviceEvent>,
 }
 
-/// Blocks the current thread until *any* audio device change notification happens.
-pub(crate) fn wait_for_audio_device_change() {
-    unsafe {
-        // initialize COM apartment
-        let hr = CoInitializeEx(ptr::null_mut(), COINIT_APARTMENTTHREADED);
-        if hresult_failed(hr) {
-            panic!("CoInitializeEx failed: 0x{:08x}", hr);
-        }
+/// Reports the audio endpoint changes through an IMMNotificationClient.
+///
+/// COM is initialized for the thread creating the watcher, which must also drop it.
+pub(crate) struct EndpointWatcher {
+    enumerator: *mut IMMDeviceEnumerator,
+    callback: *mut IMMNotificationClient,
+    rx: Receiver<DeviceEvent>,
+}
 
-        // create IMMDeviceEnumerator
-        let mut enumerator_ptr: *mut IMMDeviceEnumerator = ptr::null_mut();
-        let hr = CoCreateInstance(
-            &CLSID_MMDeviceEnumerator,
-            ptr::null_mut(),
-            CLSCTX_ALL,
-            &IID_IMMDEVICE_ENUMERATOR,
-            &mut enumerator_ptr as *mut _ as *mut LPVOID,
-        );
-        if hresult_failed(hr) || enumerator_ptr.is_null() {
-            CoUninitialize();
-            panic!(
-                "CoCreateInstance(CLSID_MMDeviceEnumerator) failed: 0x{:08x}",
-                hr
+impl EndpointWatcher {
+    pub(crate) fn new() -> Result<Self> {
+        unsafe {
+            // initialize COM apartment
+            let hr = CoInitializeEx(ptr::null_mut(), COINIT_APARTMENTTHREADED);
+            if hresult_failed(hr) {
+                return Err(ErrorKind::Com("CoInitializeEx", hr).into());
+            }
+
+            // create IMMDeviceEnumerator
+            let mut enumerator: *mut IMMDeviceEnumerator = ptr::null_mut();
+            let hr = CoCreateInstance(
+                &CLSID_MMDeviceEnumerator,
+                ptr::null_mut(),
+                CLSCTX_ALL,
+                &IID_IMMDEVICE_ENUMERATOR,
+                &mut enumerator as *mut _ as *mut LPVOID,
             );
-        }
+            if hresult_failed(hr) || enumerator.is_null() {
+                CoUninitialize();
+                return Err(
+                    ErrorKind::Com("CoCreateInstance(CLSID_MMDeviceEnumerator)", hr).into(),
+                );
+            }
 
-        let enumerator = &mut *enumerator_ptr;
-        // create channel for notifications
-        let (tx, rx) = channel::<()>();
-        // create our callback COM object
-        let callback_ptr = create_audio_device_callback(tx);
-
-        // register callback
-        let hr = (*enumerator).RegisterEndpointNotificationCallback(callback_ptr);
-        if hresult_failed(hr) {
-            adc_release(callback_ptr.cast());
-            (*enumerator).Release();
-            CoUninitialize();
-            panic!("RegisterEndpointNotificationCallback failed: 0x{:08x}", hr);
-        }
+            // create channel for notifications
+            let (tx, rx) = channel::<DeviceEvent>();
+            // create our callback COM object
+            let callback = create_audio_device_callback(tx);
 
-        // block until devices change
-        let _ = rx.recv();
+            // register callback
+            let hr = (*enumerator).RegisterEndpointNotificationCallback(callback);
+            if hresult_failed(hr) {
+                adc_release(callback.cast());
+                (*enumerator).Release();
+                CoUninitialize();
+                return Err(ErrorKind::Com("RegisterEndpointNotificationCallback", hr).into());
+            }
 
-        // unregister callback
-        let hr = (*enumerator).UnregisterEndpointNotificationCallback(callback_ptr);
-        if hresult_failed(hr) {
-            warn!(
-                "UnregisterEndpointNotificationCallback failed: 0x{:08x}",
-                hr
-            );
+            Ok(EndpointWatcher {
+                enumerator,
+                callback,
+                rx,
+            })
         }
+    }
+}
+
+impl DeviceWatcher for EndpointWatcher {
+    fn next_event(&mut self) -> Option<DeviceEvent> {
+        // the callback holds the sender, so this blocks until the next change
+        self.rx.recv().ok()
+    }
+}
+
+impl Drop for EndpointWatcher {
+    fn drop(&mut self) {
+        unsafe {
+            // unregister callback
+            let hr = (*self.enumerator).UnregisterEndpointNotificationCallback(self.callback);
+            if hresult_failed(hr) {
+                warn!(
+                    "UnregisterEndpointNotificationCallback failed: 0x{:08x}",
+                    hr
+                );
+            }
 
-        adc_release(callback_ptr.cast());
-        (*enumerator).Release();
-        CoUninitialize();
+            adc_release(self.callback.cast());
+            (*self.enumerator).Release();
+            CoUninitialize();
+        }
     }
 }
 
-unsafe fn create_audio_device_callback(tx: Sender<()>) -> *mut IMMNotificationClient {
+unsafe fn create_audio_device_callback(tx: Sender<DeviceEvent>) -> *mut IMMNotificationClient {
     let obj = Box::new(AudioDeviceCallback {
         vtable: &AUDIO_DEVICE_CALLBACK_VTBL,
         ref_count: AtomicU32::new(1), // our own ref
@@ -125,6 +153,29 @@ fn hresult_failed(hr: i32) -> bool {
     hr < 0
 }
 
+/// Helper: the id of an endpoint in the format of the cpal device ids
+fn endpoint_id(id: LPCWSTR) -> Option<String> {
+    if id.is_null() {
+        return None;
+    }
+
+    let id = unsafe {
+        let len = (0..).take_while(|&i| *id.add(i) != 0).count();
+        String::from_utf16_lossy(std::slice::from_raw_parts(id, len))
+    };
+    Some(DeviceId(HostId::Wasapi, id).to_string())
+}
+
+/// Helper: the direction of an endpoint, whose id starts with {0.0.0. for render and
+/// {0.0.1. for capture devices, which still works once the device was removed
+fn endpoint_direction(id: &str) -> Direction {
+    if id.contains("{0.0.1.") {
+        Direction::Input
+    } else {
+        Direction::Output
+    }
+}
+
 /// Cast from interface pointer to our struct
 fn from_this<'a>(this: *mut IMMNotificationClient) -> &'a mut AudioDeviceCallback {
     unsafe { &mut *(this as *mut AudioDeviceCallback) }
@@ -180,43 +231,72 @@ unsafe extern "system" fn adc_release(this: *mut IUnknown) -> ULONG {
 /// IMMNotificationClient::OnDeviceStateChanged
 unsafe extern "system" fn adc_on_device_state_changed(
     this: *mut IMMNotificationClient,
-    _id: LPCWSTR,
-    _new_state: DWORD,
+    id: LPCWSTR,
+    new_state: DWORD,
 ) -> i32 {
-    let adc = from_this(this);
-    let _ = adc.tx.send(());
+    if let Some(id) = endpoint_id(id) {
+        let adc = from_this(this);
+        let _ = adc.tx.send(DeviceEvent::StateChanged {
+            direction: endpoint_direction(&id),
+            id,
+            active: new_state == DEVICE_STATE_ACTIVE,
+        });
+    }
     S_OK
 }
 
 /// IMMNotificationClient::OnDeviceAdded
 unsafe extern "system" fn adc_on_device_added(
     this: *mut IMMNotificationClient,
-    _id: LPCWSTR,
+    id: LPCWSTR,
 ) -> i32 {
-    let adc = from_this(this);
-    let _ = adc.tx.send(());
+    if let Some(id) = endpoint_id(id) {
+        let adc = from_this(this);
+        let _ = adc.tx.send(DeviceEvent::Added {
+            direction: endpoint_direction(&id),
+            id,
+        });
+    }
     S_OK
 }
 
 /// IMMNotificationClient::OnDeviceRemoved
 unsafe extern "system" fn adc_on_device_removed(
     this: *mut IMMNotificationClient,
-    _id: LPCWSTR,
+    id: LPCWSTR,
 ) -> i32 {
-    let adc = from_this(this);
-    let _ = adc.tx.send(());
+    if let Some(id) = endpoint_id(id) {
+        let adc = from_this(this);
+        let _ = adc.tx.send(DeviceEvent::Removed {
+            direction: endpoint_direction(&id),
+            id,
+        });
+    }
     S_OK
 }
 
 /// IMMNotificationClient::OnDefaultDeviceChanged
 unsafe extern "system" fn adc_on_default_device_changed(
     this: *mut IMMNotificationClient,
-    _flow: EDataFlow,
-    _role: ERole,
-    _id: LPCWSTR,
+    flow: EDataFlow,
+    role: ERole,
+    id: LPCWSTR,
 ) -> i32 {
+    // the backend opens the console defaults, the other roles report the same change again
+    if role != eConsole {
+        return S_OK;
+    }
+
+    let direction = if flow == eCapture {
+        Direction::Input
+    } else {
+        Direction::Output
+    };
     let adc = from_this(this);
-    let _ = adc.tx.send(());
+    let _ = adc.tx.send(DeviceEvent::DefaultChanged {
+        id: endpoint_id(id),
+        direction,
+    });
     S_OK
 }
 
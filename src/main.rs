@@ -1,59 +1,45 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, default_host};
 use lazy_static::lazy_static;
-use log::{LevelFilter, debug, error, info, warn};
-use minimal_windows_gui as win;
-use minimal_windows_gui::class::Class;
-use minimal_windows_gui::message::Message;
-use minimal_windows_gui::window::Window;
-use rtrb::{Consumer, Producer, RingBuffer};
-use std::path::Path;
-use std::process::Command;
-use std::sync::atomic::AtomicBool;
-use std::sync::atomic::Ordering::Relaxed;
-use std::sync::{Arc, Condvar, Mutex, RwLock};
-use std::thread::{sleep, spawn};
-use std::time::Duration;
-use tray_icon::menu::{MenuEvent, MenuItem};
-use tray_icon::{Icon, TrayIconBuilder, menu::Menu};
-use vst::host::{Host, HostBuffer, PluginInstance, PluginLoader};
+#[cfg(windows)]
+use log::info;
+use log::{LevelFilter, error, warn};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
 use vst::prelude::Plugin;
-use winapi::shared::windef::HWND;
-use winapi::um::processthreadsapi::GetCurrentProcess;
-use winapi::um::processthreadsapi::SetPriorityClass;
+#[cfg(windows)]
+use winapi::um::processthreadsapi::{GetCurrentProcess, SetPriorityClass};
+#[cfg(windows)]
 use winapi::um::winbase::HIGH_PRIORITY_CLASS;
-use winapi::um::winuser::{
-    LB_GETCURSEL, LB_SETCURSEL, SW_HIDE, SW_SHOW, SendMessageA, ShowWindow, UpdateWindow,
-};
-
-use crate::config::{AtomicConfig, config_saver};
-use crate::device_callback::wait_for_audio_device_change;
-use crate::error::ErrorKind;
 
-// block non windows builds
-#[cfg(not(target_os = "windows"))]
-compile_error!("This application only supports Windows.");
+use whisper_ware_core::{
+    AtomicConfig, Compressor, CompressorHost, ErrorKind, LoadedPlugin, PluginChain,
+    SANDBOX_ARGUMENT, SandboxHost, SandboxedPlugin, config_saver, process_file, serve_sandbox,
+};
 
-mod config;
+#[cfg(windows)]
 mod device_callback;
 mod error;
+#[cfg(windows)]
+mod tray;
+
+/// the tray application only runs on Windows, other platforms have the offline mode
+#[cfg(not(windows))]
+mod tray {
+    pub(crate) fn run() -> crate::Result<()> {
+        eprintln!("the tray application only runs on Windows");
+        eprintln!("{}", crate::USAGE);
+        std::process::exit(2);
+    }
+}
 
 type Result<T> = std::result::Result<T, error::Error>;
 
-/// the size of the audio frames used for processing
-const BLOCK_SIZE: usize = 512;
-/// the class name for windowing
-const CLASS_NAME: &str = "whisperWare";
-/// the control ids for the device manager
-const IDC_INPUT_SELECT: u16 = 101;
-const IDC_OUTPUT_SELECT: u16 = 102;
+/// the usage of the offline mode
+const USAGE: &str = "usage: whisper_ware process <input.wav> <output.wav> [--preset <name>]";
 
-// shared values accessed in callbacks
 lazy_static! {
-    static ref INPUT_DEVICES: RwLock<Vec<(String, Option<String>)>> = Default::default();
-    static ref OUTPUT_DEVICES: RwLock<Vec<(String, Option<String>)>> = Default::default();
     static ref CONFIG: Arc<AtomicConfig> = {
         let (sender, receiver) = std::sync::mpsc::channel();
         let config = Arc::new(AtomicConfig::new(sender));
@@ -63,20 +49,43 @@ lazy_static! {
     };
 }
 
-/// the host for the compressor plugin
-struct CompressorHost;
-
-impl Host for CompressorHost {
-    /// callback for parameter changes
-    fn automate(&self, index: i32, value: f32) {
-        CONFIG.set_parameter(index as usize, value);
-    }
-}
-
 fn main() -> Result<()> {
-    simple_logging::log_to_file("whisper_ware.log", LevelFilter::Warn)?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // a sandbox logs to its own file so it never clobbers the log of the tray app
+    let sandbox_plugin = match args.as_slice() {
+        [command, plugin] if command == SANDBOX_ARGUMENT => Some(PathBuf::from(plugin)),
+        _ => None,
+    };
+    let log_file = match sandbox_plugin {
+        Some(_) => "whisper_ware_sandbox.log",
+        None => "whisper_ware.log",
+    };
+    simple_logging::log_to_file(log_file, LevelFilter::Warn)?;
     log_panics::init();
 
+    // offline mode: whisper_ware process <input.wav> <output.wav> [--preset <name>]
+    if let [command, options @ ..] = args.as_slice()
+        && command == "process"
+    {
+        attach_console();
+        let (input, output, preset) = match options {
+            [input, output] => (input, output, None),
+            [input, output, flag, name] if flag == "--preset" => {
+                (input, output, Some(name.as_str()))
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                std::process::exit(2);
+            }
+        };
+
+        if process(Path::new(input), Path::new(output), preset).is_err() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    #[cfg(windows)]
     unsafe {
         let process = GetCurrentProcess();
 
@@ -87,551 +96,147 @@ fn main() -> Result<()> {
         }
     }
 
-    if let Err(error) = app() {
-        win::messagebox::message_box(
-            "Whisper Ware encountered a critical error",
-            &error.to_string(),
-            &[win::messagebox::Config::IconError],
-        )?;
+    // started by a sandboxed slot: whisper_ware sandbox <plugin>
+    if let Some(plugin) = sandbox_plugin {
+        return sandbox(&plugin);
     }
 
-    Ok(())
+    tray::run()
 }
 
-/// the main application logic
-fn app() -> Result<()> {
-    let class = Arc::new(
-        win::class::build()
-            .load_icon(win::icon::Icon::FromResource(1))?
-            .background(win::class::Background::Window)
-            .load_small_icon(win::icon::Icon::FromResource(1))?
-            .register(CLASS_NAME)?,
-    );
-
-    // create the host
-    let plugin_host = Arc::new(Mutex::new(CompressorHost));
-    // initialize the plugin loader
-    let mut loader = PluginLoader::load(Path::new("RoughRider3.dll"), plugin_host)?;
-
-    // create the plugin instance
-    let mut instance = loader.instance()?;
-    CONFIG.apply_parameters(&mut instance); // apply the saved parameters
-
-    // get the editor
-    let mut editor = instance.get_editor().ok_or(ErrorKind::EditorMissing)?;
-
-    let editor_window = win::window::build()
-        .set_message_callback(editor_callback)
-        .add_extended_style(win::window::ExtendedStyle::ClientEdge)
-        .add_style(win::window::Style::OverlappedWindow)
-        .add_style(win::window::Style::Caption)
-        .add_style(win::window::Style::SysMenu)
-        .add_style(win::window::Style::Group)
-        .size(1125, 410)
-        .create(&class, "Configurator")?;
-
-    let editor_hwnd = editor_window.hwnd_ptr() as usize;
-
-    // load the icon from the resources
-    let icon = Icon::from_resource(1, None)?;
-
-    // create the tray menu
-    let configurator = MenuItem::new("Show Configurator", true, None);
-    let device_manager = MenuItem::new("Device Manager", true, None);
-    let view_log = MenuItem::new("View Log", true, None);
-    let restart_backend = MenuItem::new("Restart Backend", true, None);
-    let exit = MenuItem::new("Exit", true, None);
-
-    let tray_menu = Menu::with_items(&[
-        &configurator,
-        &device_manager,
-        &restart_backend,
-        &view_log,
-        &exit,
-    ])?;
-
-    // create the tray icon
-    let _tray_icon = TrayIconBuilder::new()
-        .with_menu(Box::new(tray_menu))
-        .with_icon(icon)
-        .build()?;
-
-    // controls the background audio processing thread
-    let run: Arc<AtomicBool> = Arc::new(AtomicBool::new(true));
-    // prevents multiple instances of the device manager from opening
-    let manager_open: Arc<AtomicBool> = Default::default();
-    // the host for the audio recording and playback
-    let cpal_host = Arc::new(default_host());
-
-    // references for the menu event handler
-    let run_clone = Arc::clone(&run);
-    let host_clone = Arc::clone(&cpal_host);
-    let class_clone = Arc::clone(&class);
-
-    MenuEvent::set_event_handler(Some(Box::new(move |event: MenuEvent| {
-        let result = menu_handler(
-            event,
-            editor_hwnd,
-            &manager_open,
-            &run_clone,
-            &host_clone,
-            &class_clone,
-        );
-
-        if let Err(error) = result {
-            error!("an error occurred in the menu handler: {}", error);
+/// runs a WAV file through the processing chain with the saved parameters or a preset
+fn process(input: &Path, output: &Path, preset: Option<&str>) -> Result<()> {
+    let mut chain = load_chain();
+
+    if let Some(name) = preset {
+        // the preset only applies to this run, the saved config is left alone
+        let preset = CONFIG.presets().load(name).inspect_err(|error| {
+            report(&format!("failed to load preset {}: {}", name, error));
+        })?;
+        for (_, instance) in chain.instances_mut() {
+            preset.apply(instance);
         }
-    })));
-
-    // references for the backend thread
-    let run_clone = Arc::clone(&run);
-
-    spawn(move || {
-        // only allows the plugin to be initialized once
-        let mut initialize = true;
-        // only log each error once
-        let mut last_error: Option<ErrorKind> = None;
-
-        loop {
-            match backend(&cpal_host, &mut instance, &run_clone, &mut initialize) {
-                Ok(()) => (),
-                Err(error) => match error.kind {
-                    ErrorKind::NoInputDevice | ErrorKind::NoOutputDevice => {
-                        debug!("waiting for audio device change");
-                        wait_for_audio_device_change();
-                        debug!("audio device change occurred");
-                        continue;
-                    }
-                    error => {
-                        if last_error.as_ref() != Some(&error) {
-                            error!("backend error: {error:?}");
-                        }
-                        last_error = Some(error);
-                    }
-                },
-            }
-
-            sleep(Duration::from_millis(100));
-        }
-    });
-
-    // open the editor
-    editor.open(editor_hwnd as *mut std::ffi::c_void);
-    // run the event loop for the editor window
-    win::message_loop();
+    }
 
+    process_file(input, output, &mut chain, &CONFIG).inspect_err(|error| {
+        report(&format!("failed to process {}: {}", input.display(), error));
+    })?;
     Ok(())
 }
 
-/// configures and runs the audio processing backend
-fn backend(
-    host: &Arc<cpal::Host>,
-    instance: &mut PluginInstance,
-    run: &Arc<AtomicBool>,
-    initialize: &mut bool,
-) -> Result<()> {
-    let (input_device_id, output_device_id) = CONFIG.devices();
-
-    let input_device = device_by_id(host, &input_device_id, cpal::Host::default_input_device)
-        .ok_or(ErrorKind::NoInputDevice)?;
-
-    let output_device = device_by_id(host, &output_device_id, cpal::Host::default_output_device)
-        .ok_or(ErrorKind::NoOutputDevice)?;
-
-    info!(
-        "output device: {}",
-        output_device
-            .description()
-            .map(|d| d.to_string())
-            .unwrap_or_else(|_| "unknown".to_string())
-    );
-    info!(
-        "input device: {}",
-        input_device
-            .description()
-            .map(|d| d.to_string())
-            .unwrap_or_else(|_| "unknown".to_string())
-    );
-
-    let input_config = input_device.default_input_config()?;
-    let output_config = output_device.default_output_config()?;
-    let input_sample_rate = input_config.sample_rate() as f32;
-    let output_sample_rate = output_config.sample_rate() as f32;
-    let input_channels = input_config.channels() as usize;
-    let output_channels = output_config.channels() as usize;
-
-    if input_sample_rate != output_sample_rate {
-        Err(ErrorKind::InvalidConfiguration(
-            "input and output sample rates are different",
-        ))?;
-    } else if input_channels != 2 || output_channels != 2 {
-        Err(ErrorKind::InvalidConfiguration("only stereo is supported"))?;
-    }
-
-    instance.set_sample_rate(input_sample_rate);
-    instance.set_block_size(BLOCK_SIZE as i64);
-
-    if *initialize {
-        instance.init();
-        *initialize = false;
-    }
-
-    let (mut input_producer, input_consumer) = RingBuffer::<[f32; 2]>::new(BLOCK_SIZE * 4);
-    let input_notify = Arc::new(Condvar::new());
-    let input_notify_clone = Arc::clone(&input_notify);
-
-    let (output_producer, mut output_consumer) = RingBuffer::<[f32; 2]>::new(BLOCK_SIZE * 4);
-
-    // allows input_stream to stop the program on errors
-    let run_clone_a = Arc::clone(run);
-    // allows output_stream to stop the program on errors
-    let run_clone_b = Arc::clone(run);
-
-    let input_stream = input_device.build_input_stream(
-        &input_config.clone().into(),
-        move |input: &[f32], _: &_| {
-            let Ok(chunk) = input_producer.write_chunk_uninit(input.len() / input_channels) else {
-                return;
-            };
-
-            chunk.fill_from_iter(input.chunks(2).map(|frame| [frame[0], frame[1]]));
-            input_notify.notify_one();
-        },
-        move |error| {
-            error!("an error occurred on the input stream: {error}");
-            run_clone_a.store(false, Relaxed);
-        },
-        None,
-    )?;
-
-    let output_stream = output_device.build_output_stream(
-        &output_config.clone().into(),
-        move |output: &mut [f32], _: &_| {
-            let num_frames = output.len() / output_channels;
-            match output_consumer.read_chunk(num_frames) {
-                Ok(chunk) => {
-                    for (samples, frame) in
-                        chunk.into_iter().zip(output.chunks_mut(output_channels))
-                    {
-                        frame[0] = samples[0];
-                        frame[1] = samples[1];
-                    }
-                }
-                Err(_) => {
-                    // Not enough samples available; fill with silence
-                    output.fill(0_f32);
-                }
-            }
-        },
-        move |error| {
-            error!("an error occurred on the output stream: {error}");
-            run_clone_b.store(false, Relaxed);
-        },
-        None,
-    )?;
-
-    input_stream.play()?;
-    output_stream.play()?;
-
-    processor(
-        input_consumer,
-        output_producer,
-        input_notify_clone,
-        instance,
-        run,
-    )
+/// tells the user of the offline mode about an error, on the console and in the log
+fn report(message: &str) {
+    error!("{}", message);
+    eprintln!("{}", message);
 }
 
-/// the audio processing thread
-fn processor(
-    mut consumer: Consumer<[f32; 2]>,
-    mut producer: Producer<[f32; 2]>,
-    notify: Arc<Condvar>,
-    instance: &mut PluginInstance,
-    run: &Arc<AtomicBool>,
-) -> Result<()> {
-    // buffers for the audio processing
-    // three inputs/outputs are needed for stereo processing
-    let mut inputs = [[0_f32; BLOCK_SIZE]; 3];
-    let mut outputs = [[0_f32; BLOCK_SIZE]; 3];
-    // the host buffer
-    let mut buffer = HostBuffer::new(3, 3);
-    // dummy mutex
-    let mutex = Mutex::new(());
-
-    while run.load(Relaxed) {
-        // block until enough slots are available
-        loop {
-            let available = consumer.slots();
-            if available >= BLOCK_SIZE {
-                break; // there are enough slots available
-            } else if consumer.is_abandoned() {
-                return Ok(()); // EOF
-            }
-            let guard = mutex.lock().unwrap();
-            drop(notify.wait(guard).unwrap());
-            continue;
-        }
-        // read at most the number of samples that will fit in dst
-        let chunk = consumer.read_chunk(BLOCK_SIZE)?;
-        // copy samples into inputs, consuming chunk
-        for (i, frame) in chunk.into_iter().enumerate() {
-            inputs[0][i] = frame[0];
-            inputs[1][i] = frame[1];
-        }
+/// connects stderr to the console the offline mode was started from, a release build is a
+/// windows app without a console of its own
+#[cfg(windows)]
+fn attach_console() {
+    use winapi::um::consoleapi::AllocConsole;
+    use winapi::um::wincon::{ATTACH_PARENT_PROCESS, AttachConsole};
 
-        // bind the buffer to the inputs and outputs
-        let mut audio_buffer = buffer.bind(&inputs, &mut outputs);
-        // process the audio
-        instance.process(&mut audio_buffer);
-
-        let available = producer.slots();
-        let to_write = BLOCK_SIZE.min(available);
-
-        // re-interleave the processed buffers and send it to the output
-        if let Ok(chunk) = producer.write_chunk_uninit(to_write) {
-            chunk.fill_from_iter(
-                outputs[0]
-                    .into_iter()
-                    .zip(outputs[1].into_iter())
-                    .map(|(a, b)| [a, b]),
-            );
+    // SAFETY: neither call takes a pointer, both fail harmlessly when a console is attached
+    unsafe {
+        if AttachConsole(ATTACH_PARENT_PROCESS) == 0 {
+            AllocConsole();
         }
     }
-
-    // restore original state
-    run.store(true, Relaxed);
-    Ok(())
 }
 
-/// menu event handler for tray application
-fn menu_handler(
-    event: MenuEvent,
-    editor_hwnd: usize,
-    manager_open: &Arc<AtomicBool>,
-    run_clone: &Arc<AtomicBool>,
-    host_clone: &Arc<cpal::Host>,
-    class_clone: &Arc<Class>,
-) -> Result<()> {
-    match event.id.as_ref().parse::<i32>() {
-        Ok(1000) => {
-            let hwnd = editor_hwnd as HWND;
-
-            unsafe {
-                ShowWindow(hwnd, SW_SHOW);
-                UpdateWindow(hwnd);
-            }
-        }
-        Ok(1001) => {
-            if manager_open.load(Relaxed) {
-                return Ok(());
-            } else {
-                let mut input_devices = INPUT_DEVICES.write().unwrap();
-                let mut output_devices = OUTPUT_DEVICES.write().unwrap();
-
-                input_devices.clear();
-                output_devices.clear();
-
-                // add "Default" entry with None as device ID
-                input_devices.push(("Default".to_string(), None));
-                output_devices.push(("Default".to_string(), None));
-
-                if let Ok(devices) = host_clone.input_devices() {
-                    for device in devices {
-                        if let (Ok(desc), Ok(id)) = (device.description(), device.id()) {
-                            input_devices.push((desc.name().to_string(), Some(id.to_string())));
-                        }
-                    }
-                }
-
-                if let Ok(devices) = host_clone.output_devices() {
-                    for device in devices {
-                        if let (Ok(desc), Ok(id)) = (device.description(), device.id()) {
-                            output_devices.push((desc.name().to_string(), Some(id.to_string())));
-                        }
-                    }
-                }
-            }
-
-            let old_devices = CONFIG.devices();
-
-            let window = win::window::build()
-                .set_message_callback(|window, message| {
-                    device_manager_callback(window, message).unwrap_or_else(|error| {
-                        error!("device manager callback failed: {}", error);
-                        Some(1)
-                    })
-                })
-                .add_extended_style(win::window::ExtendedStyle::ClientEdge)
-                .add_style(win::window::Style::OverlappedWindow)
-                .size(480, 320)
-                .create(class_clone, "Device Manager")?;
-
-            manager_open.store(true, Relaxed);
-
-            window.show_default();
-            _ = window.update();
-            win::message_loop();
-
-            manager_open.store(false, Relaxed);
-
-            if old_devices != CONFIG.devices() {
-                // restart the backend if the devices have changed
-                run_clone.store(false, Relaxed);
-            }
-        }
-        Ok(1002) => {
-            Command::new("notepad.exe")
-                .arg("whisper_ware.log")
-                .spawn()?;
+/// other platforms keep the console the process was started from
+#[cfg(not(windows))]
+fn attach_console() {}
+
+/// hosts the plugin of a sandboxed slot for the tray app that started this process
+fn sandbox(path: &Path) -> Result<()> {
+    let plugin_host = Arc::new(Mutex::new(SandboxHost));
+    let instance = match LoadedPlugin::load(path, plugin_host) {
+        Ok(instance) => instance,
+        Err(error) => {
+            error!(
+                "failed to load {} in the sandbox: {}",
+                path.display(),
+                error
+            );
+            return Err(ErrorKind::PluginLoad(error).into());
         }
-        Ok(1003) => run_clone.store(false, Relaxed),
-        Ok(1004) => std::process::exit(0),
-        event => error!("Unknown event: {:?}", event),
-    }
+    };
 
+    serve_sandbox(Box::new(instance), pump_messages)?;
     Ok(())
 }
 
-/// window callback for the device manager
-fn device_manager_callback(window: &Window, message: Message) -> Result<Option<isize>> {
-    match message {
-        Message::Create => {
-            let (input_device, output_device) = CONFIG.devices();
-
-            build_device_widget(
-                window,
-                &INPUT_DEVICES,
-                "Input Device",
-                &input_device,
-                0,
-                IDC_INPUT_SELECT,
-            )?;
-
-            build_device_widget(
-                window,
-                &OUTPUT_DEVICES,
-                "Output Device",
-                &output_device,
-                160,
-                IDC_OUTPUT_SELECT,
-            )?;
-        }
-        Message::Size(info) => {
-            let input_ctrl = window.get_dialog_item(IDC_INPUT_SELECT)?;
-            let output_ctrl = window.get_dialog_item(IDC_OUTPUT_SELECT)?;
-
-            input_ctrl.set_rect(
-                win::rect::Rect::new(info.width() as i32 / 2, info.height() as i32).at(0, 0),
-            )?;
-
-            output_ctrl.set_rect(
-                win::rect::Rect::new(info.width() as i32 / 2, info.height() as i32)
-                    .at(info.width() as i32 / 2, 0),
-            )?;
-        }
-        Message::Command(info) => unsafe {
-            if let Some(control_data) = info.control_data() {
-                let hwnd = control_data.window.hwnd_ptr();
-
-                let cur_sel = SendMessageA(hwnd, LB_GETCURSEL, 0, 0);
-
-                // the default value is -1 which will overflow and cause issues
-                if cur_sel < 0 {
-                    return Ok(None);
-                }
-
-                let index = cur_sel as usize;
-
-                if control_data.id == IDC_INPUT_SELECT {
-                    let devices = INPUT_DEVICES.read().unwrap();
-                    if let Some((_, device_id)) = devices.get(index) {
-                        CONFIG.set_input_device(device_id.clone())?;
-                    }
-                } else if control_data.id == IDC_OUTPUT_SELECT {
-                    let devices = OUTPUT_DEVICES.read().unwrap();
-                    if let Some((_, device_id)) = devices.get(index) {
-                        CONFIG.set_output_device(device_id.clone())?;
-                    }
-                }
-            }
-        },
-        Message::Close => window.destroy()?,
-        Message::Destroy => win::post_quit_message(0),
-        _ => return Ok(None),
-    }
-
-    Ok(Some(0))
-}
+/// dispatches the window messages of an editor open in the sandbox
+#[cfg(windows)]
+fn pump_messages() {
+    use winapi::um::winuser::{DispatchMessageW, MSG, PM_REMOVE, PeekMessageW, TranslateMessage};
 
-/// window callback for the configurator
-fn editor_callback(window: &Window, message: Message) -> Option<isize> {
-    match message {
-        Message::Close => {
-            // hide the window instead of destroying it
-            _ = unsafe { ShowWindow(window.hwnd_ptr(), SW_HIDE) };
-            Some(0)
+    // SAFETY: the message is plain data the calls fill in, on the thread that owns the editor
+    unsafe {
+        let mut message: MSG = std::mem::zeroed();
+        while PeekMessageW(&mut message, std::ptr::null_mut(), 0, 0, PM_REMOVE) != 0 {
+            TranslateMessage(&message);
+            DispatchMessageW(&message);
         }
-        _ => None,
     }
 }
 
-/// builds a list box widget for the device manager
-fn build_device_widget(
-    window: &Window,
-    devices: &RwLock<Vec<(String, Option<String>)>>,
-    name: &str,
-    selected: &Option<String>,
-    x: i32,
-    control_id: u16,
-) -> Result<()> {
-    let ctrl = win::window::build()
-        .add_style(win::window::Style::Visible)
-        .add_style(win::window::Style::Center)
-        .add_style(win::window::Style::Caption)
-        .pos(x, 0)
-        .size(150, 100)
-        .parent(window)
-        .set_child_id(control_id)
-        .create(win::class::list_box(), name)?;
-
-    let mut selected_output = None;
-
-    for (index, (display_name, device_id)) in devices.read().unwrap().iter().enumerate() {
-        if device_id == selected {
-            selected_output = Some(index);
-        }
+/// other platforms have no editor windows to dispatch
+#[cfg(not(windows))]
+fn pump_messages() {}
 
-        // this error is ignored because it is not critical
-        _ = ctrl.add_string_item(display_name);
-    }
+/// loads the plugin of every slot and applies their saved values
+pub(crate) fn load_chain() -> PluginChain {
+    let mut chain = PluginChain::new();
 
-    if let Some(index) = selected_output {
-        let hwnd = ctrl.hwnd_ptr();
-        unsafe {
-            SendMessageA(hwnd, LB_SETCURSEL, index, 0);
-        }
+    for slot in CONFIG.slot_ids() {
+        let plugin_host = CompressorHost::new(Arc::clone(&CONFIG), slot, chain.transport());
+        let plugin_host = Arc::new(Mutex::new(plugin_host));
+        let mut instance = load_plugin(slot, plugin_host);
+        CONFIG.bind_parameters(slot, instance.as_mut());
+        chain.push(slot, instance);
     }
 
-    Ok(())
+    chain
 }
 
-fn device_by_id(
-    host: &cpal::Host,
-    id: &Option<String>,
-    default: fn(&cpal::Host) -> Option<Device>,
-) -> Option<Device> {
-    match id {
-        None => default(host),
-        Some(id_string) => match id_string.parse::<cpal::DeviceId>() {
-            Ok(device_id) => host.device_by_id(&device_id),
-            Err(err) => {
+/// loads the plugin of a slot, falling back to the native compressor when it is unavailable
+fn load_plugin(slot: u32, plugin_host: Arc<Mutex<CompressorHost>>) -> Box<dyn Plugin> {
+    let Some(path) = CONFIG.resolve_plugin(slot) else {
+        if let Some(path) = CONFIG.plugin_path(slot) {
+            warn!(
+                "plugin {} not found, using the native compressor",
+                path.display()
+            );
+        }
+        return Box::new(Compressor::default());
+    };
+
+    if CONFIG.sandboxed(slot) {
+        match SandboxedPlugin::spawn(&path, plugin_host) {
+            Ok(instance) => return Box::new(instance),
+            Err(error) => {
                 warn!(
-                    "failed to parse device ID '{}': {}, falling back to default",
-                    id_string, err
+                    "failed to start the sandbox of {}, using the native compressor: {}",
+                    path.display(),
+                    error
                 );
-                default(host)
+                return Box::new(Compressor::default());
             }
-        },
+        }
+    }
+
+    match LoadedPlugin::load(&path, plugin_host) {
+        Ok(instance) => Box::new(instance),
+        Err(error) => {
+            warn!(
+                "failed to load {}, using the native compressor: {}",
+                path.display(),
+                error
+            );
+            Box::new(Compressor::default())
+        }
     }
 }
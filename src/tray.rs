@@ -0,0 +1,1109 @@
+//! the Windows tray application: the tray menu, the device manager, the plugin picker and
+//! the plugin editors
+
+use cpal::default_host;
+use cpal::traits::{DeviceTrait, HostTrait};
+use lazy_static::lazy_static;
+use log::{error, warn};
+use minimal_windows_gui as win;
+use minimal_windows_gui::class::Class;
+use minimal_windows_gui::message::Message;
+use minimal_windows_gui::window::Window;
+use std::cell::RefCell;
+use std::ffi::{OsStr, OsString};
+use std::fs::{create_dir_all, metadata};
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::path::PathBuf;
+use std::process::Command;
+use std::ptr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::spawn;
+use std::time::SystemTime;
+use tray_icon::menu::{
+    CheckMenuItem, IsMenuItem, MenuEvent, MenuItem, PredefinedMenuItem, Submenu,
+};
+use tray_icon::{Icon, TrayIconBuilder, menu::Menu};
+use winapi::shared::basetsd::UINT_PTR;
+use winapi::shared::minwindef::{DWORD, MAX_PATH, UINT};
+use winapi::shared::windef::{HMENU, HWND};
+use winapi::um::commdlg::{
+    GetOpenFileNameW, GetSaveFileNameW, OFN_FILEMUSTEXIST, OFN_NOCHANGEDIR, OFN_OVERWRITEPROMPT,
+    OPENFILENAMEW,
+};
+use winapi::um::winuser::{
+    BS_DEFPUSHBUTTON, CreateWindowExW, ES_AUTOHSCROLL, GetWindowTextLengthW, GetWindowTextW, IDYES,
+    LB_GETCURSEL, LB_SETCURSEL, MB_ICONQUESTION, MB_YESNO, MessageBoxW, SW_HIDE, SW_SHOW,
+    SendMessageA, SetFocus, SetTimer, ShowWindow, UpdateWindow, WS_BORDER, WS_CHILD, WS_TABSTOP,
+    WS_VISIBLE,
+};
+
+use whisper_ware_core::{
+    BackendControl, BackendState, SidechainSource, Transport, Watchdog, config_watcher,
+};
+
+use crate::device_callback::EndpointWatcher;
+use crate::{CONFIG, Result, load_chain};
+
+/// the class name for windowing
+const CLASS_NAME: &str = "whisperWare";
+/// the control ids for the device manager
+const IDC_INPUT_SELECT: u16 = 101;
+const IDC_OUTPUT_SELECT: u16 = 102;
+const IDC_SIDECHAIN_SELECT: u16 = 104;
+/// the control id for the plugin picker
+const IDC_PLUGIN_SELECT: u16 = 103;
+/// the control ids for the preset name prompt
+const IDC_PRESET_NAME: u16 = 105;
+const IDC_PRESET_OK: u16 = 106;
+/// the menu id prefix of the stored preset items, followed by the action and the preset name
+const PRESET_PREFIX: &str = "preset:";
+/// the menu id of the item saving the current parameters as a preset
+const SAVE_PRESET_ID: &str = "save_preset";
+/// the menu id of the item copying a preset file into the presets directory
+const IMPORT_PRESET_ID: &str = "import_preset";
+/// the menu id of the item opening the presets directory
+const OPEN_PRESETS_ID: &str = "open_presets";
+/// the menu id prefix of the fxp and fxb items, followed by the file name
+const FX_PREFIX: &str = "fx:";
+/// the menu id prefix of the plugin slot items, followed by the slot id and the action
+const SLOT_PREFIX: &str = "slot:";
+/// the menu id of the item adding a plugin to the chain
+const ADD_PLUGIN_ID: &str = "add_plugin";
+/// the menu id of the item showing the latency of the stream
+const LATENCY_ID: &str = "latency";
+/// the menu id of the disabled item showing the backend state
+const STATUS_ID: &str = "status";
+/// how often the status item picks up backend state changes, in milliseconds
+const STATUS_INTERVAL: UINT = 250;
+/// how often the presets submenu picks up files added to the presets directory, in milliseconds
+const PRESETS_INTERVAL: UINT = 1000;
+
+// shared values accessed in callbacks
+lazy_static! {
+    static ref INPUT_DEVICES: RwLock<Vec<(String, Option<String>)>> = Default::default();
+    static ref OUTPUT_DEVICES: RwLock<Vec<(String, Option<String>)>> = Default::default();
+    static ref SIDECHAIN_DEVICES: RwLock<Vec<(String, Option<String>)>> = Default::default();
+    static ref PLUGINS: RwLock<Vec<(String, Option<String>)>> = Default::default();
+    /// the index in PLUGINS chosen in the plugin picker
+    static ref PICKED_PLUGIN: Mutex<Option<usize>> = Default::default();
+    /// the slot the plugin picker chooses for, none when adding a plugin
+    static ref PICKER_SLOT: Mutex<Option<u32>> = Default::default();
+    /// the name the preset name prompt starts with
+    static ref PROMPT_DEFAULT: Mutex<String> = Default::default();
+    /// the name confirmed in the preset name prompt, none when it was closed
+    static ref PROMPT_NAME: Mutex<Option<String>> = Default::default();
+    /// restarts the backend when the stream stalls and records how often it does
+    static ref WATCHDOG: Watchdog = Watchdog::open(CONFIG.stall_log()).on_hang(relaunch);
+    /// runs the backend and reports its state
+    static ref CONTROL: BackendControl = BackendControl::new();
+}
+
+// menu items live on the thread that owns the tray, which also handles the menu events
+thread_local! {
+    /// the sidechain source items of each slot
+    static SIDECHAIN_ITEMS: RefCell<Vec<(u32, Vec<(SidechainSource, CheckMenuItem)>)>> =
+        Default::default();
+    /// the status item and the backend states it shows, refreshed by a timer
+    static STATUS: RefCell<Option<(MenuItem, Receiver<BackendState>)>> = Default::default();
+    /// the presets submenu, filled again when the presets change
+    static PRESETS: RefCell<Option<PresetMenu>> = Default::default();
+}
+
+/// the presets submenu and the items it currently holds
+struct PresetMenu {
+    menu: Submenu,
+    items: Vec<Box<dyn IsMenuItem>>,
+    /// when the presets directory changed before the items were built
+    modified: Option<SystemTime>,
+}
+
+impl PresetMenu {
+    fn new(menu: Submenu) -> Result<Self> {
+        let mut presets = PresetMenu {
+            menu,
+            items: Vec::new(),
+            modified: None,
+        };
+        presets.fill()?;
+        Ok(presets)
+    }
+
+    /// replaces the items with the stored presets, the fxp and fxb files and the actions
+    fn fill(&mut self) -> Result<()> {
+        for item in self.items.drain(..) {
+            self.menu.remove(item.as_ref())?;
+        }
+
+        let store = CONFIG.presets();
+        self.modified = presets_modified();
+
+        // one submenu per stored preset, identified by the action and the preset name
+        for name in store.list().unwrap_or_default() {
+            let id = |action: &str| format!("{PRESET_PREFIX}{action}:{name}");
+            let load = MenuItem::with_id(id("load"), "Load", true, None);
+            let rename = MenuItem::with_id(id("rename"), "Rename", true, None);
+            let export = MenuItem::with_id(id("export"), "Export", true, None);
+            let delete = MenuItem::with_id(id("delete"), "Delete", true, None);
+
+            self.items.push(Box::new(Submenu::with_items(
+                &name,
+                true,
+                &[&load, &rename, &export, &delete],
+            )?));
+        }
+
+        // the programs and banks shared with DAWs, identified by their file name
+        let fx_files = store.fx_files().unwrap_or_default();
+        let fx_names: Vec<&str> = fx_files
+            .iter()
+            .filter_map(|path| path.file_name()?.to_str())
+            .collect();
+        if !fx_names.is_empty() {
+            self.items.push(Box::new(PredefinedMenuItem::separator()));
+        }
+        for name in fx_names {
+            let id = format!("{FX_PREFIX}{name}");
+            self.items
+                .push(Box::new(MenuItem::with_id(id, name, true, None)));
+        }
+
+        self.items.push(Box::new(PredefinedMenuItem::separator()));
+        self.items.push(Box::new(MenuItem::with_id(
+            SAVE_PRESET_ID,
+            "Save as Preset",
+            true,
+            None,
+        )));
+        self.items.push(Box::new(MenuItem::with_id(
+            IMPORT_PRESET_ID,
+            "Import Preset",
+            true,
+            None,
+        )));
+        self.items.push(Box::new(MenuItem::with_id(
+            OPEN_PRESETS_ID,
+            "Open Presets Folder",
+            true,
+            None,
+        )));
+
+        for item in &self.items {
+            self.menu.append(item.as_ref())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// runs the tray application, showing a critical error in a message box
+pub(crate) fn run() -> Result<()> {
+    if let Err(error) = app() {
+        win::messagebox::message_box(
+            "Whisper Ware encountered a critical error",
+            &error.to_string(),
+            &[win::messagebox::Config::IconError],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// the main application logic
+fn app() -> Result<()> {
+    let class = Arc::new(
+        win::class::build()
+            .load_icon(win::icon::Icon::FromResource(1))?
+            .background(win::class::Background::Window)
+            .load_small_icon(win::icon::Icon::FromResource(1))?
+            .register(CLASS_NAME)?,
+    );
+
+    // let the user choose another plugin for every slot whose plugin is missing
+    for slot in CONFIG.slot_ids() {
+        if let Some(path) = CONFIG.plugin_path(slot)
+            && CONFIG.resolve_plugin(slot).is_none()
+        {
+            warn!(
+                "plugin {} not found, opening the plugin picker",
+                path.display()
+            );
+            pick_plugin(&class, Some(slot))?;
+        }
+    }
+
+    // create the plugin instances, reading their parameters and applying the saved values
+    let mut chain = load_chain();
+
+    // one configurator window per plugin editor, the native compressor does not have one
+    let mut editors = Vec::new();
+    // the slot id, name and editor window of every plugin for the tray menu
+    let mut slots = Vec::new();
+    for (slot, instance) in chain.instances_mut() {
+        let name = instance.get_info().name;
+        let mut editor_hwnd = None;
+
+        if let Some(editor) = instance.get_editor() {
+            let editor_window = win::window::build()
+                .set_message_callback(editor_callback)
+                .add_extended_style(win::window::ExtendedStyle::ClientEdge)
+                .add_style(win::window::Style::OverlappedWindow)
+                .add_style(win::window::Style::Caption)
+                .add_style(win::window::Style::SysMenu)
+                .add_style(win::window::Style::Group)
+                .size(1125, 410)
+                .create(&class, &format!("{name} - Configurator"))?;
+
+            let hwnd = editor_window.hwnd_ptr() as usize;
+            editors.push((editor, hwnd));
+            editor_hwnd = Some(hwnd);
+        }
+
+        slots.push((slot, name, editor_hwnd));
+    }
+    let editor_hwnds: Vec<(u32, usize)> = slots
+        .iter()
+        .filter_map(|(slot, _, hwnd)| Some((*slot, (*hwnd)?)))
+        .collect();
+
+    // load the icon from the resources
+    let icon = Icon::from_resource(1, None)?;
+
+    // create the tray menu
+    let configurator = MenuItem::new("Show Configurator", !editors.is_empty(), None);
+    let device_manager = MenuItem::new("Device Manager", true, None);
+    let latency = MenuItem::with_id(LATENCY_ID, "Latency", true, None);
+    let view_log = MenuItem::new("View Log", true, None);
+    let restart_backend = MenuItem::new("Restart Backend", true, None);
+    let exit = MenuItem::new("Exit", true, None);
+    // created after the numbered items so their ids stay the same
+    let status = MenuItem::with_id(STATUS_ID, status_text(&CONTROL.state()), false, None);
+    let status_separator = PredefinedMenuItem::separator();
+
+    // filled with the presets now and again whenever they change
+    let presets = Submenu::new("Presets", true);
+    PRESETS.set(Some(PresetMenu::new(presets.clone())?));
+
+    // one submenu per plugin in the chain, identified by its slot id
+    let mut slot_menus = Vec::new();
+    for (slot, name, editor_hwnd) in &slots {
+        let id = |action: &str| format!("{SLOT_PREFIX}{slot}:{action}");
+        let show_editor =
+            MenuItem::with_id(id("editor"), "Show Editor", editor_hwnd.is_some(), None);
+        let bypassed = CONFIG.bypassed(*slot);
+        let bypass = CheckMenuItem::with_id(id("bypass"), "Bypass", true, bypassed, None);
+        let current = CONFIG.sidechain(*slot).unwrap_or_default();
+        let sources: Vec<_> = [
+            ("sidechain_off", "Off", SidechainSource::Silence),
+            ("sidechain_input", "Input", SidechainSource::Input),
+            (
+                "sidechain_device",
+                "Sidechain Device",
+                SidechainSource::Device,
+            ),
+        ]
+        .into_iter()
+        .map(|(action, text, source)| {
+            let checked = source == current;
+            (
+                source,
+                CheckMenuItem::with_id(id(action), text, true, checked, None),
+            )
+        })
+        .collect();
+        let source_items: Vec<&dyn IsMenuItem> = sources
+            .iter()
+            .map(|(_, item)| item as &dyn IsMenuItem)
+            .collect();
+        let sidechain = Submenu::with_items("Sidechain", true, &source_items)?;
+        let sandboxed = CONFIG.sandboxed(*slot);
+        let sandbox = CheckMenuItem::with_id(id("sandbox"), "Run Isolated", true, sandboxed, None);
+        SIDECHAIN_ITEMS.with_borrow_mut(|items| items.push((*slot, sources)));
+        let move_up = MenuItem::with_id(id("up"), "Move Up", true, None);
+        let move_down = MenuItem::with_id(id("down"), "Move Down", true, None);
+        let export_fx = MenuItem::with_id(id("export"), "Export FXP/FXB", true, None);
+
+        slot_menus.push(Submenu::with_items(
+            name,
+            true,
+            &[
+                &show_editor,
+                &bypass,
+                &sidechain,
+                &sandbox,
+                &move_up,
+                &move_down,
+                &export_fx,
+            ],
+        )?);
+    }
+    let plugins_separator = PredefinedMenuItem::separator();
+    let add_plugin = MenuItem::with_id(ADD_PLUGIN_ID, "Add Plugin", true, None);
+
+    let mut plugins_items: Vec<&dyn IsMenuItem> = slot_menus
+        .iter()
+        .map(|item| item as &dyn IsMenuItem)
+        .collect();
+    plugins_items.extend([&plugins_separator as &dyn IsMenuItem, &add_plugin]);
+    let plugins = Submenu::with_items("Plugins", true, &plugins_items)?;
+
+    let tray_menu = Menu::with_items(&[
+        &status,
+        &status_separator,
+        &configurator,
+        &presets,
+        &plugins,
+        &device_manager,
+        &latency,
+        &restart_backend,
+        &view_log,
+        &exit,
+    ])?;
+
+    // create the tray icon
+    let _tray_icon = TrayIconBuilder::new()
+        .with_menu(Box::new(tray_menu))
+        .with_icon(icon)
+        .build()?;
+
+    // the status item follows the backend from the thread that owns the tray
+    STATUS.set(Some((status, CONTROL.subscribe())));
+    unsafe {
+        SetTimer(ptr::null_mut(), 0, STATUS_INTERVAL, Some(refresh_status));
+        SetTimer(ptr::null_mut(), 0, PRESETS_INTERVAL, Some(refresh_presets));
+    }
+
+    // prevents multiple instances of the device manager from opening
+    let manager_open: Arc<AtomicBool> = Default::default();
+    // the host for the audio recording and playback
+    let cpal_host = Arc::new(default_host());
+
+    // reload external edits to the config, restarting the backend like the device manager
+    spawn(move || config_watcher(Arc::clone(&CONFIG), || CONTROL.restart()));
+
+    // references for the menu event handler
+    let host_clone = Arc::clone(&cpal_host);
+    let class_clone = Arc::clone(&class);
+    let transport = chain.transport();
+
+    MenuEvent::set_event_handler(Some(Box::new(move |event: MenuEvent| {
+        let result = menu_handler(
+            event,
+            &editor_hwnds,
+            &manager_open,
+            &host_clone,
+            &class_clone,
+            &transport,
+        );
+
+        if let Err(error) = result {
+            error!("an error occurred in the menu handler: {}", error);
+        }
+    })));
+
+    // follows the default devices and wakes the backend while it waits for a device
+    spawn(|| match EndpointWatcher::new() {
+        Ok(mut watcher) => CONTROL.watch_devices(&mut watcher),
+        Err(error) => error!("failed to watch the audio devices: {}", error),
+    });
+
+    spawn(move || CONTROL.run(&cpal_host, &CONFIG, &mut chain, &WATCHDOG));
+
+    // open the editors
+    for (mut editor, editor_hwnd) in editors {
+        editor.open(editor_hwnd as *mut std::ffi::c_void);
+    }
+    // run the event loop for the editor windows
+    win::message_loop();
+
+    Ok(())
+}
+
+/// starts the application again and exits, the only way past a plugin hung inside process
+///
+/// the watchdog already switched the slot to run isolated, which applies from the new start.
+/// the plugin state is not saved since reading it may wait on the hung plugin
+fn relaunch() {
+    if let Err(error) = CONFIG.save_settings() {
+        error!("failed to save the config before starting again: {}", error);
+    }
+    match std::env::current_exe().and_then(|exe| Command::new(exe).spawn()) {
+        Ok(_) => std::process::exit(0),
+        Err(error) => error!("failed to start the application again: {}", error),
+    }
+}
+
+/// scans for plugins and lets the user choose the one a slot loads on the next start
+///
+/// without a slot the chosen plugin is added to the end of the chain
+fn pick_plugin(class: &Class, slot: Option<u32>) -> Result<()> {
+    let plugins = CONFIG.plugin_scanner().scan(&CONFIG.plugin_search_paths());
+
+    let mut items = PLUGINS.write().unwrap();
+    items.clear();
+    // the native compressor is stored as no plugin path
+    items.push(("Built-in Compressor".to_string(), None));
+    items.extend(plugins.into_iter().map(|plugin| {
+        let name = format!(
+            "{} ({}, {} in / {} out, {} parameters)",
+            plugin.name, plugin.vendor, plugin.inputs, plugin.outputs, plugin.parameters
+        );
+        (name, Some(plugin.path.to_string_lossy().into_owned()))
+    }));
+    drop(items);
+
+    *PICKED_PLUGIN.lock().unwrap() = None;
+    *PICKER_SLOT.lock().unwrap() = slot;
+
+    let window = win::window::build()
+        .set_message_callback(|window, message| {
+            plugin_picker_callback(window, message).unwrap_or_else(|error| {
+                error!("plugin picker callback failed: {}", error);
+                Some(1)
+            })
+        })
+        .add_extended_style(win::window::ExtendedStyle::ClientEdge)
+        .add_style(win::window::Style::OverlappedWindow)
+        .size(480, 320)
+        .create(class, "Choose Plugin")?;
+
+    window.show_default();
+    _ = window.update();
+    win::message_loop();
+
+    let Some(index) = PICKED_PLUGIN.lock().unwrap().take() else {
+        return Ok(());
+    };
+    let path = PLUGINS.read().unwrap()[index].1.as_ref().map(PathBuf::from);
+    match slot {
+        Some(slot) => CONFIG.set_plugin_path(slot, path),
+        None => {
+            CONFIG.add_slot(path);
+            win::messagebox::message_box(
+                "Plugin added",
+                "The plugin is loaded the next time WhisperWare starts",
+                &[],
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// saves, imports, loads, renames, exports or deletes a preset for a presets menu item
+fn preset_action(class: &Class, id: &str) -> Result<()> {
+    let store = CONFIG.presets();
+
+    if id == SAVE_PRESET_ID {
+        if let Some(name) = prompt_name(class, "Save as Preset", "")? {
+            store.save(&CONFIG.capture_preset(&name))?;
+        }
+        return Ok(());
+    } else if id == IMPORT_PRESET_ID {
+        if let Some(path) = choose_preset_file(false, "") {
+            store.import(&path)?;
+        }
+        return Ok(());
+    }
+
+    let Some((action, name)) = id
+        .strip_prefix(PRESET_PREFIX)
+        .and_then(|rest| rest.split_once(':'))
+    else {
+        return Ok(());
+    };
+    match action {
+        // the backend pushes the values to the plugin before its next block
+        "load" => CONFIG.apply_preset(&store.load(name)?),
+        "rename" => {
+            if let Some(new_name) = prompt_name(class, "Rename Preset", name)? {
+                store.rename(name, &new_name)?;
+            }
+        }
+        "export" => {
+            if let Some(path) = choose_preset_file(true, name) {
+                store.export(name, &path)?;
+            }
+        }
+        "delete" => {
+            let text = wide(&format!("Delete the preset {name}?"));
+            let caption = wide("Delete Preset");
+            // SAFETY: the strings are null terminated and outlive the call
+            let answer = unsafe {
+                MessageBoxW(
+                    ptr::null_mut(),
+                    text.as_ptr(),
+                    caption.as_ptr(),
+                    MB_YESNO | MB_ICONQUESTION,
+                )
+            };
+            if answer == IDYES {
+                store.delete(name)?;
+            }
+        }
+        action => error!("Unknown preset action: {}", action),
+    }
+
+    Ok(())
+}
+
+/// asks for the name of a preset, none when the prompt is closed without confirming
+fn prompt_name(class: &Class, title: &str, default: &str) -> Result<Option<String>> {
+    *PROMPT_DEFAULT.lock().unwrap() = default.to_string();
+    *PROMPT_NAME.lock().unwrap() = None;
+
+    let window = win::window::build()
+        .set_message_callback(|window, message| {
+            name_prompt_callback(window, message).unwrap_or_else(|error| {
+                error!("preset name prompt callback failed: {}", error);
+                Some(1)
+            })
+        })
+        .add_extended_style(win::window::ExtendedStyle::ClientEdge)
+        .add_style(win::window::Style::OverlappedWindow)
+        .size(320, 120)
+        .create(class, title)?;
+
+    window.show_default();
+    _ = window.update();
+    win::message_loop();
+
+    Ok(PROMPT_NAME.lock().unwrap().take())
+}
+
+/// asks which preset file to import or where to export a preset to, none when cancelled
+fn choose_preset_file(save: bool, name: &str) -> Option<PathBuf> {
+    let filter = wide("Presets (*.json)\0*.json\0");
+    let extension = wide("json");
+    // starts with the suggested file name and receives the chosen path
+    let mut file = wide(name);
+    file.resize(file.len().max(MAX_PATH), 0);
+
+    // SAFETY: all fields of the struct may be zero
+    let mut dialog: OPENFILENAMEW = unsafe { std::mem::zeroed() };
+    dialog.lStructSize = size_of::<OPENFILENAMEW>() as DWORD;
+    dialog.lpstrFilter = filter.as_ptr();
+    dialog.lpstrFile = file.as_mut_ptr();
+    dialog.nMaxFile = file.len() as DWORD;
+    dialog.lpstrDefExt = extension.as_ptr();
+    dialog.Flags = OFN_NOCHANGEDIR
+        | if save {
+            OFN_OVERWRITEPROMPT
+        } else {
+            OFN_FILEMUSTEXIST
+        };
+
+    // SAFETY: the strings and the buffer outlive the call, which returns once the dialog closed
+    let chosen = unsafe {
+        if save {
+            GetSaveFileNameW(&mut dialog)
+        } else {
+            GetOpenFileNameW(&mut dialog)
+        }
+    };
+    if chosen == 0 {
+        return None;
+    }
+
+    let length = file.iter().position(|c| *c == 0).unwrap_or(file.len());
+    Some(PathBuf::from(OsString::from_wide(&file[..length])))
+}
+
+/// a null terminated wide string for the windows calls
+fn wide(text: &str) -> Vec<u16> {
+    OsStr::new(text).encode_wide().chain(Some(0)).collect()
+}
+
+/// the text of the status item for a backend state
+fn status_text(state: &BackendState) -> String {
+    format!("Status: {state}")
+}
+
+/// shows the latest backend state on the status item, called by a timer on the tray thread
+unsafe extern "system" fn refresh_status(_: HWND, _: UINT, _: UINT_PTR, _: DWORD) {
+    STATUS.with_borrow(|status| {
+        if let Some((item, states)) = status
+            && let Some(state) = states.try_iter().last()
+        {
+            item.set_text(status_text(&state));
+        }
+    });
+}
+
+/// when files were last added to, renamed in or removed from the presets directory
+fn presets_modified() -> Option<SystemTime> {
+    metadata(CONFIG.presets().directory())
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// fills the presets submenu again after the files in the presets directory changed
+fn refresh_preset_menu(force: bool) -> Result<()> {
+    PRESETS.with_borrow_mut(|presets| match presets {
+        Some(presets) if force || presets.modified != presets_modified() => presets.fill(),
+        _ => Ok(()),
+    })
+}
+
+/// picks up presets copied into the presets directory, called by a timer on the tray thread
+unsafe extern "system" fn refresh_presets(_: HWND, _: UINT, _: UINT_PTR, _: DWORD) {
+    if let Err(error) = refresh_preset_menu(false) {
+        error!("failed to refresh the presets: {}", error);
+    }
+}
+
+/// menu event handler for tray application
+fn menu_handler(
+    event: MenuEvent,
+    editor_hwnds: &[(u32, usize)],
+    manager_open: &Arc<AtomicBool>,
+    host_clone: &Arc<cpal::Host>,
+    class_clone: &Arc<Class>,
+    transport: &Transport,
+) -> Result<()> {
+    let id = event.id.as_ref();
+
+    if id.starts_with(PRESET_PREFIX) || id == SAVE_PRESET_ID || id == IMPORT_PRESET_ID {
+        // a taken or invalid name is shown rather than only logged
+        if let Err(error) = preset_action(class_clone, id) {
+            win::messagebox::message_box(
+                "Presets",
+                &error.to_string(),
+                &[win::messagebox::Config::IconError],
+            )?;
+        }
+        // the menu follows the presets even when the action failed halfway
+        refresh_preset_menu(true)?;
+        return Ok(());
+    } else if id == OPEN_PRESETS_ID {
+        let directory = CONFIG.presets().directory().to_path_buf();
+        create_dir_all(&directory)?;
+        Command::new("explorer.exe").arg(directory).spawn()?;
+        return Ok(());
+    } else if let Some(name) = id.strip_prefix(FX_PREFIX) {
+        CONFIG.import_fx(&CONFIG.presets().directory().join(name))?;
+        return Ok(());
+    } else if id == LATENCY_ID {
+        let latency = transport.latency();
+        let stages = [
+            ("Input device", latency.input_device),
+            ("Input buffer", latency.input_buffer),
+            ("Plugins", latency.plugins),
+            ("Limiter", latency.limiter),
+            ("Resampler", latency.resampler),
+            ("Output buffer", latency.output_buffer),
+            ("Output device", latency.output_device),
+            ("Total", latency.total()),
+        ];
+        let mut text: Vec<String> = stages
+            .iter()
+            .map(|(stage, delay)| format!("{stage}: {:.1} ms", delay.as_secs_f64() * 1000_f64))
+            .collect();
+        if let Some(ppm) = latency.drift_ppm {
+            text.push(format!("Clock drift correction: {ppm:+.0} ppm"));
+        }
+        text.push(format!(
+            "\nStalls since launch: {}\nStalls in total: {}",
+            WATCHDOG.stalls().len(),
+            WATCHDOG.total_stalls()
+        ));
+        win::messagebox::message_box("Latency", &text.join("\n"), &[])?;
+        return Ok(());
+    } else if id == ADD_PLUGIN_ID {
+        // shares the guard with the device manager since both run a nested message loop
+        if !manager_open.swap(true, Relaxed) {
+            let result = pick_plugin(class_clone, None);
+            manager_open.store(false, Relaxed);
+            result?;
+        }
+        return Ok(());
+    } else if let Some((slot, action)) = id
+        .strip_prefix(SLOT_PREFIX)
+        .and_then(|rest| rest.split_once(':'))
+    {
+        let slot: u32 = slot.parse().unwrap_or_default();
+
+        match action {
+            "editor" => {
+                if let Some((_, hwnd)) = editor_hwnds.iter().find(|(other, _)| *other == slot) {
+                    unsafe {
+                        ShowWindow(*hwnd as HWND, SW_SHOW);
+                        UpdateWindow(*hwnd as HWND);
+                    }
+                }
+            }
+            "bypass" => CONFIG.set_bypass(slot, !CONFIG.bypassed(slot)),
+            // the backend arranges the chain when it restarts
+            "sidechain_off" | "sidechain_input" | "sidechain_device" => {
+                let source = match action {
+                    "sidechain_input" => SidechainSource::Input,
+                    "sidechain_device" => SidechainSource::Device,
+                    _ => SidechainSource::Silence,
+                };
+                // the sources of a slot behave like radio items
+                SIDECHAIN_ITEMS.with_borrow(|items| {
+                    for (_, sources) in items.iter().filter(|(other, _)| *other == slot) {
+                        for (other, item) in sources {
+                            item.set_checked(*other == source);
+                        }
+                    }
+                });
+                CONFIG.set_sidechain(slot, source);
+                CONTROL.restart();
+            }
+            // the plugin is loaded again in or out of its sandbox on the next start
+            "sandbox" => {
+                CONFIG.set_sandboxed(slot, !CONFIG.sandboxed(slot));
+                win::messagebox::message_box(
+                    "Run Isolated",
+                    "The change takes effect the next time WhisperWare starts.",
+                    &[],
+                )?;
+            }
+            "up" => {
+                CONFIG.move_slot(slot, -1);
+                CONTROL.restart();
+            }
+            "down" => {
+                CONFIG.move_slot(slot, 1);
+                CONTROL.restart();
+            }
+            "export" => {
+                let directory = CONFIG.presets().directory().to_path_buf();
+                create_dir_all(&directory)?;
+                CONFIG.export_fx(slot, &directory.join(format!("Slot {slot}.fxp")))?;
+                CONFIG.export_fx(slot, &directory.join(format!("Slot {slot}.fxb")))?;
+            }
+            action => error!("Unknown slot action: {}", action),
+        }
+        return Ok(());
+    }
+
+    match id.parse::<i32>() {
+        Ok(1000) => {
+            for (_, hwnd) in editor_hwnds {
+                unsafe {
+                    ShowWindow(*hwnd as HWND, SW_SHOW);
+                    UpdateWindow(*hwnd as HWND);
+                }
+            }
+        }
+        Ok(1001) => {
+            if manager_open.load(Relaxed) {
+                return Ok(());
+            } else {
+                let mut input_devices = INPUT_DEVICES.write().unwrap();
+                let mut output_devices = OUTPUT_DEVICES.write().unwrap();
+                let mut sidechain_devices = SIDECHAIN_DEVICES.write().unwrap();
+
+                input_devices.clear();
+                output_devices.clear();
+                sidechain_devices.clear();
+
+                // add "Default" entry with None as device ID
+                input_devices.push(("Default".to_string(), None));
+                output_devices.push(("Default".to_string(), None));
+                // there is no default sidechain device
+                sidechain_devices.push(("None".to_string(), None));
+
+                if let Ok(devices) = host_clone.input_devices() {
+                    for device in devices {
+                        if let (Ok(desc), Ok(id)) = (device.description(), device.id()) {
+                            input_devices.push((desc.name().to_string(), Some(id.to_string())));
+                        }
+                    }
+                }
+                sidechain_devices.extend(input_devices[1..].iter().cloned());
+
+                if let Ok(devices) = host_clone.output_devices() {
+                    for device in devices {
+                        if let (Ok(desc), Ok(id)) = (device.description(), device.id()) {
+                            output_devices.push((desc.name().to_string(), Some(id.to_string())));
+                        }
+                    }
+                }
+            }
+
+            let old_devices = (CONFIG.devices(), CONFIG.sidechain_device());
+
+            let window = win::window::build()
+                .set_message_callback(|window, message| {
+                    device_manager_callback(window, message).unwrap_or_else(|error| {
+                        error!("device manager callback failed: {}", error);
+                        Some(1)
+                    })
+                })
+                .add_extended_style(win::window::ExtendedStyle::ClientEdge)
+                .add_style(win::window::Style::OverlappedWindow)
+                .size(720, 320)
+                .create(class_clone, "Device Manager")?;
+
+            manager_open.store(true, Relaxed);
+
+            window.show_default();
+            _ = window.update();
+            win::message_loop();
+
+            manager_open.store(false, Relaxed);
+
+            if old_devices != (CONFIG.devices(), CONFIG.sidechain_device()) {
+                // restart the backend if the devices have changed
+                CONTROL.restart();
+            }
+        }
+        Ok(1002) => {
+            Command::new("notepad.exe")
+                .arg("whisper_ware.log")
+                .spawn()?;
+        }
+        Ok(1003) => CONTROL.restart(),
+        Ok(1004) => {
+            // the saver may still be waiting out its debounce window
+            if let Err(error) = CONFIG.save() {
+                error!("Failed to save config file: {}", error);
+            }
+            std::process::exit(0)
+        }
+        event => error!("Unknown event: {:?}", event),
+    }
+
+    Ok(())
+}
+
+/// window callback for the device manager
+fn device_manager_callback(window: &Window, message: Message) -> Result<Option<isize>> {
+    match message {
+        Message::Create => {
+            let (input_device, output_device) = CONFIG.devices();
+
+            build_device_widget(
+                window,
+                &INPUT_DEVICES,
+                "Input Device",
+                &input_device,
+                0,
+                IDC_INPUT_SELECT,
+            )?;
+
+            build_device_widget(
+                window,
+                &OUTPUT_DEVICES,
+                "Output Device",
+                &output_device,
+                160,
+                IDC_OUTPUT_SELECT,
+            )?;
+
+            build_device_widget(
+                window,
+                &SIDECHAIN_DEVICES,
+                "Sidechain Device",
+                &CONFIG.sidechain_device(),
+                320,
+                IDC_SIDECHAIN_SELECT,
+            )?;
+        }
+        Message::Size(info) => {
+            let width = info.width() as i32 / 3;
+            let controls = [IDC_INPUT_SELECT, IDC_OUTPUT_SELECT, IDC_SIDECHAIN_SELECT];
+
+            for (column, control_id) in controls.into_iter().enumerate() {
+                window.get_dialog_item(control_id)?.set_rect(
+                    win::rect::Rect::new(width, info.height() as i32).at(width * column as i32, 0),
+                )?;
+            }
+        }
+        Message::Command(info) => unsafe {
+            if let Some(control_data) = info.control_data() {
+                let hwnd = control_data.window.hwnd_ptr();
+
+                let cur_sel = SendMessageA(hwnd, LB_GETCURSEL, 0, 0);
+
+                // the default value is -1 which will overflow and cause issues
+                if cur_sel < 0 {
+                    return Ok(None);
+                }
+
+                let index = cur_sel as usize;
+
+                if control_data.id == IDC_INPUT_SELECT {
+                    let devices = INPUT_DEVICES.read().unwrap();
+                    if let Some((_, device_id)) = devices.get(index) {
+                        CONFIG.set_input_device(device_id.clone())?;
+                    }
+                } else if control_data.id == IDC_OUTPUT_SELECT {
+                    let devices = OUTPUT_DEVICES.read().unwrap();
+                    if let Some((_, device_id)) = devices.get(index) {
+                        CONFIG.set_output_device(device_id.clone())?;
+                    }
+                } else if control_data.id == IDC_SIDECHAIN_SELECT {
+                    let devices = SIDECHAIN_DEVICES.read().unwrap();
+                    if let Some((_, device_id)) = devices.get(index) {
+                        CONFIG.set_sidechain_device(device_id.clone())?;
+                    }
+                }
+            }
+        },
+        Message::Close => window.destroy()?,
+        Message::Destroy => win::post_quit_message(0),
+        _ => return Ok(None),
+    }
+
+    Ok(Some(0))
+}
+
+/// window callback for the plugin picker
+fn plugin_picker_callback(window: &Window, message: Message) -> Result<Option<isize>> {
+    match message {
+        Message::Create => {
+            // highlight the current plugin of the slot
+            let selected = PICKER_SLOT
+                .lock()
+                .unwrap()
+                .and_then(|slot| CONFIG.plugin_path(slot))
+                .map(|path| path.to_string_lossy().into_owned());
+
+            build_device_widget(window, &PLUGINS, "Plugin", &selected, 0, IDC_PLUGIN_SELECT)?;
+        }
+        Message::Size(info) => {
+            let plugin_ctrl = window.get_dialog_item(IDC_PLUGIN_SELECT)?;
+
+            plugin_ctrl.set_rect(
+                win::rect::Rect::new(info.width() as i32, info.height() as i32).at(0, 0),
+            )?;
+        }
+        Message::Command(info) => unsafe {
+            if let Some(control_data) = info.control_data() {
+                let hwnd = control_data.window.hwnd_ptr();
+
+                let cur_sel = SendMessageA(hwnd, LB_GETCURSEL, 0, 0);
+
+                // the default value is -1 which will overflow and cause issues
+                if cur_sel < 0 {
+                    return Ok(None);
+                }
+
+                // applied when the picker closes, so picking twice does not add two plugins
+                *PICKED_PLUGIN.lock().unwrap() = Some(cur_sel as usize);
+            }
+        },
+        Message::Close => window.destroy()?,
+        Message::Destroy => win::post_quit_message(0),
+        _ => return Ok(None),
+    }
+
+    Ok(Some(0))
+}
+
+/// window callback for the preset name prompt
+fn name_prompt_callback(window: &Window, message: Message) -> Result<Option<isize>> {
+    match message {
+        Message::Create => unsafe {
+            let hwnd = window.hwnd_ptr();
+            let default = wide(&PROMPT_DEFAULT.lock().unwrap());
+
+            let edit = CreateWindowExW(
+                0,
+                wide("EDIT").as_ptr(),
+                default.as_ptr(),
+                WS_CHILD | WS_VISIBLE | WS_BORDER | WS_TABSTOP | ES_AUTOHSCROLL,
+                10,
+                10,
+                280,
+                24,
+                hwnd,
+                IDC_PRESET_NAME as HMENU,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+            CreateWindowExW(
+                0,
+                wide("BUTTON").as_ptr(),
+                wide("OK").as_ptr(),
+                WS_CHILD | WS_VISIBLE | WS_TABSTOP | BS_DEFPUSHBUTTON,
+                210,
+                44,
+                80,
+                26,
+                hwnd,
+                IDC_PRESET_OK as HMENU,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+            SetFocus(edit);
+        },
+        Message::Command(info) => unsafe {
+            if let Some(control_data) = info.control_data()
+                && control_data.id == IDC_PRESET_OK
+            {
+                let edit = window.get_dialog_item(IDC_PRESET_NAME)?.hwnd_ptr();
+                let mut text = vec![0; GetWindowTextLengthW(edit) as usize + 1];
+                let length = GetWindowTextW(edit, text.as_mut_ptr(), text.len() as i32);
+                let name = String::from_utf16_lossy(&text[..length.max(0) as usize]);
+
+                // the store rejects names it cannot use as a file name
+                *PROMPT_NAME.lock().unwrap() = Some(name.trim().to_string());
+                window.destroy()?;
+            }
+        },
+        Message::Close => window.destroy()?,
+        Message::Destroy => win::post_quit_message(0),
+        _ => return Ok(None),
+    }
+
+    Ok(Some(0))
+}
+
+/// window callback for the configurator
+fn editor_callback(window: &Window, message: Message) -> Option<isize> {
+    match message {
+        Message::Close => {
+            // hide the window instead of destroying it
+            _ = unsafe { ShowWindow(window.hwnd_ptr(), SW_HIDE) };
+            Some(0)
+        }
+        _ => None,
+    }
+}
+
+/// builds a list box widget for the device manager and the plugin picker
+fn build_device_widget(
+    window: &Window,
+    devices: &RwLock<Vec<(String, Option<String>)>>,
+    name: &str,
+    selected: &Option<String>,
+    x: i32,
+    control_id: u16,
+) -> Result<()> {
+    let ctrl = win::window::build()
+        .add_style(win::window::Style::Visible)
+        .add_style(win::window::Style::Center)
+        .add_style(win::window::Style::Caption)
+        .pos(x, 0)
+        .size(150, 100)
+        .parent(window)
+        .set_child_id(control_id)
+        .create(win::class::list_box(), name)?;
+
+    let mut selected_output = None;
+
+    for (index, (display_name, device_id)) in devices.read().unwrap().iter().enumerate() {
+        if device_id == selected {
+            selected_output = Some(index);
+        }
+
+        // this error is ignored because it is not critical
+        _ = ctrl.add_string_item(display_name);
+    }
+
+    if let Some(index) = selected_output {
+        let hwnd = ctrl.hwnd_ptr();
+        unsafe {
+            SendMessageA(hwnd, LB_SETCURSEL, index, 0);
+        }
+    }
+
+    Ok(())
+}